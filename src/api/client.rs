@@ -0,0 +1,124 @@
+// A connection that survives engine restarts and dropped sockets. Whenever the connection is
+// lost, it is re-established with exponential backoff and a `ClientEvent::Resync` is emitted,
+// since every broadcast sent in the meantime was missed and all state should be re-fetched.
+use super::error::{Error, Result};
+use super::{connect, RecvConnection, SendConnection};
+use crate::commands::{BroadcastAction, EngineCommand, ResponseAction};
+use crate::ConnectionRole;
+use tokio::time::{sleep, Duration};
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum ClientEvent {
+    Broadcast(BroadcastAction),
+    Resync,
+}
+
+pub struct Client {
+    address: Option<String>,
+    send: SendConnection,
+    recv: RecvConnection,
+    resync_pending: bool,
+    role: ConnectionRole,
+    secret: Option<String>,
+}
+
+impl Client {
+    pub async fn connect(address: Option<&str>) -> Result<Self> {
+        let (send, recv) = connect(address).await?;
+        Ok(Self {
+            address: address.map(|a| a.into()),
+            send,
+            recv: recv.activate().await,
+            resync_pending: false,
+            role: ConnectionRole::default(),
+            secret: None,
+        })
+    }
+
+    // The secret is resent whenever the connection is re-established
+    pub async fn authenticate(&mut self, secret: impl Into<String>) -> Result<()> {
+        let secret = secret.into();
+        self.send.authenticate(secret.clone()).await?;
+        self.secret = Some(secret);
+        Ok(())
+    }
+
+    // The role is restored whenever the connection is re-established
+    pub async fn set_role(&mut self, role: ConnectionRole) -> Result<()> {
+        self.send.set_connection_role(role).await?;
+        self.role = role;
+        Ok(())
+    }
+
+    // If the connection was lost, this reconnects and returns `Error::Disconnect`. The command is
+    // not resent, since the engine might have processed it before the connection dropped.
+    pub async fn send(&mut self, command: EngineCommand) -> Result<ResponseAction> {
+        match self.send.send(command).await {
+            Err(Error::Disconnect) => {
+                self.reconnect().await;
+                Err(Error::Disconnect)
+            }
+            other => other,
+        }
+    }
+
+    // Waits for the next broadcast, reconnecting if necessary. Never returns `None`.
+    pub async fn recv(&mut self) -> ClientEvent {
+        if self.resync_pending {
+            self.resync_pending = false;
+            return ClientEvent::Resync;
+        }
+        match self.recv.recv().await {
+            Some(action) => ClientEvent::Broadcast(action),
+            None => {
+                self.reconnect().await;
+                self.resync_pending = false;
+                ClientEvent::Resync
+            }
+        }
+    }
+
+    // A handle to send commands without reconnection, e.g. from another task
+    pub fn sender(&self) -> SendConnection {
+        self.send.clone()
+    }
+
+    pub async fn disconnect(self) {
+        self.recv.disconnect().await
+    }
+
+    async fn reconnect(&mut self) {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            match connect(self.address.as_deref()).await {
+                Ok((mut send, recv)) => {
+                    let authenticated = match &self.secret {
+                        Some(secret) => send.authenticate(secret.clone()).await.is_ok(),
+                        None => true,
+                    };
+                    if !authenticated
+                        || self.role != ConnectionRole::default()
+                            && send.set_connection_role(self.role).await.is_err()
+                    {
+                        sleep(backoff).await;
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                        continue;
+                    }
+                    let old_recv = std::mem::replace(&mut self.recv, recv.activate().await);
+                    old_recv.disconnect().await;
+                    self.send = send;
+                    self.resync_pending = true;
+                    return;
+                }
+                Err(_) => {
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
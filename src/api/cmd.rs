@@ -0,0 +1,490 @@
+// Shorthands for building `EngineCommand`s, so tools don't have to nest the actions by hand.
+// Session specific commands take the session id as their first argument.
+use crate::commands::{EngineAction, EngineCommand};
+use crate::*;
+use std::collections::HashMap;
+
+fn global(action: EngineAction) -> EngineCommand {
+    EngineCommand {
+        session: None,
+        action,
+    }
+}
+
+fn in_session(session: u64, action: EngineAction) -> EngineCommand {
+    EngineCommand {
+        session: Some(session),
+        action,
+    }
+}
+
+// global commands
+
+pub fn add_session(name: impl Into<String>, mode: Mode) -> EngineCommand {
+    global(EngineAction::AddSession {
+        name: name.into(),
+        mode,
+    })
+}
+
+pub fn add_player(
+    name: impl Into<String>,
+    discord_id: Option<u64>,
+    passphrase: impl Into<String>,
+    session: Option<u64>,
+) -> EngineCommand {
+    global(EngineAction::AddPlayer {
+        name: name.into(),
+        discord_id,
+        passphrase: passphrase.into(),
+        session,
+    })
+}
+
+pub fn set_player_session(player: u64, session: Option<u64>) -> EngineCommand {
+    global(EngineAction::SetPlayerSession { player, session })
+}
+
+pub fn move_player(player: u64, to_session: u64, to_team: Option<usize>) -> EngineCommand {
+    global(EngineAction::MovePlayer {
+        player,
+        to_session,
+        to_team,
+    })
+}
+
+pub fn set_player_name(player: u64, name: impl Into<String>) -> EngineCommand {
+    global(EngineAction::SetPlayerName {
+        player,
+        name: name.into(),
+    })
+}
+
+pub fn set_player_passphrase(player: u64, passphrase: impl Into<String>) -> EngineCommand {
+    global(EngineAction::SetPlayerPassphrase {
+        player,
+        passphrase: passphrase.into(),
+    })
+}
+
+pub fn set_player_language(player: u64, language: Option<String>) -> EngineCommand {
+    global(EngineAction::SetPlayerLanguage { player, language })
+}
+
+pub fn set_player_ready(player: u64, ready: bool) -> EngineCommand {
+    global(EngineAction::SetPlayerReady { player, ready })
+}
+
+pub fn remove_player(player: u64) -> EngineCommand {
+    global(EngineAction::RemovePlayer { player })
+}
+
+pub fn get_player_by_passphrase(passphrase: impl Into<String>) -> EngineCommand {
+    global(EngineAction::GetPlayerByPassphrase(passphrase.into()))
+}
+
+pub fn get_raw_challenges() -> EngineCommand {
+    global(EngineAction::GetRawChallenges)
+}
+
+pub fn set_raw_challenge(challenge: RawChallenge) -> EngineCommand {
+    global(EngineAction::SetRawChallenge(challenge))
+}
+
+pub fn add_raw_challenge(challenge: RawChallenge) -> EngineCommand {
+    global(EngineAction::AddRawChallenge(challenge))
+}
+
+pub fn ping(payload: Option<String>) -> EngineCommand {
+    global(EngineAction::Ping(payload))
+}
+
+// Goes to every connection, with a session only the players of that session are meant
+pub fn announce(session: Option<u64>, message: impl Into<String>) -> EngineCommand {
+    EngineCommand {
+        session,
+        action: EngineAction::Announce {
+            message: message.into(),
+        },
+    }
+}
+
+pub fn get_global_state() -> EngineCommand {
+    global(EngineAction::GetState)
+}
+
+pub fn get_sessions_detailed() -> EngineCommand {
+    global(EngineAction::GetSessionsDetailed)
+}
+
+pub fn get_zone(id: u64) -> EngineCommand {
+    global(EngineAction::GetZone(id))
+}
+
+pub fn get_zone_by_number(number: u64) -> EngineCommand {
+    global(EngineAction::GetZoneByNumber(number))
+}
+
+pub fn delete_zone(id: u64, cascade: bool) -> EngineCommand {
+    global(EngineAction::DeleteZone { zone: id, cascade })
+}
+
+pub fn set_zone_distances(from_zone: u64, distances: HashMap<u64, u64>) -> EngineCommand {
+    global(EngineAction::SetZoneDistances {
+        from_zone,
+        distances,
+    })
+}
+
+pub fn compute_zone_distances() -> EngineCommand {
+    global(EngineAction::ComputeZoneDistances)
+}
+
+pub fn set_zone_centre(zone: u64, centre: Option<(f64, f64)>) -> EngineCommand {
+    global(EngineAction::SetZoneCentre { zone, centre })
+}
+
+pub fn set_zone_boundary(zone: u64, boundary: Vec<(f64, f64)>) -> EngineCommand {
+    global(EngineAction::SetZoneBoundary { zone, boundary })
+}
+
+pub fn get_zone_at(location: (f64, f64)) -> EngineCommand {
+    global(EngineAction::GetZoneAt { location })
+}
+
+pub fn reload_config() -> EngineCommand {
+    global(EngineAction::ReloadConfig)
+}
+
+pub fn get_all_active_timers() -> EngineCommand {
+    global(EngineAction::GetActiveTimers)
+}
+
+pub fn cancel_timer(id: u64) -> EngineCommand {
+    global(EngineAction::CancelTimer { id })
+}
+
+pub fn check_integrity() -> EngineCommand {
+    global(EngineAction::CheckIntegrity)
+}
+
+pub fn repair_integrity() -> EngineCommand {
+    global(EngineAction::RepairIntegrity)
+}
+
+pub fn get_action_log(
+    session: Option<u64>,
+    since: Option<chrono::DateTime<chrono::Local>>,
+) -> EngineCommand {
+    global(EngineAction::GetActionLog { session, since })
+}
+
+#[cfg(feature = "debug-time")]
+pub fn advance_time(minutes: i64) -> EngineCommand {
+    global(EngineAction::AdvanceTime { minutes })
+}
+
+#[cfg(feature = "debug-locations")]
+pub fn ingest_locations(
+    session: u64,
+    team: usize,
+    locations: Vec<MinimalLocation>,
+) -> EngineCommand {
+    in_session(session, EngineAction::IngestLocations { team, locations })
+}
+
+pub fn get_recent_broadcasts(since: Option<u64>, limit: Option<usize>) -> EngineCommand {
+    global(EngineAction::GetRecentBroadcasts { since, limit })
+}
+
+pub fn suggest_zone_distances(min_samples: u64) -> EngineCommand {
+    global(EngineAction::SuggestZoneDistances { min_samples })
+}
+
+pub fn get_metrics() -> EngineCommand {
+    global(EngineAction::GetMetrics)
+}
+
+pub fn get_picture_metadata(picture: u64) -> EngineCommand {
+    global(EngineAction::GetPictureMetadata(picture))
+}
+
+pub fn export_snapshot() -> EngineCommand {
+    global(EngineAction::ExportSnapshot)
+}
+
+pub fn import_snapshot(snapshot: Snapshot) -> EngineCommand {
+    global(EngineAction::ImportSnapshot(snapshot))
+}
+
+pub fn authenticate(secret: impl Into<String>) -> EngineCommand {
+    global(EngineAction::Authenticate {
+        secret: secret.into(),
+    })
+}
+
+pub fn set_connection_role(role: ConnectionRole) -> EngineCommand {
+    global(EngineAction::SetConnectionRole(role))
+}
+
+// session commands
+
+pub fn get_state(session: u64) -> EngineCommand {
+    in_session(session, EngineAction::GetState)
+}
+
+// Only the teams in `teams_offset..teams_offset + teams_limit`, optionally without their
+// completed challenges and locations, which get big late in a game
+pub fn get_state_paged(
+    session: u64,
+    include_completed: bool,
+    include_locations: bool,
+    teams_offset: usize,
+    teams_limit: Option<usize>,
+) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::GetStatePaged {
+            include_completed,
+            include_locations,
+            teams_offset,
+            teams_limit,
+        },
+    )
+}
+
+// Version 0 gets every team
+pub fn get_state_diff(session: u64, since_version: u64) -> EngineCommand {
+    in_session(session, EngineAction::GetStateDiff { since_version })
+}
+
+pub fn get_events(session: u64) -> EngineCommand {
+    in_session(session, EngineAction::GetEvents)
+}
+
+pub fn get_readiness(session: u64) -> EngineCommand {
+    in_session(session, EngineAction::GetReadiness)
+}
+
+pub fn get_available_colours(session: u64) -> EngineCommand {
+    in_session(session, EngineAction::GetAvailableColours)
+}
+
+pub fn get_team_catch_stats(session: u64, team: usize) -> EngineCommand {
+    in_session(session, EngineAction::GetTeamCatchStats { team })
+}
+
+pub fn get_player_contributions(session: u64, team: usize) -> EngineCommand {
+    in_session(session, EngineAction::GetPlayerContributions { team })
+}
+
+pub fn is_game_running(session: u64) -> EngineCommand {
+    in_session(session, EngineAction::IsGameRunning)
+}
+
+pub fn get_active_timers(session: u64) -> EngineCommand {
+    in_session(session, EngineAction::GetActiveTimers)
+}
+
+pub fn get_period_path(session: u64, team: usize, period: usize) -> EngineCommand {
+    in_session(session, EngineAction::GetPeriodPath { team, period })
+}
+
+pub fn set_game_end_time(session: u64, new_time: chrono::NaiveTime) -> EngineCommand {
+    in_session(session, EngineAction::SetGameEndTime { new_time })
+}
+
+// Only before the game starts
+pub fn set_session_mode(session: u64, mode: Mode) -> EngineCommand {
+    in_session(session, EngineAction::SetSessionMode { mode })
+}
+
+pub fn snapshot_scores(session: u64) -> EngineCommand {
+    in_session(session, EngineAction::SnapshotScores)
+}
+
+pub fn get_score_snapshots(session: u64) -> EngineCommand {
+    in_session(session, EngineAction::GetScoreSnapshots)
+}
+
+pub fn start(session: u64) -> EngineCommand {
+    in_session(session, EngineAction::Start)
+}
+
+pub fn stop(session: u64) -> EngineCommand {
+    in_session(session, EngineAction::Stop)
+}
+
+pub fn add_team(
+    session: u64,
+    name: impl Into<String>,
+    discord_channel: Option<u64>,
+    colour: Option<Colour>,
+) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::AddTeam {
+            name: name.into(),
+            discord_channel,
+            colour,
+        },
+    )
+}
+
+pub fn rename_team(session: u64, team: usize, new_name: impl Into<String>) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::RenameTeam {
+            team,
+            new_name: new_name.into(),
+        },
+    )
+}
+
+pub fn assign_player_to_team(session: u64, player: u64, team: Option<usize>) -> EngineCommand {
+    in_session(session, EngineAction::AssignPlayerToTeam { player, team })
+}
+
+pub fn make_team_catcher(session: u64, team: usize) -> EngineCommand {
+    in_session(session, EngineAction::MakeTeamCatcher(team))
+}
+
+pub fn make_team_runner(session: u64, team: usize) -> EngineCommand {
+    in_session(session, EngineAction::MakeTeamRunner(team))
+}
+
+pub fn catch(session: u64, catcher: usize, caught: usize) -> EngineCommand {
+    in_session(session, EngineAction::Catch { catcher, caught })
+}
+
+pub fn complete(session: u64, completer: usize, completed: usize) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::Complete {
+            completer,
+            completed,
+            pictures: Vec::new(),
+            player: None,
+        },
+    )
+}
+
+pub fn complete_with_pictures(
+    session: u64,
+    completer: usize,
+    completed: usize,
+    pictures: Vec<Picture>,
+) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::Complete {
+            completer,
+            completed,
+            pictures,
+            player: None,
+        },
+    )
+}
+
+// Credits the completion to `player`, who has to be in the completing team
+pub fn complete_as_player(
+    session: u64,
+    completer: usize,
+    completed: usize,
+    player: u64,
+    pictures: Vec<Picture>,
+) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::Complete {
+            completer,
+            completed,
+            pictures,
+            player: Some(player),
+        },
+    )
+}
+
+pub fn send_location(session: u64, player: u64, location: (f64, f64)) -> EngineCommand {
+    in_session(session, EngineAction::SendLocation { player, location })
+}
+
+pub fn upload_period_pictures(
+    session: u64,
+    team: usize,
+    period: usize,
+    pictures: Vec<Picture>,
+    token: u64,
+) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::UploadPeriodPictures {
+            team,
+            period,
+            pictures,
+            token,
+        },
+    )
+}
+
+pub fn add_challenge_to_team(session: u64, team: usize, challenge: Challenge) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::AddChallengeToTeam { team, challenge },
+    )
+}
+
+pub fn simulate_game(session: u64, games: u64, challenge_set: Option<u64>) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::SimulateGame {
+            games,
+            challenge_set,
+        },
+    )
+}
+
+// New challenges for a runner team, as its refresh timer would give it
+pub fn refresh_challenges(session: u64, team: usize) -> EngineCommand {
+    in_session(session, EngineAction::RefreshChallenges { team })
+}
+
+// Replaces all of the team's open challenges except the ones at `keep_indices`
+pub fn regenerate_challenges_except(
+    session: u64,
+    team: usize,
+    keep_indices: Vec<usize>,
+) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::RegenerateChallengesExcept { team, keep_indices },
+    )
+}
+
+// What generation would choose the team's next challenge from, to see why a challenge came up
+pub fn get_candidate_challenges(
+    session: u64,
+    team: usize,
+    period: Option<GenerationPeriod>,
+) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::GetCandidateChallenges { team, period },
+    )
+}
+
+pub fn analyze_challenge_points(
+    session: u64,
+    challenge_id: u64,
+    from_zone: Option<u64>,
+    samples: u64,
+) -> EngineCommand {
+    in_session(
+        session,
+        EngineAction::AnalyzeChallengePoints {
+            challenge_id,
+            from_zone,
+            samples,
+        },
+    )
+}
@@ -1,23 +1,59 @@
 use crate::commands;
 
+// Everything that can go wrong for a client, whether on the way to the engine or in it. The
+// conversions below let `?` turn any of the underlying errors into this one.
 #[derive(Debug)]
 pub enum Error {
     Disconnect,
-    InvalidSignal,
+    Undecodable, // a message from the engine that isn't a ClientCommand
+    InvalidSignal {
+        expected: &'static str, // the ResponseAction variant the request should get
+        got: Box<commands::ResponseAction>,
+    },
     Connection(std::io::Error),
     Truinlag(commands::Error),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+impl Error {
+    // The error the engine responded with, if that's what went wrong, e.g. to tell a
+    // `NotFound` from a `GameInProgress` with `err.as_truinlag() == Some(&NotFound)`
+    pub fn as_truinlag(&self) -> Option<&commands::Error> {
+        match self {
+            Error::Truinlag(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    pub fn into_truinlag(self) -> Option<commands::Error> {
+        match self {
+            Error::Truinlag(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn invalid_signal(expected: &'static str, got: commands::ResponseAction) -> Self {
+        Error::InvalidSignal {
+            expected,
+            got: Box::new(got),
+        }
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Error::Disconnect => write!(f, "disconnected from the Truinlag engine"),
-            Error::InvalidSignal => write!(
+            Error::Undecodable => write!(
                 f,
                 "received invalid signal from engine (not able to deserialise to ClientCommand)"
             ),
+            Error::InvalidSignal { expected, got } => write!(
+                f,
+                "received unexpected response from engine, expected {} but got {:?}",
+                expected, got
+            ),
             Error::Connection(err) => write!(f, "couldn't connect: {}", err),
             Error::Truinlag(err) => write!(f, "cruinlag returned an error: {}", err),
         }
@@ -30,10 +66,44 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<bincode::Error> for Error {
+    fn from(_error: bincode::Error) -> Self {
+        Error::Undecodable
+    }
+}
+
+// The channels to the connection's tasks only close when the connection is gone
+impl<T> From<tokio::sync::mpsc::error::SendError<T>> for Error {
+    fn from(_error: tokio::sync::mpsc::error::SendError<T>) -> Self {
+        Error::Disconnect
+    }
+}
+
+impl From<tokio::sync::oneshot::error::RecvError> for Error {
+    fn from(_error: tokio::sync::oneshot::error::RecvError) -> Self {
+        Error::Disconnect
+    }
+}
+
 impl From<commands::Error> for Error {
     fn from(error: commands::Error) -> Self {
         Error::Truinlag(error)
     }
 }
 
-impl std::error::Error for Error {}
+// Input the client refuses to send is reported the same way the engine would report it
+impl From<crate::TextError> for Error {
+    fn from(error: crate::TextError) -> Self {
+        Error::Truinlag(commands::Error::BadData(error.to_string()))
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connection(err) => Some(err),
+            Error::Truinlag(err) => Some(err),
+            _ => None,
+        }
+    }
+}
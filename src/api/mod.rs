@@ -4,7 +4,7 @@ use crate::commands::{
 };
 use crate::*;
 use bytes::Bytes;
-use error::{Error, Result};
+pub use error::{Error, Result};
 use futures::prelude::*;
 use futures::SinkExt;
 use std::sync::Arc;
@@ -12,6 +12,8 @@ use tokio::net::UnixStream;
 use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 
+pub mod client;
+pub mod cmd;
 pub mod error;
 
 struct SendRequest {
@@ -25,16 +27,19 @@ struct ResponseInfo {
     channel: oneshot::Sender<ResponseAction>,
 }
 
+// A broadcast with its sequence number, if it has one
+type NumberedBroadcast = (Option<u64>, BroadcastAction);
+
 #[derive(Debug)]
 enum DistributorMessage {
-    Command(ClientCommand),
+    Command(Box<ClientCommand>),
     ResponseInfo(ResponseInfo),
     Err(Error),
 }
 
 async fn connectinator<R, W>(
     mut send_req_recv: mpsc::Receiver<SendRequest>,
-    broadcast_send: mpsc::Sender<BroadcastAction>,
+    broadcast_send: mpsc::Sender<NumberedBroadcast>,
     socket_read: R,
     socket_write: W,
 ) -> Result<()>
@@ -80,18 +85,15 @@ where
         while let Some(message) = transport.next().await {
             match message {
                 Ok(message) => {
-                    let command: ClientCommand =
-                        bincode::deserialize(&message).map_err(|_err| Error::InvalidSignal)?;
+                    let command: ClientCommand = bincode::deserialize(&message)?;
                     command_send
-                        .send(DistributorMessage::Command(command))
-                        .await
-                        .map_err(|_| Error::Disconnect)?;
+                        .send(DistributorMessage::Command(Box::new(command)))
+                        .await?;
                 }
                 Err(_err) => {
                     command_send
                         .send(DistributorMessage::Err(Error::Disconnect))
-                        .await
-                        .map_err(|_| Error::Disconnect)?;
+                        .await?;
                     return Err(Error::Disconnect);
                 }
             }
@@ -114,12 +116,20 @@ where
                         info_cache.push(info);
                     };
                 }
-                DistributorMessage::Command(command) => match command {
+                DistributorMessage::Command(command) => match *command {
                     ClientCommand::Broadcast(msg) => {
-                        broadcast_send.send(msg).await.expect(
+                        broadcast_send.send((None, msg)).await.expect(
                             "Receiver handle can only be dropped if JoinHandle is dropped too",
                         );
                     }
+                    ClientCommand::SequencedBroadcast(msg) => {
+                        broadcast_send
+                            .send((Some(msg.sequence), msg.action))
+                            .await
+                            .expect(
+                                "Receiver handle can only be dropped if JoinHandle is dropped too",
+                            );
+                    }
                     ClientCommand::Response(msg) => {
                         if let Some(info_index) = info_cache.iter().position(|inf| inf.id == msg.id)
                         {
@@ -150,14 +160,49 @@ where
     Ok(())
 }
 
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/truinsocket";
+
+// The socket the engine listens on unless told otherwise, overridable with TRUINLAG_SOCKET.
+// Both the engine and `connect` use this, so they agree without configuration.
+pub fn default_socket_path() -> String {
+    std::env::var("TRUINLAG_SOCKET").unwrap_or_else(|_| DEFAULT_SOCKET_PATH.into())
+}
+
 pub async fn connect(address: Option<&str>) -> Result<(SendConnection, InactiveRecvConnection)> {
-    let (socket_read, socket_write) = UnixStream::connect(address.unwrap_or("/tmp/truinsocket"))
+    let address = address
+        .map(String::from)
+        .unwrap_or_else(default_socket_path);
+    let (socket_read, socket_write) = UnixStream::connect(address)
         .await
-        .map_err(|err| Error::Connection(err))?
+        .map_err(Error::Connection)?
         .into_split();
     insert_connection(socket_read, socket_write).await
 }
 
+#[derive(Debug, Clone)]
+pub struct HealthInfo {
+    pub round_trip: std::time::Duration, // of a single request
+    pub metrics: Option<Vec<CommandMetrics>>,
+}
+
+// Connects, queries the engine and disconnects again, e.g. for a liveness probe. The metrics are
+// only returned if asked for. Unlike a ping, the query isn't broadcast to every client.
+pub async fn healthcheck(address: Option<&str>, with_metrics: bool) -> Result<HealthInfo> {
+    let (mut send, recv) = connect(address).await?;
+    // the connection is closed whether or not the check succeeds
+    let result = async {
+        let sent = std::time::Instant::now();
+        let metrics = send.get_metrics().await?;
+        Ok(HealthInfo {
+            round_trip: sent.elapsed(),
+            metrics: with_metrics.then_some(metrics),
+        })
+    }
+    .await;
+    recv.disconnect().await;
+    result
+}
+
 pub async fn insert_connection<R, W>(
     read: R,
     write: W,
@@ -178,6 +223,7 @@ where
         RecvConnection {
             broadcast_recv,
             handle,
+            last_sequence: None,
         }
         .deactivate()
         .await,
@@ -196,11 +242,8 @@ impl SendConnection {
             command,
             response_channel: resp_send,
         };
-        self.send_req_send
-            .send(package)
-            .await
-            .map_err(|_| Error::Disconnect)?;
-        Ok(resp_recv.await.map_err(|_| Error::Disconnect)?)
+        self.send_req_send.send(package).await?;
+        Ok(resp_recv.await?)
     }
 
     pub async fn get_global_state(&mut self) -> Result<(Vec<GameSession>, Vec<Player>)> {
@@ -213,12 +256,20 @@ impl SendConnection {
         {
             Ok(thing) => match thing {
                 ResponseAction::SendGlobalState { sessions, players } => Ok((sessions, players)),
-                _ => Err(Error::InvalidSignal),
+                other => Err(Error::invalid_signal("SendGlobalState", other)),
             },
             Err(err) => Err(err),
         }
     }
 
+    pub async fn get_sessions_detailed(&mut self) -> Result<Vec<SessionDetails>> {
+        match self.send(cmd::get_sessions_detailed()).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendSessionsDetailed(sessions) => Ok(sessions),
+            other => Err(Error::invalid_signal("SendSessionsDetailed", other)),
+        }
+    }
+
     pub async fn get_raw_challenges(&mut self) -> Result<Vec<RawChallenge>> {
         match self
             .send(EngineCommand {
@@ -229,13 +280,47 @@ impl SendConnection {
         {
             ResponseAction::Error(err) => Err(Error::Truinlag(err)),
             ResponseAction::SendRawChallenges(challenges) => Ok(challenges),
-            _ => Err(Error::InvalidSignal),
+            other => Err(Error::invalid_signal("SendRawChallenges", other)),
+        }
+    }
+
+    pub async fn get_zone(&mut self, id: u64) -> Result<Zone> {
+        match self
+            .send(EngineCommand {
+                session: None,
+                action: EngineAction::GetZone(id),
+            })
+            .await?
+        {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendZones(mut zones) if zones.len() == 1 => {
+                Ok(zones.pop().expect("there is exactly one zone"))
+            }
+            other => Err(Error::invalid_signal("SendZones", other)),
+        }
+    }
+
+    pub async fn get_zone_by_number(&mut self, number: u64) -> Result<Zone> {
+        match self
+            .send(EngineCommand {
+                session: None,
+                action: EngineAction::GetZoneByNumber(number),
+            })
+            .await?
+        {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendZones(mut zones) if zones.len() == 1 => {
+                Ok(zones.pop().expect("there is exactly one zone"))
+            }
+            other => Err(Error::invalid_signal("SendZones", other)),
         }
     }
 
     pub async fn set_raw_challenge(&mut self, challenge: RawChallenge) -> Result<()> {
-        if let None = challenge.id {
-            return Err(Error::InvalidSignal);
+        if challenge.id.is_none() {
+            return Err(Error::Truinlag(commands::Error::BadData(
+                "the challenge has no id, so there's nothing to overwrite".into(),
+            )));
         }
         match self
             .send(EngineCommand {
@@ -246,13 +331,15 @@ impl SendConnection {
         {
             ResponseAction::Error(err) => Err(Error::Truinlag(err)),
             ResponseAction::Success => Ok(()),
-            _ => Err(Error::InvalidSignal),
+            other => Err(Error::invalid_signal("Success", other)),
         }
     }
 
     pub async fn add_raw_challenge(&mut self, challenge: RawChallenge) -> Result<()> {
-        if let Some(_) = challenge.id {
-            return Err(Error::InvalidSignal);
+        if challenge.id.is_some() {
+            return Err(Error::Truinlag(commands::Error::BadData(
+                "the challenge already has an id, use set_raw_challenge to change it".into(),
+            )));
         }
         match self
             .send(EngineCommand {
@@ -263,19 +350,709 @@ impl SendConnection {
         {
             ResponseAction::Error(err) => Err(Error::Truinlag(err)),
             ResponseAction::Success => Ok(()),
-            _ => Err(Error::InvalidSignal),
+            other => Err(Error::invalid_signal("Success", other)),
+        }
+    }
+
+    async fn send_expecting_success(&mut self, command: EngineCommand) -> Result<()> {
+        ResponseAction::unwrap_success(self.send(command).await?)
+    }
+
+    pub async fn add_session(&mut self, name: impl Into<String>, mode: Mode) -> Result<()> {
+        self.send_expecting_success(cmd::add_session(name, mode))
+            .await
+    }
+
+    pub async fn add_player(
+        &mut self,
+        name: impl Into<String>,
+        discord_id: Option<u64>,
+        passphrase: impl Into<String>,
+        session: Option<u64>,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::add_player(name, discord_id, passphrase, session))
+            .await
+    }
+
+    pub async fn set_player_session(&mut self, player: u64, session: Option<u64>) -> Result<()> {
+        self.send_expecting_success(cmd::set_player_session(player, session))
+            .await
+    }
+
+    // Puts the player into another session and team at once, unlike `set_player_session`
+    // followed by `assign_player_to_team`
+    pub async fn move_player(
+        &mut self,
+        player: u64,
+        to_session: u64,
+        to_team: Option<usize>,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::move_player(player, to_session, to_team))
+            .await
+    }
+
+    pub async fn set_player_name(&mut self, player: u64, name: impl Into<String>) -> Result<()> {
+        self.send_expecting_success(cmd::set_player_name(player, name))
+            .await
+    }
+
+    pub async fn set_player_passphrase(
+        &mut self,
+        player: u64,
+        passphrase: impl Into<String>,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::set_player_passphrase(player, passphrase))
+            .await
+    }
+
+    pub async fn set_player_language(
+        &mut self,
+        player: u64,
+        language: Option<String>,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::set_player_language(player, language))
+            .await
+    }
+
+    // Starting a game with unready players only gets an `UnreadyAtStart` broadcast
+    pub async fn set_player_ready(&mut self, player: u64, ready: bool) -> Result<()> {
+        self.send_expecting_success(cmd::set_player_ready(player, ready))
+            .await
+    }
+
+    pub async fn remove_player(&mut self, player: u64) -> Result<()> {
+        self.send_expecting_success(cmd::remove_player(player))
+            .await
+    }
+
+    pub async fn get_player_by_passphrase(
+        &mut self,
+        passphrase: impl Into<String>,
+    ) -> Result<Player> {
+        ResponseAction::unwrap_player(self.send(cmd::get_player_by_passphrase(passphrase)).await?)
+    }
+
+    pub async fn ping(&mut self, payload: Option<String>) -> Result<()> {
+        self.send_expecting_success(cmd::ping(payload)).await
+    }
+
+    // Recent announcements can be fetched again with `get_recent_broadcasts`
+    pub async fn announce(
+        &mut self,
+        session: Option<u64>,
+        message: impl Into<String>,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::announce(session, message))
+            .await
+    }
+
+    pub async fn get_state(&mut self, session: u64) -> Result<(Vec<Team>, Option<Game>)> {
+        ResponseAction::unwrap_send_state(self.send(cmd::get_state(session)).await?)
+    }
+
+    // Returns the requested teams, the game and the total number of teams in the session
+    pub async fn get_state_paged(
+        &mut self,
+        session: u64,
+        include_completed: bool,
+        include_locations: bool,
+        teams_offset: usize,
+        teams_limit: Option<usize>,
+    ) -> Result<(Vec<Team>, Option<Game>, usize)> {
+        match self
+            .send(cmd::get_state_paged(
+                session,
+                include_completed,
+                include_locations,
+                teams_offset,
+                teams_limit,
+            ))
+            .await?
+        {
+            ResponseAction::SendStatePage {
+                teams,
+                game,
+                total_teams,
+            } => Ok((teams, game, total_teams)),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("SendStatePage", other)),
+        }
+    }
+
+    // Returns the current state version and the teams that changed since `since_version`. Pass
+    // the returned version next time, or 0 to get every team.
+    pub async fn get_state_diff(
+        &mut self,
+        session: u64,
+        since_version: u64,
+    ) -> Result<(u64, Vec<Team>, Option<Game>)> {
+        match self
+            .send(cmd::get_state_diff(session, since_version))
+            .await?
+        {
+            ResponseAction::SendStateDiff {
+                version,
+                teams,
+                game,
+            } => Ok((version, teams, game)),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("SendStateDiff", other)),
+        }
+    }
+
+    pub async fn get_readiness(&mut self, session: u64) -> Result<Vec<TeamReadiness>> {
+        match self.send(cmd::get_readiness(session)).await? {
+            ResponseAction::SendReadiness(readiness) => Ok(readiness),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("SendReadiness", other)),
+        }
+    }
+
+    // Which of the configured team colours are still free, e.g. for a colour picker
+    pub async fn get_available_colours(&mut self, session: u64) -> Result<ColourPalette> {
+        match self.send(cmd::get_available_colours(session)).await? {
+            ResponseAction::SendColours(palette) => Ok(palette),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("SendColours", other)),
+        }
+    }
+
+    // Catches made and suffered by a team, with the bounty that changed hands
+    pub async fn get_team_catch_stats(
+        &mut self,
+        session: u64,
+        team: usize,
+    ) -> Result<TeamCatchStats> {
+        match self.send(cmd::get_team_catch_stats(session, team)).await? {
+            ResponseAction::SendTeamCatchStats(stats) => Ok(stats),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("SendTeamCatchStats", other)),
+        }
+    }
+
+    // Completions per player of a team, only counting completions that were credited to a player
+    // separately from the rest
+    pub async fn get_player_contributions(
+        &mut self,
+        session: u64,
+        team: usize,
+    ) -> Result<Vec<PlayerContribution>> {
+        match self
+            .send(cmd::get_player_contributions(session, team))
+            .await?
+        {
+            ResponseAction::SendPlayerContributions(contributions) => Ok(contributions),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("SendPlayerContributions", other)),
+        }
+    }
+
+    // All events of the current game so far, oldest first. New ones are broadcast as they happen.
+    pub async fn get_events(&mut self, session: u64) -> Result<Vec<Event>> {
+        match self.send(cmd::get_events(session)).await? {
+            ResponseAction::SendEvents(events) => Ok(events),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("SendEvents", other)),
+        }
+    }
+
+    // The events of the current game as csv, see `Event::to_csv` for the columns
+    pub async fn export_events(&mut self, session: u64) -> Result<String> {
+        Ok(Event::to_csv(&self.get_events(session).await?))
+    }
+
+    // Safe to retry with the same token after a dropped connection, the pictures are only added
+    // once. Returns the ids of the stored pictures.
+    pub async fn upload_period_pictures(
+        &mut self,
+        session: u64,
+        team: usize,
+        period: usize,
+        pictures: Vec<Picture>,
+        token: u64,
+    ) -> Result<Vec<u64>> {
+        match self
+            .send(cmd::upload_period_pictures(
+                session, team, period, pictures, token,
+            ))
+            .await?
+        {
+            ResponseAction::SendPictureIds(ids) => Ok(ids),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("SendPictureIds", other)),
+        }
+    }
+
+    pub async fn is_game_running(&mut self, session: u64) -> Result<bool> {
+        match self.send(cmd::is_game_running(session)).await? {
+            ResponseAction::GameRunning(running) => Ok(running),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("GameRunning", other)),
+        }
+    }
+
+    // Periods are counted in the order they happened, regardless of their kind
+    pub async fn get_period_path(
+        &mut self,
+        session: u64,
+        team: usize,
+        period: usize,
+    ) -> Result<Vec<MinimalLocation>> {
+        match self
+            .send(cmd::get_period_path(session, team, period))
+            .await?
+        {
+            ResponseAction::SendLocations(locations) => Ok(locations),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("SendLocations", other)),
+        }
+    }
+
+    pub async fn set_game_end_time(
+        &mut self,
+        session: u64,
+        new_time: chrono::NaiveTime,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::set_game_end_time(session, new_time))
+            .await
+    }
+
+    pub async fn set_session_mode(&mut self, session: u64, mode: Mode) -> Result<()> {
+        self.send_expecting_success(cmd::set_session_mode(session, mode))
+            .await
+    }
+
+    pub async fn snapshot_scores(&mut self, session: u64) -> Result<()> {
+        self.send_expecting_success(cmd::snapshot_scores(session))
+            .await
+    }
+
+    pub async fn get_score_snapshots(&mut self, session: u64) -> Result<Vec<ScoreSnapshot>> {
+        match self.send(cmd::get_score_snapshots(session)).await? {
+            ResponseAction::SendScoreSnapshots(snapshots) => Ok(snapshots),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("SendScoreSnapshots", other)),
+        }
+    }
+
+    pub async fn start(&mut self, session: u64) -> Result<()> {
+        self.send_expecting_success(cmd::start(session)).await
+    }
+
+    pub async fn stop(&mut self, session: u64) -> Result<()> {
+        self.send_expecting_success(cmd::stop(session)).await
+    }
+
+    pub async fn add_team(
+        &mut self,
+        session: u64,
+        name: impl Into<String>,
+        discord_channel: Option<u64>,
+        colour: Option<Colour>,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::add_team(session, name, discord_channel, colour))
+            .await
+    }
+
+    // Like `add_team`, with the colour as `#RRGGBB`. Malformed colours never reach the engine.
+    pub async fn add_team_with_hex_colour(
+        &mut self,
+        session: u64,
+        name: impl Into<String>,
+        discord_channel: Option<u64>,
+        colour: &str,
+    ) -> Result<()> {
+        let colour = Colour::from_hex(colour)?;
+        self.add_team(session, name, discord_channel, Some(colour))
+            .await
+    }
+
+    pub async fn rename_team(
+        &mut self,
+        session: u64,
+        team: usize,
+        new_name: impl Into<String>,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::rename_team(session, team, new_name))
+            .await
+    }
+
+    pub async fn assign_player_to_team(
+        &mut self,
+        session: u64,
+        player: u64,
+        team: Option<usize>,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::assign_player_to_team(session, player, team))
+            .await
+    }
+
+    pub async fn make_team_catcher(&mut self, session: u64, team: usize) -> Result<()> {
+        self.send_expecting_success(cmd::make_team_catcher(session, team))
+            .await
+    }
+
+    pub async fn make_team_runner(&mut self, session: u64, team: usize) -> Result<()> {
+        self.send_expecting_success(cmd::make_team_runner(session, team))
+            .await
+    }
+
+    pub async fn catch(
+        &mut self,
+        session: u64,
+        catcher: usize,
+        caught: usize,
+    ) -> Result<PeriodSummary> {
+        ResponseAction::unwrap_period_summary(
+            self.send(cmd::catch(session, catcher, caught)).await?,
+        )
+    }
+
+    pub async fn complete(
+        &mut self,
+        session: u64,
+        completer: usize,
+        completed: usize,
+    ) -> Result<PeriodSummary> {
+        ResponseAction::unwrap_period_summary(
+            self.send(cmd::complete(session, completer, completed))
+                .await?,
+        )
+    }
+
+    // Challenges that require a photo can only be completed this way
+    pub async fn complete_with_pictures(
+        &mut self,
+        session: u64,
+        completer: usize,
+        completed: usize,
+        pictures: Vec<Picture>,
+    ) -> Result<PeriodSummary> {
+        ResponseAction::unwrap_period_summary(
+            self.send(cmd::complete_with_pictures(
+                session, completer, completed, pictures,
+            ))
+            .await?,
+        )
+    }
+
+    pub async fn complete_as_player(
+        &mut self,
+        session: u64,
+        completer: usize,
+        completed: usize,
+        player: u64,
+        pictures: Vec<Picture>,
+    ) -> Result<PeriodSummary> {
+        ResponseAction::unwrap_period_summary(
+            self.send(cmd::complete_as_player(
+                session, completer, completed, player, pictures,
+            ))
+            .await?,
+        )
+    }
+
+    pub async fn send_location(
+        &mut self,
+        session: u64,
+        player: u64,
+        location: (f64, f64),
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::send_location(session, player, location))
+            .await
+    }
+
+    pub async fn add_challenge_to_team(
+        &mut self,
+        session: u64,
+        team: usize,
+        challenge: Challenge,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::add_challenge_to_team(session, team, challenge))
+            .await
+    }
+
+    pub async fn delete_zone(&mut self, id: u64, cascade: bool) -> Result<()> {
+        self.send_expecting_success(cmd::delete_zone(id, cascade))
+            .await
+    }
+
+    pub async fn set_zone_distances(
+        &mut self,
+        from_zone: u64,
+        distances: std::collections::HashMap<u64, u64>,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::set_zone_distances(from_zone, distances))
+            .await
+    }
+
+    // Where the zone is, for location checks. `None` removes it.
+    pub async fn set_zone_centre(&mut self, zone: u64, centre: Option<(f64, f64)>) -> Result<()> {
+        self.send_expecting_success(cmd::set_zone_centre(zone, centre))
+            .await
+    }
+
+    // The outline of the zone as a polygon of at least three points. An empty one removes it.
+    pub async fn set_zone_boundary(&mut self, zone: u64, boundary: Vec<(f64, f64)>) -> Result<()> {
+        self.send_expecting_success(cmd::set_zone_boundary(zone, boundary))
+            .await
+    }
+
+    // The zone containing the location or else the one with the closest centre, `None` if no
+    // zone has any geography
+    pub async fn get_zone_at(&mut self, location: (f64, f64)) -> Result<Option<Zone>> {
+        match self.send(cmd::get_zone_at(location)).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendZones(mut zones) if zones.len() <= 1 => Ok(zones.pop()),
+            other => Err(Error::invalid_signal("SendZones", other)),
+        }
+    }
+
+    pub async fn compute_zone_distances(&mut self) -> Result<u64> {
+        match self.send(cmd::compute_zone_distances()).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::FilledZoneDistances(filled) => Ok(filled),
+            other => Err(Error::invalid_signal("FilledZoneDistances", other)),
+        }
+    }
+
+    // Without a session, the timers of all sessions are returned
+    pub async fn get_active_timers(&mut self, session: Option<u64>) -> Result<Vec<Timer>> {
+        let command = match session {
+            Some(session) => cmd::get_active_timers(session),
+            None => cmd::get_all_active_timers(),
+        };
+        match self.send(command).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendTimers(timers) => Ok(timers),
+            other => Err(Error::invalid_signal("SendTimers", other)),
+        }
+    }
+
+    pub async fn cancel_timer(&mut self, id: u64) -> Result<()> {
+        self.send_expecting_success(cmd::cancel_timer(id)).await
+    }
+
+    // Unlocks privileged actions like `DeleteZone` for this connection
+    pub async fn authenticate(&mut self, secret: impl Into<String>) -> Result<()> {
+        self.send_expecting_success(cmd::authenticate(secret)).await
+    }
+
+    // Spectator connections can't be turned back into referee connections
+    pub async fn set_connection_role(&mut self, role: ConnectionRole) -> Result<()> {
+        self.send_expecting_success(cmd::set_connection_role(role))
+            .await
+    }
+
+    pub async fn reload_config(&mut self) -> Result<()> {
+        self.send_expecting_success(cmd::reload_config()).await
+    }
+
+    pub async fn check_integrity(&mut self) -> Result<Vec<IntegrityProblem>> {
+        match self.send(cmd::check_integrity()).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendIntegrityReport(problems) => Ok(problems),
+            other => Err(Error::invalid_signal("SendIntegrityReport", other)),
+        }
+    }
+
+    // Removes all dangling references and returns the problems that were fixed
+    pub async fn repair_integrity(&mut self) -> Result<Vec<IntegrityProblem>> {
+        match self.send(cmd::repair_integrity()).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendIntegrityReport(problems) => Ok(problems),
+            other => Err(Error::invalid_signal("SendIntegrityReport", other)),
+        }
+    }
+
+    // The successfully applied mutating commands, optionally only those of one session and
+    // those after a point in time
+    pub async fn get_action_log(
+        &mut self,
+        session: Option<u64>,
+        since: Option<chrono::DateTime<chrono::Local>>,
+    ) -> Result<Vec<LoggedAction>> {
+        match self.send(cmd::get_action_log(session, since)).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendActionLog(log) => Ok(log),
+            other => Err(Error::invalid_signal("SendActionLog", other)),
+        }
+    }
+
+    // The engine has to be built with the `debug-time` feature as well
+    #[cfg(feature = "debug-time")]
+    pub async fn advance_time(&mut self, minutes: i64) -> Result<()> {
+        self.send_expecting_success(cmd::advance_time(minutes))
+            .await
+    }
+
+    // Feeds a recorded trace through the engine's location check, returning the rejected
+    // locations. The engine has to be built with the `debug-locations` feature as well.
+    #[cfg(feature = "debug-locations")]
+    pub async fn ingest_locations(
+        &mut self,
+        session: u64,
+        team: usize,
+        locations: Vec<MinimalLocation>,
+    ) -> Result<Vec<MinimalLocation>> {
+        match self
+            .send(cmd::ingest_locations(session, team, locations))
+            .await?
+        {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendLocations(rejected) => Ok(rejected),
+            other => Err(Error::invalid_signal("SendLocations", other)),
+        }
+    }
+
+    // The broadcasts a freshly activated `RecvConnection` missed, oldest first. Pass its
+    // `last_sequence` to only get newer ones.
+    pub async fn get_recent_broadcasts(
+        &mut self,
+        since: Option<u64>,
+        limit: Option<usize>,
+    ) -> Result<Vec<SequencedBroadcast>> {
+        match self.send(cmd::get_recent_broadcasts(since, limit)).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendBroadcasts(broadcasts) => Ok(broadcasts),
+            other => Err(Error::invalid_signal("SendBroadcasts", other)),
+        }
+    }
+
+    // Travel times between zones as past games saw them, for checking `set_zone_distances`
+    // against. Nothing is changed.
+    pub async fn suggest_zone_distances(
+        &mut self,
+        min_samples: u64,
+    ) -> Result<Vec<TravelTimeSuggestion>> {
+        match self.send(cmd::suggest_zone_distances(min_samples)).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendTravelTimeSuggestions(suggestions) => Ok(suggestions),
+            other => Err(Error::invalid_signal("SendTravelTimeSuggestions", other)),
+        }
+    }
+
+    pub async fn get_metrics(&mut self) -> Result<Vec<CommandMetrics>> {
+        match self.send(cmd::get_metrics()).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendMetrics(metrics) => Ok(metrics),
+            other => Err(Error::invalid_signal("SendMetrics", other)),
+        }
+    }
+
+    // Only challenge pictures have metadata, fields the picture didn't have are `None`
+    pub async fn get_picture_metadata(&mut self, picture: u64) -> Result<PictureMetadata> {
+        match self.send(cmd::get_picture_metadata(picture)).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendPictureMetadata(metadata) => Ok(metadata),
+            other => Err(Error::invalid_signal("SendPictureMetadata", other)),
+        }
+    }
+
+    pub async fn export_snapshot(&mut self) -> Result<Snapshot> {
+        match self.send(cmd::export_snapshot()).await? {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendSnapshot(snapshot) => Ok(snapshot),
+            other => Err(Error::invalid_signal("SendSnapshot", other)),
+        }
+    }
+
+    pub async fn import_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+        self.send_expecting_success(cmd::import_snapshot(snapshot))
+            .await
+    }
+
+    pub async fn simulate_game(
+        &mut self,
+        session: u64,
+        games: u64,
+        challenge_set: Option<u64>,
+    ) -> Result<Vec<SimulatedPeriod>> {
+        match self
+            .send(cmd::simulate_game(session, games, challenge_set))
+            .await?
+        {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendSimulation(periods) => Ok(periods),
+            other => Err(Error::invalid_signal("SendSimulation", other)),
+        }
+    }
+
+    pub async fn analyze_challenge_points(
+        &mut self,
+        session: u64,
+        challenge_id: u64,
+        from_zone: Option<u64>,
+        samples: u64,
+    ) -> Result<PointDistribution> {
+        match self
+            .send(cmd::analyze_challenge_points(
+                session,
+                challenge_id,
+                from_zone,
+                samples,
+            ))
+            .await?
+        {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendPointDistribution(distribution) => Ok(distribution),
+            other => Err(Error::invalid_signal("SendPointDistribution", other)),
+        }
+    }
+
+    pub async fn refresh_challenges(&mut self, session: u64, team: usize) -> Result<()> {
+        self.send_expecting_success(cmd::refresh_challenges(session, team))
+            .await
+    }
+
+    pub async fn regenerate_challenges_except(
+        &mut self,
+        session: u64,
+        team: usize,
+        keep_indices: Vec<usize>,
+    ) -> Result<()> {
+        self.send_expecting_success(cmd::regenerate_challenges_except(
+            session,
+            team,
+            keep_indices,
+        ))
+        .await
+    }
+
+    pub async fn get_candidate_challenges(
+        &mut self,
+        session: u64,
+        team: usize,
+        period: Option<GenerationPeriod>,
+    ) -> Result<CandidateChallenges> {
+        match self
+            .send(cmd::get_candidate_challenges(session, team, period))
+            .await?
+        {
+            ResponseAction::Error(err) => Err(Error::Truinlag(err)),
+            ResponseAction::SendCandidateChallenges(candidates) => Ok(candidates),
+            other => Err(Error::invalid_signal("SendCandidateChallenges", other)),
         }
     }
 }
 
 pub struct RecvConnection {
-    broadcast_recv: mpsc::Receiver<BroadcastAction>,
+    broadcast_recv: mpsc::Receiver<NumberedBroadcast>,
     handle: tokio::task::JoinHandle<Result<()>>,
+    last_sequence: Option<u64>,
 }
 
 impl RecvConnection {
     pub async fn recv(&mut self) -> Option<BroadcastAction> {
-        self.broadcast_recv.recv().await
+        let (sequence, action) = self.broadcast_recv.recv().await?;
+        self.last_sequence = sequence.or(self.last_sequence);
+        Some(action)
+    }
+
+    // The sequence number of the latest broadcast received, for asking `get_recent_broadcasts`
+    // for the ones missed while deactivated or disconnected
+    pub fn last_sequence(&self) -> Option<u64> {
+        self.last_sequence
     }
 
     pub async fn disconnect(self) {
@@ -287,20 +1064,38 @@ impl RecvConnection {
         let inner_recv = broadcast_recv.clone();
         let eater_handle = tokio::spawn(async move {
             let mut inner_recv = inner_recv.lock().await;
-            while let Some(_) = inner_recv.recv().await {}
+            while inner_recv.recv().await.is_some() {}
         });
         InactiveRecvConnection {
             broadcast_recv,
             eater_handle,
             handle: self.handle,
+            last_sequence: self.last_sequence,
         }
     }
 }
 
+// Allows using stream combinators on the broadcasts, e.g. `recv_connection.filter(...)`
+impl Stream for RecvConnection {
+    type Item = BroadcastAction;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.broadcast_recv.poll_recv(cx).map(|received| {
+            let (sequence, action) = received?;
+            self.last_sequence = sequence.or(self.last_sequence);
+            Some(action)
+        })
+    }
+}
+
 pub struct InactiveRecvConnection {
-    broadcast_recv: Arc<Mutex<mpsc::Receiver<BroadcastAction>>>,
+    broadcast_recv: Arc<Mutex<mpsc::Receiver<NumberedBroadcast>>>,
     eater_handle: tokio::task::JoinHandle<()>,
     handle: tokio::task::JoinHandle<Result<()>>,
+    last_sequence: Option<u64>,
 }
 
 impl InactiveRecvConnection {
@@ -311,6 +1106,7 @@ impl InactiveRecvConnection {
         RecvConnection {
             handle: self.handle,
             broadcast_recv,
+            last_sequence: self.last_sequence,
         }
     }
 
@@ -321,19 +1117,19 @@ impl InactiveRecvConnection {
 }
 
 impl commands::ResponseAction {
-    fn unwrap_team(action: commands::ResponseAction) -> Result<Team> {
+    fn unwrap_player(action: commands::ResponseAction) -> Result<Player> {
         match action {
-            ResponseAction::Team(team) => Ok(team),
+            ResponseAction::Player(player) => Ok(player),
             ResponseAction::Error(error) => Err(error.into()),
-            _ => Err(Error::InvalidSignal),
+            other => Err(Error::invalid_signal("Player", other)),
         }
     }
 
-    fn unwrap_player(action: commands::ResponseAction) -> Result<Player> {
+    fn unwrap_period_summary(action: commands::ResponseAction) -> Result<PeriodSummary> {
         match action {
-            ResponseAction::Player(player) => Ok(player),
+            ResponseAction::SendPeriodSummary(summary) => Ok(summary),
             ResponseAction::Error(error) => Err(error.into()),
-            _ => Err(Error::InvalidSignal),
+            other => Err(Error::invalid_signal("SendPeriodSummary", other)),
         }
     }
 
@@ -341,7 +1137,455 @@ impl commands::ResponseAction {
         match action {
             ResponseAction::SendState { teams, game } => Ok((teams, game)),
             ResponseAction::Error(error) => Err(error.into()),
-            _ => Err(Error::InvalidSignal),
+            other => Err(Error::invalid_signal("SendState", other)),
+        }
+    }
+
+    fn unwrap_success(action: commands::ResponseAction) -> Result<()> {
+        match action {
+            ResponseAction::Success => Ok(()),
+            ResponseAction::Error(error) => Err(error.into()),
+            other => Err(Error::invalid_signal("Success", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Answers the commands it receives with `responses` in order, passing the commands on
+    async fn fake_engine(
+        responses: Vec<ResponseAction>,
+    ) -> (
+        SendConnection,
+        InactiveRecvConnection,
+        mpsc::UnboundedReceiver<EngineCommand>,
+    ) {
+        let (client, engine) = tokio::io::duplex(1 << 16);
+        let (client_read, client_write) = tokio::io::split(client);
+        let (engine_read, engine_write) = tokio::io::split(engine);
+        let (command_send, command_recv) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut read = FramedRead::new(engine_read, LengthDelimitedCodec::new());
+            let mut write = FramedWrite::new(engine_write, LengthDelimitedCodec::new());
+            for action in responses {
+                let Some(Ok(frame)) = read.next().await else {
+                    return;
+                };
+                let package: EngineCommandPackage = bincode::deserialize(&frame).unwrap();
+                command_send.send(package.command).ok();
+                let response = ClientCommand::Response(ResponsePackage {
+                    action,
+                    id: package.id,
+                });
+                let serialized = bincode::serialize(&response).unwrap();
+                write.send(Bytes::from(serialized)).await.unwrap();
+            }
+        });
+        let (send, recv) = insert_connection(client_read, client_write).await.unwrap();
+        (send, recv, command_recv)
+    }
+
+    // No wrapper expects this one
+    fn unexpected() -> ResponseAction {
+        ResponseAction::SendGlobalState {
+            sessions: Vec::new(),
+            players: Vec::new(),
+        }
+    }
+
+    fn raw_challenge(id: Option<u64>) -> RawChallenge {
+        RawChallenge {
+            kind: ChallengeType::Unspezifisch,
+            sets: Default::default(),
+            status: ChallengeStatus::Approved,
+            title: Some("Test".into()),
+            description: Some("Test".into()),
+            random_place: None,
+            place: None,
+            comment: String::new(),
+            kaffskala: None,
+            grade: None,
+            zone: Vec::new(),
+            bias_sat: 1.0,
+            bias_sun: 1.0,
+            walking_time: 0,
+            stationary_time: 0,
+            additional_points: 100,
+            repetitions: 0..1,
+            points_per_rep: 0,
+            station_distance: 0,
+            time_to_hb: 0,
+            departures: 0,
+            dead_end: false,
+            no_disembark: false,
+            requires_photo: false,
+            requires_completed: Vec::new(),
+            exclusive_group: None,
+            fixed: true,
+            in_perimeter_override: None,
+            translated_titles: HashMap::new(),
+            translated_descriptions: HashMap::new(),
+            action: None,
+            // fixed, so both copies of the challenge are the same
+            last_edit: chrono::DateTime::UNIX_EPOCH.into(),
+            id,
+            dangling_sets: Vec::new(),
+            dangling_zones: Vec::new(),
+        }
+    }
+
+    fn zone(id: u64) -> Zone {
+        Zone {
+            zone: 110,
+            num_conn_zones: 0,
+            num_connections: 0,
+            train_through: false,
+            mongus: false,
+            s_bahn_zone: false,
+            minutes_to: HashMap::new(),
+            id,
+            centre: None,
+            boundary: Vec::new(),
+        }
+    }
+
+    fn challenge() -> Challenge {
+        Challenge {
+            title: "Test".into(),
+            description: "Test".into(),
+            points: 100,
+            completable: true,
+            completable_at: None,
+            breakdown: None,
         }
     }
+
+    fn summary(team: usize) -> PeriodSummary {
+        PeriodSummary {
+            team,
+            points_gained: 100,
+            points: 100,
+            bounty: 0,
+            challenges: Vec::new(),
+            catch: None,
+            milestones: Vec::new(),
+        }
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            version: 2,
+            data: "{}".into(),
+        }
+    }
+
+    // Each wrapper has to send `$command` and make `$result` of `$response`. Engine errors have
+    // to come back as `Error::Truinlag` and unexpected responses as `Error::InvalidSignal`.
+    macro_rules! wrapper_tests {
+        ($($(#[$meta:meta])* $name:ident: $method:ident($($arg:expr),*) sends $command:expr,
+            $response:expr => $result:pat $(if $guard:expr)?,)*) => {
+            $(
+                $(#[$meta])*
+                #[tokio::test]
+                async fn $name() {
+                    let (mut send, _recv, mut received) = fake_engine(vec![
+                        $response,
+                        ResponseAction::Error(commands::Error::NotFound),
+                        unexpected(),
+                    ])
+                    .await;
+                    let result = send.$method($($arg),*).await;
+                    assert!(matches!(&result, $result $(if $guard)?), "{:?}", result);
+                    assert_eq!(
+                        format!("{:?}", received.recv().await.unwrap()),
+                        format!("{:?}", $command)
+                    );
+                    let result = send.$method($($arg),*).await;
+                    assert!(
+                        matches!(result, Err(Error::Truinlag(commands::Error::NotFound))),
+                        "{:?}",
+                        result
+                    );
+                    let result = send.$method($($arg),*).await;
+                    assert!(
+                        matches!(result, Err(Error::InvalidSignal { .. })),
+                        "{:?}",
+                        result
+                    );
+                }
+            )*
+        };
+    }
+
+    wrapper_tests! {
+        get_sessions_detailed: get_sessions_detailed() sends cmd::get_sessions_detailed(),
+            ResponseAction::SendSessionsDetailed(Vec::new()) => Ok(s) if s.is_empty(),
+        get_raw_challenges: get_raw_challenges() sends cmd::get_raw_challenges(),
+            ResponseAction::SendRawChallenges(Vec::new()) => Ok(c) if c.is_empty(),
+        get_zone: get_zone(4) sends cmd::get_zone(4),
+            ResponseAction::SendZones(vec![zone(4)]) => Ok(z) if z.id == 4,
+        get_zone_by_number: get_zone_by_number(110) sends cmd::get_zone_by_number(110),
+            ResponseAction::SendZones(vec![zone(4)]) => Ok(z) if z.zone == 110,
+        set_raw_challenge: set_raw_challenge(raw_challenge(Some(3)))
+            sends cmd::set_raw_challenge(raw_challenge(Some(3))),
+            ResponseAction::Success => Ok(()),
+        add_raw_challenge: add_raw_challenge(raw_challenge(None))
+            sends cmd::add_raw_challenge(raw_challenge(None)),
+            ResponseAction::Success => Ok(()),
+        add_session: add_session("Test", Mode::Traditional)
+            sends cmd::add_session("Test", Mode::Traditional),
+            ResponseAction::Success => Ok(()),
+        add_player: add_player("Hans", Some(7), "passphrase", Some(1))
+            sends cmd::add_player("Hans", Some(7), "passphrase", Some(1)),
+            ResponseAction::Success => Ok(()),
+        set_player_session: set_player_session(2, Some(1))
+            sends cmd::set_player_session(2, Some(1)),
+            ResponseAction::Success => Ok(()),
+        move_player: move_player(2, 1, Some(0)) sends cmd::move_player(2, 1, Some(0)),
+            ResponseAction::Success => Ok(()),
+        set_player_name: set_player_name(2, "Ueli") sends cmd::set_player_name(2, "Ueli"),
+            ResponseAction::Success => Ok(()),
+        set_player_passphrase: set_player_passphrase(2, "secret")
+            sends cmd::set_player_passphrase(2, "secret"),
+            ResponseAction::Success => Ok(()),
+        set_player_language: set_player_language(2, Some("en".into()))
+            sends cmd::set_player_language(2, Some("en".into())),
+            ResponseAction::Success => Ok(()),
+        set_player_ready: set_player_ready(2, true) sends cmd::set_player_ready(2, true),
+            ResponseAction::Success => Ok(()),
+        remove_player: remove_player(2) sends cmd::remove_player(2),
+            ResponseAction::Success => Ok(()),
+        get_player_by_passphrase: get_player_by_passphrase("secret")
+            sends cmd::get_player_by_passphrase("secret"),
+            ResponseAction::Player(Player {
+                name: "Hans".into(),
+                id: 2,
+                session: None,
+                ready: false,
+            }) => Ok(p) if p.id == 2,
+        ping: ping(Some("hi".into())) sends cmd::ping(Some("hi".into())),
+            ResponseAction::Success => Ok(()),
+        announce: announce(Some(1), "hello") sends cmd::announce(Some(1), "hello"),
+            ResponseAction::Success => Ok(()),
+        get_state: get_state(1) sends cmd::get_state(1),
+            ResponseAction::SendState {
+                teams: Vec::new(),
+                game: None,
+            } => Ok((t, None)) if t.is_empty(),
+        get_state_paged: get_state_paged(1, false, true, 2, Some(3))
+            sends cmd::get_state_paged(1, false, true, 2, Some(3)),
+            ResponseAction::SendStatePage {
+                teams: Vec::new(),
+                game: None,
+                total_teams: 5,
+            } => Ok((_, None, 5)),
+        get_state_diff: get_state_diff(1, 4) sends cmd::get_state_diff(1, 4),
+            ResponseAction::SendStateDiff {
+                version: 6,
+                teams: Vec::new(),
+                game: None,
+            } => Ok((6, _, None)),
+        get_readiness: get_readiness(1) sends cmd::get_readiness(1),
+            ResponseAction::SendReadiness(Vec::new()) => Ok(r) if r.is_empty(),
+        get_available_colours: get_available_colours(1) sends cmd::get_available_colours(1),
+            ResponseAction::SendColours(ColourPalette {
+                used: Vec::new(),
+                available: vec![Colour { r: 1, g: 2, b: 3 }],
+            }) => Ok(p) if p.available.len() == 1,
+        get_team_catch_stats: get_team_catch_stats(1, 2) sends cmd::get_team_catch_stats(1, 2),
+            ResponseAction::SendTeamCatchStats(TeamCatchStats {
+                team: 2,
+                ..Default::default()
+            }) => Ok(s) if s.team == 2,
+        get_player_contributions: get_player_contributions(1, 2)
+            sends cmd::get_player_contributions(1, 2),
+            ResponseAction::SendPlayerContributions(Vec::new()) => Ok(c) if c.is_empty(),
+        get_events: get_events(1) sends cmd::get_events(1),
+            ResponseAction::SendEvents(Vec::new()) => Ok(e) if e.is_empty(),
+        export_events: export_events(1) sends cmd::get_events(1),
+            ResponseAction::SendEvents(Vec::new()) => Ok(csv) if csv.starts_with("time,kind,"),
+        upload_period_pictures: upload_period_pictures(1, 2, 3, Vec::new(), 99)
+            sends cmd::upload_period_pictures(1, 2, 3, Vec::new(), 99),
+            ResponseAction::SendPictureIds(vec![5, 6]) => Ok(ids) if *ids == [5, 6],
+        is_game_running: is_game_running(1) sends cmd::is_game_running(1),
+            ResponseAction::GameRunning(true) => Ok(true),
+        get_period_path: get_period_path(1, 2, 3) sends cmd::get_period_path(1, 2, 3),
+            ResponseAction::SendLocations(Vec::new()) => Ok(l) if l.is_empty(),
+        set_game_end_time: set_game_end_time(1, chrono::NaiveTime::from_hms_opt(1, 0, 0).unwrap())
+            sends cmd::set_game_end_time(1, chrono::NaiveTime::from_hms_opt(1, 0, 0).unwrap()),
+            ResponseAction::Success => Ok(()),
+        set_session_mode: set_session_mode(1, Mode::Gfrorefurz)
+            sends cmd::set_session_mode(1, Mode::Gfrorefurz),
+            ResponseAction::Success => Ok(()),
+        snapshot_scores: snapshot_scores(1) sends cmd::snapshot_scores(1),
+            ResponseAction::Success => Ok(()),
+        get_score_snapshots: get_score_snapshots(1) sends cmd::get_score_snapshots(1),
+            ResponseAction::SendScoreSnapshots(Vec::new()) => Ok(s) if s.is_empty(),
+        start: start(1) sends cmd::start(1), ResponseAction::Success => Ok(()),
+        stop: stop(1) sends cmd::stop(1), ResponseAction::Success => Ok(()),
+        add_team: add_team(1, "Team", Some(8), Some(Colour { r: 1, g: 2, b: 3 }))
+            sends cmd::add_team(1, "Team", Some(8), Some(Colour { r: 1, g: 2, b: 3 })),
+            ResponseAction::Success => Ok(()),
+        add_team_with_hex_colour: add_team_with_hex_colour(1, "Team", Some(8), "#010203")
+            sends cmd::add_team(1, "Team", Some(8), Some(Colour { r: 1, g: 2, b: 3 })),
+            ResponseAction::Success => Ok(()),
+        rename_team: rename_team(1, 2, "New") sends cmd::rename_team(1, 2, "New"),
+            ResponseAction::Success => Ok(()),
+        assign_player_to_team: assign_player_to_team(1, 2, Some(0))
+            sends cmd::assign_player_to_team(1, 2, Some(0)),
+            ResponseAction::Success => Ok(()),
+        make_team_catcher: make_team_catcher(1, 2) sends cmd::make_team_catcher(1, 2),
+            ResponseAction::Success => Ok(()),
+        make_team_runner: make_team_runner(1, 2) sends cmd::make_team_runner(1, 2),
+            ResponseAction::Success => Ok(()),
+        catch: catch(1, 0, 2) sends cmd::catch(1, 0, 2),
+            ResponseAction::SendPeriodSummary(summary(0)) => Ok(s) if s.team == 0,
+        complete: complete(1, 0, 2) sends cmd::complete(1, 0, 2),
+            ResponseAction::SendPeriodSummary(summary(0)) => Ok(s) if s.team == 0,
+        complete_with_pictures: complete_with_pictures(1, 0, 2, Vec::new())
+            sends cmd::complete_with_pictures(1, 0, 2, Vec::new()),
+            ResponseAction::SendPeriodSummary(summary(0)) => Ok(s) if s.team == 0,
+        complete_as_player: complete_as_player(1, 0, 2, 7, Vec::new())
+            sends cmd::complete_as_player(1, 0, 2, 7, Vec::new()),
+            ResponseAction::SendPeriodSummary(summary(0)) => Ok(s) if s.team == 0,
+        send_location: send_location(1, 7, (47.4, 8.5)) sends cmd::send_location(1, 7, (47.4, 8.5)),
+            ResponseAction::Success => Ok(()),
+        add_challenge_to_team: add_challenge_to_team(1, 2, challenge())
+            sends cmd::add_challenge_to_team(1, 2, challenge()),
+            ResponseAction::Success => Ok(()),
+        delete_zone: delete_zone(4, true) sends cmd::delete_zone(4, true),
+            ResponseAction::Success => Ok(()),
+        set_zone_distances: set_zone_distances(4, HashMap::from([(5, 10)]))
+            sends cmd::set_zone_distances(4, HashMap::from([(5, 10)])),
+            ResponseAction::Success => Ok(()),
+        set_zone_centre: set_zone_centre(4, Some((47.4, 8.5)))
+            sends cmd::set_zone_centre(4, Some((47.4, 8.5))),
+            ResponseAction::Success => Ok(()),
+        set_zone_boundary: set_zone_boundary(4, vec![(47.0, 8.0), (47.5, 8.0), (47.5, 8.5)])
+            sends cmd::set_zone_boundary(4, vec![(47.0, 8.0), (47.5, 8.0), (47.5, 8.5)]),
+            ResponseAction::Success => Ok(()),
+        get_zone_at: get_zone_at((47.4, 8.5)) sends cmd::get_zone_at((47.4, 8.5)),
+            ResponseAction::SendZones(Vec::new()) => Ok(None),
+        compute_zone_distances: compute_zone_distances() sends cmd::compute_zone_distances(),
+            ResponseAction::FilledZoneDistances(12) => Ok(12),
+        get_active_timers: get_active_timers(Some(1)) sends cmd::get_active_timers(1),
+            ResponseAction::SendTimers(Vec::new()) => Ok(t) if t.is_empty(),
+        get_all_active_timers: get_active_timers(None) sends cmd::get_all_active_timers(),
+            ResponseAction::SendTimers(Vec::new()) => Ok(t) if t.is_empty(),
+        cancel_timer: cancel_timer(3) sends cmd::cancel_timer(3),
+            ResponseAction::Success => Ok(()),
+        authenticate: authenticate("secret") sends cmd::authenticate("secret"),
+            ResponseAction::Success => Ok(()),
+        set_connection_role: set_connection_role(ConnectionRole::Spectator)
+            sends cmd::set_connection_role(ConnectionRole::Spectator),
+            ResponseAction::Success => Ok(()),
+        reload_config: reload_config() sends cmd::reload_config(),
+            ResponseAction::Success => Ok(()),
+        check_integrity: check_integrity() sends cmd::check_integrity(),
+            ResponseAction::SendIntegrityReport(Vec::new()) => Ok(p) if p.is_empty(),
+        repair_integrity: repair_integrity() sends cmd::repair_integrity(),
+            ResponseAction::SendIntegrityReport(Vec::new()) => Ok(p) if p.is_empty(),
+        get_action_log: get_action_log(Some(1), None) sends cmd::get_action_log(Some(1), None),
+            ResponseAction::SendActionLog(Vec::new()) => Ok(l) if l.is_empty(),
+        #[cfg(feature = "debug-time")]
+        advance_time: advance_time(30) sends cmd::advance_time(30),
+            ResponseAction::Success => Ok(()),
+        #[cfg(feature = "debug-locations")]
+        ingest_locations: ingest_locations(1, 2, Vec::new())
+            sends cmd::ingest_locations(1, 2, Vec::new()),
+            ResponseAction::SendLocations(Vec::new()) => Ok(l) if l.is_empty(),
+        get_recent_broadcasts: get_recent_broadcasts(Some(4), Some(10))
+            sends cmd::get_recent_broadcasts(Some(4), Some(10)),
+            ResponseAction::SendBroadcasts(Vec::new()) => Ok(b) if b.is_empty(),
+        suggest_zone_distances: suggest_zone_distances(3) sends cmd::suggest_zone_distances(3),
+            ResponseAction::SendTravelTimeSuggestions(Vec::new()) => Ok(s) if s.is_empty(),
+        get_metrics: get_metrics() sends cmd::get_metrics(),
+            ResponseAction::SendMetrics(Vec::new()) => Ok(m) if m.is_empty(),
+        get_picture_metadata: get_picture_metadata(5) sends cmd::get_picture_metadata(5),
+            ResponseAction::SendPictureMetadata(PictureMetadata::default())
+                => Ok(m) if *m == PictureMetadata::default(),
+        export_snapshot: export_snapshot() sends cmd::export_snapshot(),
+            ResponseAction::SendSnapshot(snapshot()) => Ok(s) if s.version == 2,
+        import_snapshot: import_snapshot(snapshot()) sends cmd::import_snapshot(snapshot()),
+            ResponseAction::Success => Ok(()),
+        simulate_game: simulate_game(1, 10, Some(2)) sends cmd::simulate_game(1, 10, Some(2)),
+            ResponseAction::SendSimulation(Vec::new()) => Ok(p) if p.is_empty(),
+        analyze_challenge_points: analyze_challenge_points(1, 3, Some(4), 100)
+            sends cmd::analyze_challenge_points(1, 3, Some(4), 100),
+            ResponseAction::SendPointDistribution(
+                PointDistribution::from_samples(&[100, 200]).unwrap()
+            ) => Ok(d) if d.samples == 2,
+        refresh_challenges: refresh_challenges(1, 2) sends cmd::refresh_challenges(1, 2),
+            ResponseAction::Success => Ok(()),
+        regenerate_challenges_except: regenerate_challenges_except(1, 2, vec![0])
+            sends cmd::regenerate_challenges_except(1, 2, vec![0]),
+            ResponseAction::Success => Ok(()),
+        get_candidate_challenges: get_candidate_challenges(1, 2, Some(GenerationPeriod::Normal))
+            sends cmd::get_candidate_challenges(1, 2, Some(GenerationPeriod::Normal)),
+            ResponseAction::SendCandidateChallenges(CandidateChallenges {
+                period: GenerationPeriod::Normal,
+                tier: 0,
+                challenges: vec![3],
+            }) => Ok(c) if c.challenges == [3],
+    }
+
+    // Unlike the others, this one doesn't tell engine errors apart from unexpected responses
+    #[tokio::test]
+    async fn get_global_state() {
+        let (mut send, _recv, mut received) = fake_engine(vec![
+            ResponseAction::SendGlobalState {
+                sessions: Vec::new(),
+                players: Vec::new(),
+            },
+            ResponseAction::Success,
+        ])
+        .await;
+        let result = send.get_global_state().await;
+        assert!(matches!(result, Ok((s, p)) if s.is_empty() && p.is_empty()));
+        assert_eq!(
+            format!("{:?}", received.recv().await.unwrap()),
+            format!("{:?}", cmd::get_global_state())
+        );
+        let result = send.get_global_state().await;
+        assert!(matches!(result, Err(Error::InvalidSignal { .. })));
+    }
+
+    // These are refused before anything is sent, so the fake engine has no responses
+    #[tokio::test]
+    async fn set_raw_challenge_without_id() {
+        let (mut send, _recv, mut received) = fake_engine(Vec::new()).await;
+        let result = send.set_raw_challenge(raw_challenge(None)).await;
+        assert!(matches!(
+            result,
+            Err(Error::Truinlag(commands::Error::BadData(_)))
+        ));
+        drop(send);
+        assert!(received.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn add_raw_challenge_with_id() {
+        let (mut send, _recv, mut received) = fake_engine(Vec::new()).await;
+        let result = send.add_raw_challenge(raw_challenge(Some(3))).await;
+        assert!(matches!(
+            result,
+            Err(Error::Truinlag(commands::Error::BadData(_)))
+        ));
+        drop(send);
+        assert!(received.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn add_team_with_malformed_hex_colour() {
+        let (mut send, _recv, mut received) = fake_engine(Vec::new()).await;
+        let result = send
+            .add_team_with_hex_colour(1, "Team", None, "#01020")
+            .await;
+        assert!(result.is_err());
+        drop(send);
+        assert!(received.recv().await.is_none());
+    }
 }
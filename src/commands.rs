@@ -30,7 +30,8 @@ impl From<ResponseAction> for EngineResponse {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ClientCommand {
-    Broadcast(BroadcastAction),
+    Broadcast(BroadcastAction), // locations, which aren't numbered
+    SequencedBroadcast(SequencedBroadcast),
     Response(ResponsePackage),
 }
 
@@ -65,6 +66,11 @@ pub enum EngineAction {
         player: u64,
         session: Option<u64>,
     },
+    MovePlayer {
+        player: u64,
+        to_session: u64,
+        to_team: Option<usize>,
+    },
     SetPlayerName {
         player: u64,
         name: String,
@@ -73,6 +79,14 @@ pub enum EngineAction {
         player: u64,
         passphrase: String,
     },
+    SetPlayerLanguage {
+        player: u64,
+        language: Option<String>,
+    },
+    SetPlayerReady {
+        player: u64,
+        ready: bool,
+    },
     RemovePlayer {
         player: u64,
     },
@@ -83,19 +97,66 @@ pub enum EngineAction {
     Complete {
         completer: usize,
         completed: usize,
+        pictures: Vec<Picture>, // required if the challenge requires a photo
+        player: Option<u64>,    // the team member to credit, if any
     },
     SendLocation {
         player: u64,
         location: (f64, f64),
     },
+    UploadPeriodPictures {
+        team: usize,
+        period: usize, // index into the team's completed challenges
+        pictures: Vec<Picture>,
+        token: u64, // chosen by the client, retries with the same token don't add duplicates
+    },
     SetRawChallenge(RawChallenge),
     AddRawChallenge(RawChallenge),
     GetPlayerByPassphrase(String),
     GetRawChallenges,
+    GetSessionsDetailed,
     Start,
     Stop,
     Ping(Option<String>),
+    Announce {
+        message: String, // to the players of the session, or everyone without one
+    },
     GetState,
+    GetStatePaged {
+        include_completed: bool,
+        include_locations: bool,
+        teams_offset: usize,
+        teams_limit: Option<usize>,
+    },
+    GetStateDiff {
+        since_version: u64,
+    },
+    GetEvents,
+    GetReadiness,
+    GetAvailableColours,
+    GetTeamCatchStats {
+        team: usize,
+    },
+    GetPlayerContributions {
+        team: usize,
+    },
+    IsGameRunning,
+    SnapshotScores,
+    GetScoreSnapshots,
+    SetGameEndTime {
+        new_time: chrono::NaiveTime,
+    },
+    SetSessionMode {
+        mode: Mode,
+    },
+    GetPeriodPath {
+        team: usize,
+        period: usize,
+    },
+    GetActiveTimers,
+    CancelTimer {
+        id: u64,
+    },
     MakeTeamCatcher(usize),
     MakeTeamRunner(usize),
     AddChallengeToTeam {
@@ -106,6 +167,289 @@ pub enum EngineAction {
         team: usize,
         new_name: String,
     },
+    GetZone(u64),
+    GetZoneByNumber(u64),
+    GetZoneAt {
+        location: (f64, f64), // latitude, longitude
+    },
+    DeleteZone {
+        zone: u64,
+        cascade: bool, // also remove the zone from distances and challenges instead of refusing
+    },
+    SetZoneDistances {
+        from_zone: u64,
+        distances: std::collections::HashMap<u64, u64>,
+    },
+    ComputeZoneDistances,
+    SetZoneCentre {
+        zone: u64,
+        centre: Option<(f64, f64)>, // latitude, longitude
+    },
+    SetZoneBoundary {
+        zone: u64,
+        boundary: Vec<(f64, f64)>, // latitude, longitude, empty removes it
+    },
+    ReloadConfig,
+    ExportSnapshot,
+    ImportSnapshot(Snapshot),
+    CheckIntegrity,
+    RepairIntegrity,
+    GetActionLog {
+        session: Option<u64>,
+        since: Option<chrono::DateTime<chrono::Local>>,
+    },
+    GetMetrics,
+    GetRecentBroadcasts {
+        since: Option<u64>,   // sequence number, only broadcasts after it are sent
+        limit: Option<usize>, // the most recent ones if there are more
+    },
+    SuggestZoneDistances {
+        min_samples: u64, // zone pairs observed fewer times are left out
+    },
+    GetPictureMetadata(u64),
+    SetConnectionRole(ConnectionRole), // handled by the runtime, never reaches the engine
+    Authenticate {
+        secret: String,
+    }, // handled by the runtime, never reaches the engine
+    SimulateGame {
+        games: u64,                 // capped by the config
+        challenge_set: Option<u64>, // only generate challenges from this set
+    },
+    AnalyzeChallengePoints {
+        challenge_id: u64,
+        from_zone: Option<u64>,
+        samples: u64, // capped by the config
+    },
+    GetCandidateChallenges {
+        team: usize,
+        period: Option<GenerationPeriod>, // None for the current one
+    },
+    RefreshChallenges {
+        team: usize,
+    },
+    RegenerateChallengesExcept {
+        team: usize,
+        keep_indices: Vec<usize>, // open challenges that stay
+    },
+    // last, so the other variants are numbered the same with and without the feature
+    #[cfg(feature = "debug-time")]
+    AdvanceTime {
+        minutes: i64, // chrono durations aren't serialisable
+    },
+    #[cfg(feature = "debug-locations")]
+    IngestLocations {
+        team: usize,
+        locations: Vec<MinimalLocation>, // oldest first
+    },
+}
+
+impl EngineAction {
+    // Whether the action changes anything in the engine, as opposed to just reading from it
+    pub fn is_mutating(&self) -> bool {
+        use EngineAction::*;
+        match self {
+            AddSession { .. }
+            | AddPlayer { .. }
+            | AddTeam { .. }
+            | AssignPlayerToTeam { .. }
+            | SetPlayerSession { .. }
+            | MovePlayer { .. }
+            | SetPlayerName { .. }
+            | SetPlayerPassphrase { .. }
+            | SetPlayerLanguage { .. }
+            | SetPlayerReady { .. }
+            | RemovePlayer { .. }
+            | Catch { .. }
+            | Complete { .. }
+            | SendLocation { .. }
+            | UploadPeriodPictures { .. }
+            | SetRawChallenge(_)
+            | AddRawChallenge(_)
+            | Start
+            | Stop
+            | SnapshotScores
+            | SetGameEndTime { .. }
+            | SetSessionMode { .. }
+            | CancelTimer { .. }
+            | MakeTeamCatcher(_)
+            | MakeTeamRunner(_)
+            | AddChallengeToTeam { .. }
+            | RenameTeam { .. }
+            | DeleteZone { .. }
+            | SetZoneDistances { .. }
+            | ComputeZoneDistances
+            | SetZoneCentre { .. }
+            | SetZoneBoundary { .. }
+            | ReloadConfig
+            | ImportSnapshot(_)
+            | RepairIntegrity
+            | RefreshChallenges { .. }
+            | RegenerateChallengesExcept { .. } => true,
+            #[cfg(feature = "debug-time")]
+            AdvanceTime { .. } => true,
+            #[cfg(feature = "debug-locations")]
+            IngestLocations { .. } => true,
+            GetPlayerByPassphrase(_)
+            | GetRawChallenges
+            | GetSessionsDetailed
+            | Ping(_)
+            | Announce { .. }
+            | GetState
+            | GetStatePaged { .. }
+            | GetStateDiff { .. }
+            | GetEvents
+            | GetReadiness
+            | GetAvailableColours
+            | GetTeamCatchStats { .. }
+            | GetPlayerContributions { .. }
+            | IsGameRunning
+            | GetScoreSnapshots
+            | GetPeriodPath { .. }
+            | GetActiveTimers
+            | GetZone(_)
+            | GetZoneByNumber(_)
+            | GetZoneAt { .. }
+            | ExportSnapshot
+            | CheckIntegrity
+            | GetActionLog { .. }
+            | GetMetrics
+            | GetRecentBroadcasts { .. }
+            | SuggestZoneDistances { .. }
+            | GetPictureMetadata(_)
+            | SetConnectionRole(_)
+            | Authenticate { .. }
+            | SimulateGame { .. }
+            | AnalyzeChallengePoints { .. }
+            | GetCandidateChallenges { .. } => false,
+        }
+    }
+
+    // The name of the variant, e.g. for grouping metrics
+    pub fn name(&self) -> &'static str {
+        use EngineAction::*;
+        match self {
+            AddSession { .. } => "AddSession",
+            AddPlayer { .. } => "AddPlayer",
+            AddTeam { .. } => "AddTeam",
+            AssignPlayerToTeam { .. } => "AssignPlayerToTeam",
+            SetPlayerSession { .. } => "SetPlayerSession",
+            MovePlayer { .. } => "MovePlayer",
+            SetPlayerName { .. } => "SetPlayerName",
+            SetPlayerPassphrase { .. } => "SetPlayerPassphrase",
+            SetPlayerLanguage { .. } => "SetPlayerLanguage",
+            SetPlayerReady { .. } => "SetPlayerReady",
+            GetReadiness => "GetReadiness",
+            GetAvailableColours => "GetAvailableColours",
+            GetTeamCatchStats { .. } => "GetTeamCatchStats",
+            GetPlayerContributions { .. } => "GetPlayerContributions",
+            RemovePlayer { .. } => "RemovePlayer",
+            Catch { .. } => "Catch",
+            Complete { .. } => "Complete",
+            SendLocation { .. } => "SendLocation",
+            UploadPeriodPictures { .. } => "UploadPeriodPictures",
+            SetRawChallenge(_) => "SetRawChallenge",
+            AddRawChallenge(_) => "AddRawChallenge",
+            Start => "Start",
+            Stop => "Stop",
+            SnapshotScores => "SnapshotScores",
+            SetGameEndTime { .. } => "SetGameEndTime",
+            SetSessionMode { .. } => "SetSessionMode",
+            CancelTimer { .. } => "CancelTimer",
+            MakeTeamCatcher(_) => "MakeTeamCatcher",
+            MakeTeamRunner(_) => "MakeTeamRunner",
+            AddChallengeToTeam { .. } => "AddChallengeToTeam",
+            RenameTeam { .. } => "RenameTeam",
+            DeleteZone { .. } => "DeleteZone",
+            SetZoneDistances { .. } => "SetZoneDistances",
+            ComputeZoneDistances => "ComputeZoneDistances",
+            SetZoneCentre { .. } => "SetZoneCentre",
+            SetZoneBoundary { .. } => "SetZoneBoundary",
+            ReloadConfig => "ReloadConfig",
+            ImportSnapshot(_) => "ImportSnapshot",
+            RepairIntegrity => "RepairIntegrity",
+            GetPlayerByPassphrase(_) => "GetPlayerByPassphrase",
+            GetRawChallenges => "GetRawChallenges",
+            GetSessionsDetailed => "GetSessionsDetailed",
+            Ping(_) => "Ping",
+            Announce { .. } => "Announce",
+            GetState => "GetState",
+            GetStatePaged { .. } => "GetStatePaged",
+            GetStateDiff { .. } => "GetStateDiff",
+            GetEvents => "GetEvents",
+            IsGameRunning => "IsGameRunning",
+            GetScoreSnapshots => "GetScoreSnapshots",
+            GetPeriodPath { .. } => "GetPeriodPath",
+            GetActiveTimers => "GetActiveTimers",
+            GetZone(_) => "GetZone",
+            GetZoneByNumber(_) => "GetZoneByNumber",
+            GetZoneAt { .. } => "GetZoneAt",
+            ExportSnapshot => "ExportSnapshot",
+            CheckIntegrity => "CheckIntegrity",
+            GetActionLog { .. } => "GetActionLog",
+            SetConnectionRole(_) => "SetConnectionRole",
+            Authenticate { .. } => "Authenticate",
+            SimulateGame { .. } => "SimulateGame",
+            AnalyzeChallengePoints { .. } => "AnalyzeChallengePoints",
+            GetCandidateChallenges { .. } => "GetCandidateChallenges",
+            RefreshChallenges { .. } => "RefreshChallenges",
+            RegenerateChallengesExcept { .. } => "RegenerateChallengesExcept",
+            GetMetrics => "GetMetrics",
+            GetRecentBroadcasts { .. } => "GetRecentBroadcasts",
+            SuggestZoneDistances { .. } => "SuggestZoneDistances",
+            #[cfg(feature = "debug-time")]
+            AdvanceTime { .. } => "AdvanceTime",
+            #[cfg(feature = "debug-locations")]
+            IngestLocations { .. } => "IngestLocations",
+            GetPictureMetadata(_) => "GetPictureMetadata",
+        }
+    }
+
+    // Destructive actions that need an authenticated connection if the engine has a secret set
+    pub fn is_privileged(&self) -> bool {
+        use EngineAction::*;
+        match self {
+            RemovePlayer { .. }
+            | SetRawChallenge(_)
+            | AddRawChallenge(_)
+            | Stop
+            | CancelTimer { .. }
+            | DeleteZone { .. }
+            | SetZoneDistances { .. }
+            | ComputeZoneDistances
+            | SetZoneCentre { .. }
+            | SetZoneBoundary { .. }
+            | ImportSnapshot(_)
+            | ExportSnapshot
+            | RepairIntegrity
+            | ReloadConfig
+            | RefreshChallenges { .. }
+            | RegenerateChallengesExcept { .. }
+            | Announce { .. } => true,
+            #[cfg(feature = "debug-time")]
+            AdvanceTime { .. } => true,
+            #[cfg(feature = "debug-locations")]
+            IngestLocations { .. } => true,
+            _ => false,
+        }
+    }
+
+    // Read-only actions that don't give away passphrases or referee internals
+    pub fn spectator_allowed(&self) -> bool {
+        use EngineAction::*;
+        !self.is_mutating()
+            && !matches!(
+                self,
+                GetPlayerByPassphrase(_)
+                    | ExportSnapshot
+                    | CheckIntegrity
+                    | GetActionLog { .. }
+                    | GetCandidateChallenges { .. }
+                    | SimulateGame { .. }
+                    | AnalyzeChallengePoints { .. }
+                    | SuggestZoneDistances { .. }
+                    | Announce { .. }
+            )
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -118,10 +462,44 @@ pub enum ResponseAction {
         teams: Vec<Team>,
         game: Option<Game>,
     },
+    SendStatePage {
+        teams: Vec<Team>,
+        game: Option<Game>,
+        total_teams: usize,
+    },
+    SendStateDiff {
+        version: u64,
+        teams: Vec<Team>, // only the teams that changed since the requested version
+        game: Option<Game>,
+    },
+    SendEvents(Vec<Event>),
+    SendReadiness(Vec<TeamReadiness>),
+    SendColours(ColourPalette),
+    SendTeamCatchStats(TeamCatchStats),
+    SendPlayerContributions(Vec<PlayerContribution>),
+    SendPictureIds(Vec<u64>),
+    SendPictureMetadata(PictureMetadata),
     SendGlobalState {
         sessions: Vec<GameSession>,
         players: Vec<Player>,
     },
+    SendSessionsDetailed(Vec<SessionDetails>),
+    GameRunning(bool),
+    SendTimers(Vec<Timer>),
+    SendPeriodSummary(PeriodSummary),
+    SendLocations(Vec<MinimalLocation>),
+    SendScoreSnapshots(Vec<ScoreSnapshot>),
+    SendZones(Vec<Zone>),
+    FilledZoneDistances(u64),
+    SendSnapshot(Snapshot),
+    SendIntegrityReport(Vec<IntegrityProblem>),
+    SendActionLog(Vec<LoggedAction>),
+    SendMetrics(Vec<CommandMetrics>),
+    SendBroadcasts(Vec<SequencedBroadcast>),
+    SendTravelTimeSuggestions(Vec<TravelTimeSuggestion>),
+    SendSimulation(Vec<SimulatedPeriod>),
+    SendPointDistribution(PointDistribution),
+    SendCandidateChallenges(CandidateChallenges),
     Success,
 }
 
@@ -136,8 +514,22 @@ pub enum BroadcastAction {
         completed: Challenge,
     },
     Started,
-    Ended,
+    UnreadyAtStart {
+        session: u64,
+        teams: Vec<TeamReadiness>, // only the teams with unready players
+    },
+    Ended {
+        summary: GameSummary,
+    },
+    GameEndTimeChanged {
+        session: u64,
+        end_time: chrono::DateTime<chrono::Local>,
+    },
     Pinged(Option<String>),
+    Announcement {
+        session: Option<u64>, // `None` for everyone
+        message: String,
+    },
     Location {
         team: usize,
         location: (f64, f64),
@@ -153,12 +545,59 @@ pub enum BroadcastAction {
         from_team: Option<usize>,
         to_team: Option<usize>,
     },
+    PlayerMoved {
+        player: Player,
+        from_session: Option<u64>,
+        from_team: Option<usize>,
+        to_session: u64,
+        to_team: Option<usize>,
+    },
+    // a team was added without a colour when all of `team_colours` were taken, so it got black
+    TeamColoursExhausted {
+        session: u64,
+        team: usize,
+    },
+    MilestoneReached {
+        session: u64,
+        team: usize,
+        milestone: Milestone,
+        points: u64,
+    },
     PlayerDeleted(Player),
     TeamMadeCatcher(Team),
     TeamMadeRunner(Team),
+    ChallengesChanged {
+        session: u64,
+        team: usize,
+        challenges: Vec<Challenge>,
+    },
+    TrapBaited {
+        session: u64,
+        team: usize,
+        message: String,
+    },
+    GenerationFailed {
+        session: u64,
+        team: usize,
+        category: GenerationPeriod,
+    }, // the team kept its previous challenges or got the default challenge
+    SuspiciousLocation {
+        session: u64,
+        team: usize,
+        implied_speed: f64, // km/h
+    },
+    NewEvent {
+        session: u64,
+        event: Event,
+    },
+    TeamStationary {
+        session: u64,
+        team: usize,
+        minutes: u64,
+    },
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum Error {
     NoSessionSupplied, // Session specific commands like catch or add_team need a session
     SessionSupplied,   // Global commands like AddPlayer cannot be run with a session supplied
@@ -166,11 +605,20 @@ pub enum Error {
     TeamExists(String), // You cannot create a team if one with a similar name already exists
     AlreadyExists,     // Things that already exist cannot be created
     GameInProgress,    // Commands like AddTeam cannot be run if a game is in progress
-    GameNotRunning,    // Commands like catch can only be run if a game is in progress
+    GameNotStarted,    // Commands like catch can only be run if a game is in progress
+    GameEnded,         // Like GameNotStarted, but the session's last game is already over
     AmbiguousData,     // If multiple matching objects exist, e.g. players with passphrase lol
     InternalError,     // Some sort of internal database error
     NotImplemented,    // Feature is not yet implemented
     BadData(String),
+    Unauthorized,  // The connection's role or authentication doesn't allow the command
+    TooRapid,      // The connection sent more commands than the rate limit allows
+    PhotoRequired, // The challenge can only be completed with a picture
+    TeamFull,      // The team already has `max_team_size` players
+    ChallengeNotYetCompletable {
+        completable_at: chrono::DateTime<chrono::Local>,
+    }, // Trap and uncompletable minutes challenges are locked for a while
+    NotAtChallengeLocation, // The team wasn't last seen near the challenge's zone
 }
 
 impl std::fmt::Display for Error {
@@ -182,11 +630,26 @@ impl std::fmt::Display for Error {
             Self::TeamExists(team) => write!(f, "Team {} already exists", team),
             Self::AlreadyExists => write!(f, "Already exists"),
             Self::GameInProgress => write!(f, "There is already a game in progress"),
-            Self::GameNotRunning => write!(f, "There is no game in progress"),
+            Self::GameNotStarted => write!(f, "The game hasn't started yet"),
+            Self::GameEnded => write!(f, "The game is over"),
             Self::AmbiguousData => write!(f, "Ambiguous data"),
             Self::InternalError => write!(f, "There was a truinlag-internal error"),
             Self::NotImplemented => write!(f, "Not yet implemented"),
             Self::BadData(text) => write!(f, "bad data: {}", text),
+            Self::Unauthorized => write!(f, "This connection is not allowed to do that"),
+            Self::TooRapid => write!(f, "Too many commands, slow down"),
+            Self::PhotoRequired => write!(f, "This challenge needs a picture to be completed"),
+            Self::TeamFull => write!(f, "The team is full"),
+            Self::ChallengeNotYetCompletable { completable_at } => write!(
+                f,
+                "The challenge can't be completed before {}",
+                completable_at.format("%H:%M:%S")
+            ),
+            Self::NotAtChallengeLocation => {
+                write!(f, "The team isn't where the challenge has to be done")
+            }
         }
     }
 }
+
+impl std::error::Error for Error {}
@@ -19,19 +19,27 @@ use bonsaidb::{
     },
 };
 use chrono::{self, NaiveTime};
+use geo::{Contains, GeodesicDistance, LineString, Point, Polygon};
 use image::imageops::FilterType;
 use partially::Partial;
 use rand::prelude::*;
 use rand_distr::{Distribution, Normal};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+};
 use strsim::normalized_damerau_levenshtein as strcmp;
 use tokio::time::{sleep, Duration};
 use truinlag::{
-    commands::{BroadcastAction, EngineAction, EngineResponse, ResponseAction},
+    commands::{BroadcastAction, EngineAction, EngineCommand, EngineResponse, ResponseAction},
     *,
 };
 
+const SLOW_COMMAND: Duration = Duration::from_millis(100);
+const STATIONARY_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const BROADCAST_REPLAY_LENGTH: usize = 256;
+
 #[derive(Partial, Debug, Clone, Serialize, Deserialize)]
 #[partially(derive(Debug, Clone, Serialize, Deserialize, Default))]
 struct Config {
@@ -42,6 +50,12 @@ struct Config {
     points_per_walking_minute: u64,
     points_per_stationary_minute: u64,
     points_per_travel_minute: u64,
+    points_for_dead_end: u64,
+    points_for_no_disembark: i64, // riding through a zone can be easier or harder than stopping
+    score_dead_ends: bool,        // off, dead ends are worth nothing, as they used to be
+    min_challenge_points: u64,
+    max_challenge_points: Option<u64>,
+    send_point_breakdown: bool,
 
     // Zonenkaff
     points_per_connected_zone_less_than_6: u64,
@@ -55,11 +69,34 @@ struct Config {
     // Number of active challenges per team
     num_challenges: u64,
 
+    // Catches
+    catch_flat_bonus: u64,        // on top of the bounty
+    catch_streak_multiplier: f64, // applied to the flat bonus per catch without completing in between
+
+    milestone_bonuses: Vec<(Milestone, u64)>, // points for the team reaching each one first
+
     // Bounty system
     bounty_base_points: u64,
     bounty_start_points: u64,
     bounty_percentage: f64,
 
+    // Stationary detection
+    stationary_radius: f64, // metres
+    stationary_minutes: u64,
+
+    // Teleport detection, jumps shorter than the minimum are put down to gps jitter
+    max_plausible_speed: f64,   // km/h
+    min_suspicious_jump: f64,   // metres
+    accept_all_locations: bool, // skips teleport detection, e.g. for replays or external tracking
+
+    // Completion checks, zones without a centre or boundary aren't checked
+    require_location_for_completion: bool,
+    completion_radius: f64, // metres from the centre, if the team isn't inside the zone's boundary
+
+    // Rate limiting per connection, only read when the engine starts
+    commands_per_second: f64,
+    command_burst: u32,
+
     // Times
     start_time: chrono::NaiveTime,
     end_time: chrono::NaiveTime,
@@ -67,11 +104,40 @@ struct Config {
     perimeter_minutes: u64,
     zkaff_minutes: u64,
     end_game_minutes: u64,
+    max_game_hours: u64, // games are stopped this long after starting, whatever the end time says
+
+    // Teams
+    min_team_size: usize,         // checked at start
+    max_team_size: Option<usize>, // checked when assigning players
 
     // Fallback Defaults
+    default_language: Option<String>, // None -> untranslated titles and descriptions
     default_challenge_title: String,
     default_challenge_description: String,
 
+    // Generation
+    centre_zone: Option<u64>, // id for ZoneEntry collection in db
+    centre_zones: Vec<u64>,   // further centres for regional end games, teams head for the nearest
+    perimeter_distance: u64,  // minutes from the centre zone
+    endgame_max_distance_from_centre: Option<u64>, // minutes, applies to zkaff and end game
+    min_challenge_zone_distance: Option<u64>, // minutes between the zones of a team's challenges
+    spread_start_challenges: bool, // send teams to different zones in the specific period at start
+    challenge_refresh_minutes: Option<u64>, // new challenges for runners who complete none for this long
+    keep_open_challenges: bool,             // completing a challenge only replaces that one
+    zone_from_location: bool, // teams' current zone follows their gps instead of their challenges
+    max_start_challenge_distance: Option<u64>, // minutes from the start zone, checked at start
+    challenge_fallbacks: Vec<ChallengeFallback>, // tried in order if no challenge fits properly
+    set_rules: HashMap<u64, Vec<ChallengeType>>, // challenge set id -> kinds generated from it
+    manual_challenges: bool,  // nothing is generated, organizers hand out every challenge
+
+    // Analysis
+    max_simulated_games: u64, // SimulateGame plays at most this many games per request
+    max_point_samples: u64,   // same for the samples of AnalyzeChallengePoints
+
+    // Memory
+    location_retention_periods: Option<usize>, // finished periods whose locations stay in memory
+    action_log_length: usize,                  // the oldest logged actions are dropped beyond this
+
     // additional options
     team_colours: Vec<Colour>,
 }
@@ -85,15 +151,33 @@ impl Default for Config {
             points_per_walking_minute: 10,
             points_per_stationary_minute: 10,
             points_per_travel_minute: 12,
+            points_for_dead_end: 40,
+            points_for_no_disembark: 0,
+            score_dead_ends: false,
+            min_challenge_points: 0,
+            max_challenge_points: None,
+            send_point_breakdown: false,
             points_per_bad_connectivity_index: 25,
             points_per_connected_zone_less_than_6: 15,
             points_for_no_train: 30,
             points_for_mongus: 50,
             num_catchers: 3,
             num_challenges: 3,
+            catch_flat_bonus: 0,
+            catch_streak_multiplier: 1.0,
+            milestone_bonuses: Vec::new(),
             bounty_base_points: 100,
             bounty_start_points: 250,
             bounty_percentage: 0.25,
+            stationary_radius: 150.0,
+            stationary_minutes: 30,
+            max_plausible_speed: 250.0,
+            min_suspicious_jump: 1000.0,
+            accept_all_locations: false,
+            require_location_for_completion: false,
+            completion_radius: 3000.0,
+            commands_per_second: 20.0,
+            command_burst: 100,
             start_time: chrono::NaiveTime::from_hms_opt(9, 0, 0)
                 .expect("This is hardcoded and should never fail"),
             end_time: chrono::NaiveTime::from_hms_opt(17, 0, 0)
@@ -102,6 +186,32 @@ impl Default for Config {
             perimeter_minutes: 90,
             zkaff_minutes: 90,
             end_game_minutes: 30,
+            max_game_hours: 24,
+            centre_zone: None,
+            centre_zones: Vec::new(),
+            perimeter_distance: 40,
+            endgame_max_distance_from_centre: None,
+            min_challenge_zone_distance: None,
+            spread_start_challenges: false,
+            challenge_refresh_minutes: None,
+            keep_open_challenges: false,
+            zone_from_location: false,
+            max_start_challenge_distance: None,
+            set_rules: HashMap::new(),
+            manual_challenges: false,
+            challenge_fallbacks: vec![
+                ChallengeFallback::Crowded,
+                ChallengeFallback::Unspread,
+                ChallengeFallback::OutOfPeriod,
+                ChallengeFallback::Repeated,
+            ],
+            max_simulated_games: 200,
+            max_point_samples: 10000,
+            location_retention_periods: None,
+            action_log_length: 5000,
+            min_team_size: 0,
+            max_team_size: None,
+            default_language: None,
             default_challenge_title: "[Kreative Titel]".into(),
             default_challenge_description:
                 "Ihr hend Päch, die Challenge isch unlösbar. Ihr müend e anderi uswähle.".into(),
@@ -156,15 +266,32 @@ impl Default for Config {
     }
 }
 
+// Looser filters for when no challenge fits the period, is far enough from the team's other
+// challenges and away from the other teams' challenges
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum ChallengeFallback {
+    Crowded,     // may share a zone or challenge with another team
+    Unspread,    // may also be close to the team's other challenges
+    OutOfPeriod, // may also not fit the period
+    Repeated,    // may also be one the team already had
+}
+
 #[derive(Schema)]
-#[schema(name="engine", collections=[Session, PlayerEntry, ChallengeEntry, ZoneEntry, PastGame, PictureEntry])]
+#[schema(name="engine", collections=[Session, PlayerEntry, ChallengeEntry, ChallengeSetEntry, ZoneEntry, PastGame, PictureEntry, TimerTracker, SchemaMeta, ActionLogEntry, LocationArchiveEntry])]
 struct EngineSchema {}
 
 #[derive(Debug, Collection, Serialize, Deserialize, Clone)]
 #[collection(name = "picture")]
 enum PictureEntry {
-    Profile { small: Picture, large: Picture },
-    ChallengePicture(Picture),
+    Profile {
+        small: Picture,
+        large: Picture,
+    },
+    ChallengePicture {
+        picture: Picture,
+        #[serde(default)]
+        metadata: PictureMetadata, // read from the EXIF data on upload
+    },
 }
 
 #[allow(dead_code)]
@@ -197,7 +324,60 @@ impl PictureEntry {
     }
 
     fn new_challenge_picture(image: image::DynamicImage) -> Result<Self, image::ImageError> {
-        Ok(Self::ChallengePicture(image.try_into()?))
+        Ok(Self::ChallengePicture {
+            picture: image.try_into()?,
+            metadata: PictureMetadata::default(),
+        })
+    }
+
+    // Checks that all pictures decode before storing any of them
+    fn store_challenge_pictures(
+        pictures: Vec<Picture>,
+        db: &Database,
+        headers: &mut Vec<Header>,
+    ) -> Result<Vec<u64>, commands::Error> {
+        if pictures
+            .iter()
+            .any(|p| image::DynamicImage::try_from(p.clone()).is_err())
+        {
+            return Err(commands::Error::BadData("couldn't decode a picture".into()));
+        }
+        let mut ids = Vec::new();
+        for picture in pictures {
+            let entry = Self::ChallengePicture {
+                metadata: picture.metadata(),
+                picture,
+            };
+            match Self::push(entry, db) {
+                Ok(doc) => {
+                    ids.push(doc.header.id);
+                    if let Ok(header) = Header::try_from(doc.header) {
+                        headers.push(header);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Engine: couldn't store challenge picture: {}", err);
+                    Self::delete_all(&ids, db, headers);
+                    return Err(commands::Error::InternalError);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    fn delete_all(ids: &[u64], db: &Database, headers: &mut Vec<Header>) {
+        for id in ids {
+            match Self::get(id, db) {
+                Ok(Some(doc)) => {
+                    if let Err(err) = doc.delete(db) {
+                        eprintln!("Engine: couldn't delete picture {}: {}", id, err);
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => eprintln!("Engine: couldn't get picture {}: {}", id, err),
+            }
+        }
+        headers.retain(|h| !h.id.deserialize::<u64>().is_ok_and(|id| ids.contains(&id)));
     }
 }
 
@@ -208,6 +388,10 @@ struct PlayerEntry {
     passphrase: String,
     discord_id: Option<u64>,
     session: Option<u64>,
+    #[serde(default)]
+    language: Option<String>, // key for the translated titles and descriptions of challenges
+    #[serde(default)]
+    ready: bool, // checked in for the next game
 }
 
 impl PlayerEntry {
@@ -216,6 +400,7 @@ impl PlayerEntry {
             name: self.name.clone(),
             session: self.session,
             id,
+            ready: self.ready,
         }
     }
 }
@@ -261,6 +446,12 @@ struct ChallengeEntry {
     departures: u8,
     dead_end: bool,
     no_disembark: bool,
+    #[serde(default)]
+    requires_photo: bool, // can't be completed without a picture
+    #[serde(default)]
+    requires_completed: Vec<u64>, // ids for ChallengeEntry collection in db
+    #[serde(default)]
+    exclusive_group: Option<u64>, // variants of a challenge that shouldn't be offered together
     fixed: bool,
     in_perimeter_override: Option<bool>,
     translated_titles: HashMap<String, String>,
@@ -275,16 +466,23 @@ impl ChallengeEntry {
         id: u64,
         challenge_sets: &[DBEntry<ChallengeSetEntry>],
         zone_entries: &[DBEntry<ZoneEntry>],
-    ) -> Result<RawChallenge, commands::Error> {
-        Ok(RawChallenge {
+    ) -> RawChallenge {
+        let mut dangling_sets = Vec::new();
+        let mut dangling_zones = Vec::new();
+        RawChallenge {
             kind: self.kind,
             sets: {
                 let mut sets = std::collections::HashSet::new();
                 for s in self.sets.clone() {
-                    sets.insert({
-                        let set = challenge_sets.iter().find(|c| c.id == s).ok_or_else(|| {
+                    match challenge_sets.iter().find(|c| c.id == s) {
+                        Some(set) => {
+                            sets.insert(set.contents.to_sendable(set.id));
+                        }
+                        None => {
                             eprintln!("Couldn't find ChallengeSet with id {} in db while making challenge with id {} sendable, maybe it was improperly removed?", s, id);
-                            commands::Error::InternalError})?; set.contents.to_sendable(set.id)});
+                            dangling_sets.push(s);
+                        }
+                    }
                 }
                 sets
             },
@@ -298,11 +496,14 @@ impl ChallengeEntry {
             grade: self.grade,
             zone: {
                 let mut zones = Vec::new();
-                for s in self.zone.clone() {
-                    zones.push({
-                        let set = zone_entries.iter().find(|z| z.id == s).ok_or_else(|| {
-                            eprintln!("Couldn't find ChallengeSet with id {} in db, maybe it was improperly removed?", s);
-                            commands::Error::InternalError})?; set.contents.to_sendable(set.id)});
+                for z in self.zone.clone() {
+                    match zone_entries.iter().find(|e| e.id == z) {
+                        Some(zone) => zones.push(zone.contents.to_sendable(zone.id)),
+                        None => {
+                            eprintln!("Couldn't find Zone with id {} in db while making challenge with id {} sendable, maybe it was improperly removed?", z, id);
+                            dangling_zones.push(z);
+                        }
+                    }
                 }
                 zones
             },
@@ -318,6 +519,9 @@ impl ChallengeEntry {
             departures: self.departures,
             dead_end: self.dead_end,
             no_disembark: self.no_disembark,
+            requires_photo: self.requires_photo,
+            requires_completed: self.requires_completed.clone(),
+            exclusive_group: self.exclusive_group,
             fixed: self.fixed,
             in_perimeter_override: self.in_perimeter_override,
             action: self.action.clone(),
@@ -325,141 +529,89 @@ impl ChallengeEntry {
             translated_descriptions: self.translated_descriptions.clone(),
             last_edit: self.last_edit,
             id: Some(id),
-        })
+            dangling_sets,
+            dangling_zones,
+        }
     }
 
-    #[allow(dead_code)]
-    async fn challenge(
+    fn challenge(
         &self,
+        id: u64,
         config: &Config,
         zone_zoneables: bool,
-        db: &Database,
-    ) -> Option<InOpenChallenge> {
+        from_zone: Option<u64>,
+        context: &EngineContext,
+        language: Option<&str>,
+    ) -> InOpenChallenge {
         // TODO: if zoneable and zone specified do something to let me know kthxbye
-        let mut points = 0_i64;
-        points += self.additional_points as i64;
-        if let Some(kaffskala) = self.kaffskala {
-            points += kaffskala as i64 * config.points_per_kaffness as i64;
-        }
-        if let Some(grade) = self.grade {
-            points += grade as i64 * config.points_per_grade as i64;
-        }
-        points += self.walking_time as i64 * config.points_per_walking_minute as i64;
-        points += self.stationary_time as i64 * config.points_per_stationary_minute as i64;
+        let zones = context.zone_entries;
         let reps = self
             .repetitions
             .clone()
             .choose(&mut thread_rng())
             .unwrap_or(0);
-        points += reps as i64 * self.points_per_rep as i64;
         let mut zone_entries = vec![];
-        let initial_zones = self.zone.clone();
-        for zone in initial_zones {
-            match db
-                .view::<ZonesByZone>()
-                .with_key(&zone)
-                .query_with_collection_docs()
-            {
-                Ok(zones) => {
-                    if zones.is_empty() {
-                        eprintln!(
-                            "Engine: Couldn't find zone {} in database, skipping Zonenkaff and granting 0 points",
-                            zone
-                        );
-                    } else if zones.len() > 1 {
-                        let entry = zones
-                            .get(0)
-                            .expect("This should never fail, since zones.len() > 1");
-                        eprintln!(
-                            "Engine: Found {} entries for zone {} in database, using the entry with id {} for Zonenkaff",
-                            zones.len(),
-                            zone,
-                            entry.document.header.id
-                            );
-                        zone_entries.push(entry.document.clone())
-                    } else {
-                        zone_entries.push(
-                            zones
-                                .get(0)
-                                .expect("This should never even be called")
-                                .document
-                                .clone(),
-                        )
-                    }
-                }
-                Err(err) => {
-                    eprintln!(
-                        "Engine: Couldn't query database for zone {}, ignoring zone: {}",
-                        zone, err
-                    )
-                }
+        for zone in &self.zone {
+            match zones.iter().find(|z| &z.id == zone) {
+                Some(entry) => zone_entries.push(entry),
+                None => eprintln!(
+                    "Engine: Couldn't find zone with id {} for challenge {}, skipping Zonenkaff and granting 0 points",
+                    zone, id
+                ),
             }
         }
+        // a challenge without getting off the train needs a zone trains go through
+        let rideable = |z: &&DBEntry<ZoneEntry>| !self.no_disembark || z.contents.train_through;
         if zone_zoneables && matches!(self.kind, ChallengeType::Zoneable) {
-            match ZoneEntry::all(db).query() {
-                Ok(entries) => {
-                    zone_entries = vec![entries
-                        .iter()
-                        .choose(&mut thread_rng())
-                        .expect("There are probably no ZoneEntries")
-                        .clone()]
-                }
-                Err(err) => {
-                    eprintln!("Engine: Couldn't retreive zones from database while selecting random zone for zoneable, skipping step: {}", err)
-                }
+            match zones.iter().filter(rideable).choose(&mut thread_rng()) {
+                Some(entry) => zone_entries = vec![entry],
+                None => eprintln!(
+                    "Engine: There are no zones to select a random zone for zoneable {} from, skipping step",
+                    id
+                ),
             }
         }
         if let Some(place_type) = &self.random_place {
-            match place_type{RandomPlaceType::Zone=>{match ZoneEntry::all(db).query(){Ok(entries)=>zone_entries=vec![entries.iter().choose(&mut thread_rng()).expect("There are probably no ZoneEntries").clone()],Err(err)=>eprintln!("Engine: Couldn't retrieve zones from database while choosing random zone, skipping step: {}",err),}}RandomPlaceType::SBahnZone=>{match db.view::<ZonesBySBahn>().with_key(&true).query_with_collection_docs(){Ok(entries)=>zone_entries=vec![entries.documents.values().choose(&mut thread_rng()).expect("no s-bahn zones found in database").clone()],Err(err)=>eprintln!("Engine: Couldn't retrieve s-bahn zones from database while choosing random s-bahn zone, skipping step: {}",err),}}}
+            let entry = match place_type {
+                RandomPlaceType::Zone => zones.iter().filter(rideable).choose(&mut thread_rng()),
+                RandomPlaceType::SBahnZone => zones
+                    .iter()
+                    .filter(rideable)
+                    .filter(|z| z.contents.s_bahn_zone)
+                    .choose(&mut thread_rng()),
+            };
+            match entry {
+                Some(entry) => zone_entries = vec![entry],
+                None => eprintln!(
+                    "Engine: Couldn't find a random {:?} for challenge {}, skipping step",
+                    place_type, id
+                ),
+            }
         }
-        let (zone, z_points) = zone_entries.iter().fold((None, 0), |acc, z| {
+        if zone_entries.iter().any(rideable) {
+            zone_entries.retain(rideable);
+        } else if self.no_disembark && !zone_entries.is_empty() {
+            eprintln!(
+                "Engine: challenge {} is no_disembark, but none of its zones are train_through",
+                id
+            );
+        }
+        let (zone, _) = zone_entries.iter().fold((None, 0), |acc, z| {
             if acc.1 == 0 || acc.1 > z.contents.zonic_kaffness(config) {
-                (Some(z), z.contents.zonic_kaffness(config))
+                (Some(*z), z.contents.zonic_kaffness(config))
             } else {
                 acc
             }
         });
-        points += z_points as i64;
-        if !self.fixed {
-            points += Normal::new(0_f64, points as f64 * config.relative_standard_deviation)
-                .expect("This should't fail if the challenge points and the relative_standard_deviation have reasonable values")
-                .sample(&mut thread_rng())
-                .round() as i64
-        }
-
-        let mut title = None;
-        if let Some(kaff) = &self.place {
-            title = Some(format!("Usflug Uf {}", kaff))
-        }
-        if let Some(title_override) = &self.title {
-            title = Some(title_override.clone())
-        }
-        if self.random_place.is_some() {
-            if let Some(t) = &mut title {
-                *t = t.replace("%p", &zone.expect("This should never fail, because it should only run if there is exactly 1 zone_entry").contents.zone.to_string())
-            }
-        }
-        if let Some(t) = &mut title {
-            *t = t.replace("%r", &reps.to_string());
-        }
-
-        let mut description = None;
-        if let Some(kaff) = &self.place {
-            description = Some(format!("Gönd nach {}.", kaff))
-        }
-        if let Some(description_override) = &self.description {
-            description = Some(description_override.clone())
-        }
-        if self.random_place.is_some() {
-            if let Some(d) = &mut description {
-                *d = d.replace("%p", &zone.expect("This should never fail, because it should only run if there is exactly 1 zone_entry").contents.zone.to_string())
-            }
-        }
-        if let Some(d) = &mut description {
-            *d = d.replace("%r", &reps.to_string());
+        let mut breakdown = context
+            .scoring
+            .score(self, reps, zone, from_zone, config, zones);
+        // also keeps negative points from wrapping around when cast to u64
+        let mut points = breakdown.total().max(config.min_challenge_points as i64) as u64;
+        if let Some(max) = config.max_challenge_points {
+            points = points.min(max);
         }
-
-        let zone = zone.map(|z| z.header.id);
+        breakdown.clamped = points as i64 - breakdown.total();
 
         let mut action = None;
         if let Some(action_entry) = &self.action {
@@ -468,26 +620,397 @@ impl ChallengeEntry {
                     stuck_minutes: min,
                     catcher_message,
                 } => ChallengeAction::Trap {
-                    completable_after: chrono::Local::now()
+                    completable_after: context.clock.now()
                         + chrono::Duration::minutes(min.unwrap_or(reps as u64) as i64),
                     catcher_message: catcher_message.clone(),
                 },
                 ChallengeActionEntry::UncompletableMinutes(minutes) => {
                     ChallengeAction::UncompletableMinutes(
-                        chrono::Local::now()
+                        context.clock.now()
                             + chrono::Duration::minutes(minutes.unwrap_or(reps as u64) as i64),
                     )
                 }
             });
         }
 
-        Some(InOpenChallenge {
+        // %p: the random place, %r: the repetitions, %n: the repetitions with thousands
+        // separators, %t: when a locked challenge unlocks, otherwise in %r minutes
+        let time = match &action {
+            Some(ChallengeAction::Trap {
+                completable_after: t,
+                catcher_message: _,
+            })
+            | Some(ChallengeAction::UncompletableMinutes(t)) => *t,
+            None => context.clock.now() + chrono::Duration::minutes(reps as i64),
+        };
+        let substitute = |text: String| {
+            let text = match (self.random_place, zone) {
+                (Some(_), Some(zone)) => text.replace("%p", &zone.contents.zone.to_string()),
+                _ => text,
+            };
+            text.replace("%r", &reps.to_string())
+                .replace("%n", &group_thousands(reps as u64, language))
+                .replace("%t", &time.format("%H:%M").to_string())
+        };
+
+        let mut title = None;
+        if let Some(kaff) = &self.place {
+            title = Some(format!("Usflug Uf {}", kaff))
+        }
+        if let Some(title_override) = language
+            .and_then(|l| self.translated_titles.get(l))
+            .or(self.title.as_ref())
+        {
+            title = Some(title_override.clone())
+        }
+        let title = title.map(substitute);
+
+        let mut description = None;
+        if let Some(kaff) = &self.place {
+            description = Some(format!("Gönd nach {}.", kaff))
+        }
+        if let Some(description_override) = language
+            .and_then(|l| self.translated_descriptions.get(l))
+            .or(self.description.as_ref())
+        {
+            description = Some(description_override.clone())
+        }
+        let description = description.map(substitute);
+
+        let zone = zone.map(|z| z.id);
+
+        InOpenChallenge {
             title: title.unwrap_or(config.default_challenge_title.clone()),
             description: description.unwrap_or(config.default_challenge_description.clone()),
-            points: points as u64,
+            points,
             action,
             zone,
+            id: Some(id),
+            breakdown: config.send_point_breakdown.then_some(breakdown),
+        }
+    }
+
+    // No disembark challenges with fixed zones need one that trains go through
+    fn is_rideable(&self, zones: &[DBEntry<ZoneEntry>]) -> bool {
+        !self.no_disembark
+            || self.zone.is_empty()
+            || self.zone.iter().any(|id| {
+                zones
+                    .iter()
+                    .any(|z| z.id == *id && z.contents.train_through)
+            })
+    }
+
+    // Specific challenges send a team to a particular place
+    fn is_specific(&self) -> bool {
+        match self.kind {
+            ChallengeType::Kaff | ChallengeType::Ortsspezifisch => true,
+            ChallengeType::Zoneable => true,
+            ChallengeType::Unspezifisch | ChallengeType::Regionsspezifisch => {
+                !self.zone.is_empty() || self.random_place.is_some()
+            }
+        }
+    }
+
+    // Sets without rules allow every kind, so a challenge that is also in an unrestricted set is
+    // never held back by the rules of its other sets.
+    fn allowed_by_set_rules(&self, config: &Config) -> bool {
+        self.sets.is_empty()
+            || self.sets.iter().any(|set| {
+                config
+                    .set_rules
+                    .get(set)
+                    .is_none_or(|kinds| kinds.contains(&self.kind))
+            })
+    }
+
+    // Whether the challenge can be done within `max_minutes` of the zone with id `centre`.
+    // Challenges with random places can't be known in advance and are never within.
+    fn is_within(&self, centre: u64, max_minutes: u64, zones: &[DBEntry<ZoneEntry>]) -> bool {
+        if self.random_place.is_some() || matches!(self.kind, ChallengeType::Zoneable) {
+            false
+        } else if self.zone.is_empty() {
+            true
+        } else {
+            self.zone.iter().any(|&z| {
+                zone_distance(zones, centre, z).is_some_and(|minutes| minutes <= max_minutes)
+            })
+        }
+    }
+
+    // Whether the challenge is allowed to be generated in the given period
+    // given the centre the team is heading for
+    fn fits_period(
+        &self,
+        period: GenerationPeriod,
+        config: &Config,
+        centre: Option<u64>,
+        zones: &[DBEntry<ZoneEntry>],
+    ) -> bool {
+        // the override lets organisers force challenges in or out of the perimeter by hand
+        let in_perimeter = || match (self.in_perimeter_override, centre) {
+            (Some(forced), _) => forced,
+            (None, Some(centre)) => self.is_within(centre, config.perimeter_distance, zones),
+            (None, None) => true,
+        };
+        match period {
+            GenerationPeriod::Specific => self.is_specific(),
+            GenerationPeriod::Normal => true,
+            GenerationPeriod::Perimeter => in_perimeter(),
+            GenerationPeriod::ZKaff => {
+                matches!(self.kind, ChallengeType::Kaff) && !self.zone.is_empty() && in_perimeter()
+            }
+            GenerationPeriod::EndGame => !self.is_specific(),
+        }
+    }
+
+    // Unlike `fits_period`, this is never relaxed when there are no candidates left
+    fn within_endgame_radius(
+        &self,
+        period: GenerationPeriod,
+        config: &Config,
+        centre: Option<u64>,
+        zones: &[DBEntry<ZoneEntry>],
+    ) -> bool {
+        match (period, centre, config.endgame_max_distance_from_centre) {
+            (GenerationPeriod::ZKaff | GenerationPeriod::EndGame, Some(centre), Some(max)) => {
+                self.is_within(centre, max, zones)
+            }
+            _ => true,
+        }
+    }
+}
+
+// 12345 -> 12'345, or 12,345 in english
+fn group_thousands(number: u64, language: Option<&str>) -> String {
+    let separator = match language {
+        Some(l) if l.starts_with("en") => ',',
+        _ => '\'',
+    };
+    let digits = number.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// Distance in metres between two (latitude, longitude) locations
+fn location_distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    Point::new(a.1, a.0).geodesic_distance(&Point::new(b.1, b.0))
+}
+
+// The zone containing the location, or else the one with the closest centre
+fn nearest_zone(zones: &[DBEntry<ZoneEntry>], location: (f64, f64)) -> Option<&DBEntry<ZoneEntry>> {
+    zones
+        .iter()
+        .find(|z| z.contents.contains(location) == Some(true))
+        .or_else(|| {
+            zones
+                .iter()
+                .filter_map(|z| Some((location_distance(z.contents.centre?, location), z)))
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, z)| z)
         })
+}
+
+// Travel time in minutes between the zones with ids `from` and `to`
+fn zone_distance(zones: &[DBEntry<ZoneEntry>], from: u64, to: u64) -> Option<u64> {
+    if from == to {
+        Some(0)
+    } else {
+        zones
+            .iter()
+            .find(|z| z.id == from)
+            .and_then(|z| z.contents.minutes_to.get(&to).copied())
+    }
+}
+
+fn announcement(session: Option<u64>, message: String) -> EngineResponse {
+    if message.trim().is_empty() {
+        return ResponseAction::Error(commands::Error::BadData("the announcement is empty".into()))
+            .into();
+    }
+    EngineResponse {
+        response_action: ResponseAction::Success,
+        broadcast_action: Some(BroadcastAction::Announcement { session, message }),
+    }
+}
+
+// The centre zone closest to the zone with id `from`. Without a known zone or distances, the
+// main centre (or the first of `centre_zones`) is used.
+fn nearest_centre(config: &Config, zones: &[DBEntry<ZoneEntry>], from: Option<u64>) -> Option<u64> {
+    let centres = config
+        .centre_zone
+        .iter()
+        .chain(&config.centre_zones)
+        .copied();
+    let first = centres.clone().next();
+    from.and_then(|from| {
+        centres
+            .filter_map(|centre| zone_distance(zones, from, centre).map(|d| (d, centre)))
+            .min()
+            .map(|(_, centre)| centre)
+    })
+    .or(first)
+}
+
+// Turns a generated challenge into points. `zone` is the zone the challenge was placed in and
+// `from_zone` the zone the team is currently in, if known. Clamping to the configured minimum and
+// maximum happens afterwards, so strategies don't have to care about it.
+trait ScoringStrategy: Send + Sync {
+    fn score(
+        &self,
+        challenge: &ChallengeEntry,
+        reps: u16,
+        zone: Option<&DBEntry<ZoneEntry>>,
+        from_zone: Option<u64>,
+        config: &Config,
+        zones: &[DBEntry<ZoneEntry>],
+    ) -> PointBreakdown;
+}
+
+// Where the game logic gets the current time from. Timers measure how long to sleep against it,
+// the action log stays on the wall clock.
+trait Clock: Send + Sync {
+    fn now(&self) -> chrono::DateTime<chrono::Local>;
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> chrono::DateTime<chrono::Local> {
+        chrono::Local::now()
+    }
+}
+
+// The wall clock moved by `AdvanceTime`
+#[cfg(feature = "debug-time")]
+struct ShiftedClock(chrono::TimeDelta);
+
+#[cfg(feature = "debug-time")]
+impl Clock for ShiftedClock {
+    fn now(&self) -> chrono::DateTime<chrono::Local> {
+        let now = chrono::Local::now();
+        // `advance_time` only accepts offsets that fit
+        now.checked_add_signed(self.0).unwrap_or(now)
+    }
+}
+
+// Always the same time, for simulations and tests
+struct FixedClock(chrono::DateTime<chrono::Local>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> chrono::DateTime<chrono::Local> {
+        self.0
+    }
+}
+
+// The regular truinlag formula
+struct DefaultScoring;
+
+impl ScoringStrategy for DefaultScoring {
+    fn score(
+        &self,
+        challenge: &ChallengeEntry,
+        reps: u16,
+        zone: Option<&DBEntry<ZoneEntry>>,
+        from_zone: Option<u64>,
+        config: &Config,
+        zones: &[DBEntry<ZoneEntry>],
+    ) -> PointBreakdown {
+        let mut breakdown = PointBreakdown {
+            additional: challenge.additional_points as i64,
+            walking: challenge.walking_time as i64 * config.points_per_walking_minute as i64,
+            stationary: challenge.stationary_time as i64
+                * config.points_per_stationary_minute as i64,
+            repetitions: reps as i64 * challenge.points_per_rep as i64,
+            ..Default::default()
+        };
+        if let Some(kaffskala) = challenge.kaffskala {
+            breakdown.kaffness = kaffskala as i64 * config.points_per_kaffness as i64;
+        }
+        if let Some(grade) = challenge.grade {
+            breakdown.grade = grade as i64 * config.points_per_grade as i64;
+        }
+        if challenge.dead_end && config.score_dead_ends {
+            breakdown.dead_end = config.points_for_dead_end as i64;
+        }
+        if challenge.no_disembark {
+            breakdown.no_disembark = config.points_for_no_disembark;
+        }
+        if let Some(zone) = zone {
+            breakdown.zone_kaffness = zone.contents.zonic_kaffness(config) as i64;
+            if let Some(from) = from_zone {
+                breakdown.travel = zone_distance(zones, from, zone.id).unwrap_or(0) as i64
+                    * config.points_per_travel_minute as i64;
+            }
+        }
+        if !challenge.fixed {
+            // a negative standard deviation is an error, so negative sums get no noise
+            breakdown.noise = Normal::new(0_f64, breakdown.total().max(0) as f64 * config.relative_standard_deviation)
+                .expect("This should't fail if the challenge points and the relative_standard_deviation have reasonable values")
+                .sample(&mut thread_rng())
+                .round() as i64
+        }
+        breakdown
+    }
+}
+
+// The generation period is determined by how far into the game we are. After the specific
+// period at the start, teams play normally until the perimeter, zkaff and end game periods
+// (in that order) pull them towards the centre.
+fn generation_period(config: &Config, time: NaiveTime) -> GenerationPeriod {
+    let mut since_start = (time - config.start_time).num_minutes();
+    let mut until_end = (config.end_time - time).num_minutes();
+    // games ending at or before their start time end the next day
+    if config.end_time <= config.start_time {
+        if since_start < 0 {
+            since_start += 24 * 60;
+        }
+        if until_end < 0 {
+            until_end += 24 * 60;
+        }
+    }
+    let end_game = config.end_game_minutes as i64;
+    let zkaff = end_game + config.zkaff_minutes as i64;
+    let perimeter = zkaff + config.perimeter_minutes as i64;
+    if since_start < config.specific_minutes as i64 {
+        GenerationPeriod::Specific
+    } else if until_end <= end_game {
+        GenerationPeriod::EndGame
+    } else if until_end <= zkaff {
+        GenerationPeriod::ZKaff
+    } else if until_end <= perimeter {
+        GenerationPeriod::Perimeter
+    } else {
+        GenerationPeriod::Normal
+    }
+}
+
+// When a game started at `start` ends at `time`: the same day, unless the configured game crosses
+// midnight (it ends at or before its start time) and `time` has already passed at the start, e.g.
+// an evening game ending at one in the morning. None if `time` is skipped that day because the
+// clocks go forward.
+fn game_end(
+    config: &Config,
+    start: chrono::DateTime<chrono::Local>,
+    time: NaiveTime,
+) -> Option<chrono::DateTime<chrono::Local>> {
+    let on = |date: chrono::NaiveDate| {
+        date.and_time(time)
+            .and_local_timezone(chrono::Local)
+            .earliest()
+    };
+    let same_day = on(start.date_naive())?;
+    if same_day <= start && config.end_time <= config.start_time {
+        // a calendar day rather than 24 hours, which is off on nights the clocks change
+        on(start.date_naive() + chrono::Days::new(1))
+    } else {
+        Some(same_day)
     }
 }
 
@@ -517,6 +1040,9 @@ impl From<RawChallenge> for ChallengeEntry {
             departures: v.departures,
             dead_end: v.dead_end,
             no_disembark: v.no_disembark,
+            requires_photo: v.requires_photo,
+            requires_completed: v.requires_completed,
+            exclusive_group: v.exclusive_group,
             fixed: v.fixed,
             in_perimeter_override: v.in_perimeter_override,
             translated_titles: v.translated_titles,
@@ -620,6 +1146,10 @@ struct ZoneEntry {
     mongus: bool,
     s_bahn_zone: bool,
     minutes_to: HashMap<u64, u64>,
+    #[serde(default)]
+    centre: Option<(f64, f64)>, // latitude, longitude
+    #[serde(default)]
+    boundary: Vec<(f64, f64)>, // latitude, longitude, empty if unknown
 }
 
 impl ZoneEntry {
@@ -633,6 +1163,31 @@ impl ZoneEntry {
             s_bahn_zone: self.s_bahn_zone,
             minutes_to: self.minutes_to.clone(),
             id,
+            centre: self.centre,
+            boundary: self.boundary.clone(),
+        }
+    }
+
+    fn has_geography(&self) -> bool {
+        self.centre.is_some() || self.boundary.len() >= 3
+    }
+
+    // `None` without a proper boundary
+    fn contains(&self, location: (f64, f64)) -> Option<bool> {
+        if self.boundary.len() < 3 {
+            return None;
+        }
+        let outline: LineString = self.boundary.iter().map(|&(lat, lon)| (lon, lat)).collect();
+        Some(Polygon::new(outline, Vec::new()).contains(&Point::new(location.1, location.0)))
+    }
+
+    // Inside the boundary or within `radius` metres of the centre. `None` if the zone's
+    // whereabouts aren't known.
+    fn is_near(&self, location: (f64, f64), radius: f64) -> Option<bool> {
+        match (self.contains(location), self.centre) {
+            (Some(true), _) => Some(true),
+            (_, Some(centre)) => Some(location_distance(centre, location) <= radius),
+            (inside, None) => inside,
         }
     }
 
@@ -661,6 +1216,56 @@ impl ZoneEntry {
     }
 }
 
+// Fills in every missing `minutes_to` entry with the shortest travel time through the zone
+// graph (Floyd-Warshall). Entries that already exist are never changed, so running this
+// repeatedly is harmless. Returns the number of entries that were added.
+fn fill_zone_distances(zones: &mut [DBEntry<ZoneEntry>]) -> u64 {
+    let n = zones.len();
+    let index_of: HashMap<u64, usize> = zones.iter().enumerate().map(|(i, z)| (z.id, i)).collect();
+    let mut dist = vec![vec![None; n]; n];
+    for (i, zone) in zones.iter().enumerate() {
+        dist[i][i] = Some(0_u64);
+        for (to, minutes) in &zone.contents.minutes_to {
+            match index_of.get(to) {
+                Some(&j) => {
+                    dist[i][j] = Some(dist[i][j].map_or(*minutes, |d: u64| d.min(*minutes)))
+                }
+                None => eprintln!(
+                    "Engine: zone {} has a distance to zone id {}, which doesn't exist, ignoring",
+                    zone.contents.zone, to
+                ),
+            }
+        }
+    }
+    for k in 0..n {
+        let via_k = dist[k].clone();
+        for row in dist.iter_mut() {
+            let Some(ik) = row[k] else { continue };
+            for (ij, kj) in row.iter_mut().zip(&via_k) {
+                if let Some(kj) = kj {
+                    if ij.is_none_or(|ij| ik + kj < ij) {
+                        *ij = Some(ik + kj);
+                    }
+                }
+            }
+        }
+    }
+    let ids: Vec<u64> = zones.iter().map(|z| z.id).collect();
+    let mut filled = 0;
+    for (i, zone) in zones.iter_mut().enumerate() {
+        for (j, &to) in ids.iter().enumerate() {
+            if i == j || zone.contents.minutes_to.contains_key(&to) {
+                continue;
+            }
+            if let Some(minutes) = dist[i][j] {
+                zone.contents.minutes_to.insert(to, minutes);
+                filled += 1;
+            }
+        }
+    }
+    filled
+}
+
 #[derive(Debug, Clone, View, ViewSchema)]
 #[view(collection = ZoneEntry, key = u64, value = u64, name = "by-zone")]
 struct ZonesByZone;
@@ -730,6 +1335,69 @@ impl CollectionMapReduce for PlayersByPassphrase {
     }
 }
 
+// The oldest locations of a team, moved out of memory once they are older than
+// `location_retention_periods`. Newest first, continuing `TeamEntry::locations`.
+#[derive(Debug, Collection, Serialize, Deserialize, Clone)]
+#[collection(name = "location archive")]
+struct LocationArchiveEntry {
+    locations: Vec<(f64, f64, NaiveTime)>,
+}
+
+impl LocationArchiveEntry {
+    // `moved` are the oldest locations of the team, newest first
+    fn append(
+        db: &Database,
+        team: &mut TeamEntry,
+        mut moved: Vec<(f64, f64, NaiveTime)>,
+    ) -> Result<(), bonsaidb::core::Error> {
+        let count = moved.len() as u64;
+        let existing = match team.location_archive {
+            Some(id) => Self::get(&id, db)?,
+            None => None,
+        };
+        match existing {
+            Some(mut doc) => {
+                // whatever a crash between archiving and saving the team left behind
+                let surplus = doc
+                    .contents
+                    .locations
+                    .len()
+                    .saturating_sub(team.archived_locations as usize);
+                doc.contents.locations.drain(..surplus);
+                moved.append(&mut doc.contents.locations);
+                doc.contents.locations = moved;
+                doc.update(db)?;
+            }
+            None => {
+                let doc = Self { locations: moved }
+                    .push_into(db)
+                    .map_err(|e| e.error)?;
+                team.location_archive = Some(doc.header.id);
+            }
+        }
+        team.archived_locations += count;
+        Ok(())
+    }
+
+    // Puts the archived locations back into the team, e.g. for a copy answering a query
+    fn restore(db: &Database, team: &mut TeamEntry) -> Result<(), bonsaidb::core::Error> {
+        if let Some(id) = team.location_archive {
+            if let Some(doc) = Self::get(&id, db)? {
+                let surplus = doc
+                    .contents
+                    .locations
+                    .len()
+                    .saturating_sub(team.archived_locations as usize);
+                let restored = doc.contents.locations.len() - surplus;
+                team.locations
+                    .extend(doc.contents.locations.into_iter().skip(surplus));
+                team.archived_locations -= restored as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TeamEntry {
     pub name: String,
@@ -745,6 +1413,32 @@ pub struct TeamEntry {
     pub catcher_periods: Vec<CatcherPeriod>,
     pub caught_periods: Vec<CaughtPeriod>,
     pub trophy_periods: Vec<TrophyPeriod>,
+    #[serde(default)]
+    pub current_zone_id: Option<u64>, // id for ZoneEntry collection in db
+    #[serde(default)]
+    pub changed_at: u64, // the session's state version of the last change to the team
+    #[serde(default)]
+    pub archived_locations: u64, // oldest locations moved to the db, they still count as indices
+    #[serde(default)]
+    pub location_archive: Option<u64>, // id for LocationArchiveEntry collection in db
+    #[serde(default)]
+    pub generation_failed: Option<GenerationPeriod>, // the last generation found too few challenges
+    #[serde(default)]
+    pub catch_streak: u64, // catches since the last completed challenge
+    #[serde(default)]
+    pub refresh_timer: Option<TimerHook>, // see `Config::challenge_refresh_minutes`
+    #[serde(default)]
+    pub milestone_bonuses: Vec<MilestoneBonus>,
+    #[serde(default)]
+    pub stationary: bool, // TeamStationary was broadcast and the team hasn't moved since
+}
+
+// Not a period of its own, the bonus is part of the period that reached the milestone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MilestoneBonus {
+    milestone: Milestone,
+    points: u64,
+    time: chrono::NaiveTime,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -769,18 +1463,43 @@ pub struct CatcherPeriod {
     bounty: u64,
     position_start_index: u64,
     position_end_index: u64,
+    #[serde(default)]
+    time: Option<chrono::NaiveTime>,
+    #[serde(default)]
+    flat_bonus: u64,
+    #[serde(default)]
+    streak: u64,
+    #[serde(default)]
+    streak_bonus: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChompletedChallengePeriod {
+impl CatcherPeriod {
+    fn breakdown(&self) -> CatchBreakdown {
+        CatchBreakdown {
+            bounty: self.bounty,
+            flat_bonus: self.flat_bonus,
+            streak: self.streak,
+            streak_bonus: self.streak_bonus,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChompletedChallengePeriod {
     title: String,
     description: String,
     zone: Option<u64>,
     points: u64,
-    photo: u64,
+    photo: Option<u64>,
+    #[serde(default)]
+    pictures: Vec<u64>, // uploaded with `UploadPeriodPictures`
     time: chrono::NaiveTime,
     position_start_index: u64,
     position_end_index: u64,
+    #[serde(default)]
+    id: Option<u64>, // id for ChallengeEntry collection in db
+    #[serde(default)]
+    player: Option<u64>, // id for PlayerEntry collection in db, the member credited
 }
 
 impl ChompletedChallengePeriod {
@@ -810,10 +1529,652 @@ impl TeamEntry {
             catcher_periods: Vec::new(),
             caught_periods: Vec::new(),
             trophy_periods: Vec::new(),
+            current_zone_id: None,
+            changed_at: 0,
+            archived_locations: 0,
+            location_archive: None,
+            generation_failed: None,
+            catch_streak: 0,
+            refresh_timer: None,
+            milestone_bonuses: Vec::new(),
+            stationary: false,
+        }
+    }
+
+    // Including the archived ones, which is what the period indices count
+    fn location_count(&self) -> u64 {
+        self.archived_locations + self.locations.len() as u64
+    }
+
+    // Moves the open challenge at `index` to the completed challenges, awards its points and
+    // generates new challenges from wherever it took the team. Location indices of the period
+    // count from the oldest location, since new locations are inserted at the front.
+    fn complete_challenge(
+        &mut self,
+        index: usize,
+        config: &Config,
+        context: &EngineContext,
+    ) -> Result<InOpenChallenge, commands::Error> {
+        let challenge = match self.challenges.get(index) {
+            None => return Err(commands::Error::NotFound),
+            Some(challenge) => challenge.clone(),
+        };
+        let now = context.clock.now();
+        if let (false, Some(completable_at)) =
+            (challenge.completable_by(now), challenge.completable_at())
+        {
+            return Err(commands::Error::ChallengeNotYetCompletable { completable_at });
+        }
+        let now = now.time();
+        self.catch_streak = 0;
+        self.completed_challenges.push(ChompletedChallengePeriod {
+            title: challenge.title.clone(),
+            description: challenge.description.clone(),
+            zone: challenge.zone,
+            points: challenge.points,
+            photo: None,
+            pictures: Vec::new(),
+            time: now,
+            position_start_index: self.last_period_end(),
+            position_end_index: self.location_count(),
+            id: challenge.id,
+            player: None,
+        });
+        self.points += challenge.points;
+        self.bounty += (challenge.points as f64 * config.bounty_percentage).round() as u64;
+        if challenge.zone.is_some() {
+            self.current_zone_id = challenge.zone;
+        }
+        self.challenges.remove(index);
+        let period = generation_period(config, now);
+        if config.keep_open_challenges {
+            let keep = self.challenges.clone();
+            self.generate_challenges_keeping(config, period, context, &[], keep);
+        } else {
+            self.generate_challenges(config, period, context);
+        }
+        Ok(challenge)
+    }
+
+    // Restarts the countdown to the team's next challenge refresh. Catchers have no challenges,
+    // so their countdown is only stopped.
+    fn reset_refresh_timer(
+        &mut self,
+        index: usize,
+        session_id: u64,
+        config: &Config,
+        timer_tracker: &mut TimerTracker,
+        now: chrono::DateTime<chrono::Local>,
+    ) -> Vec<RuntimeRequest> {
+        let mut requests: Vec<RuntimeRequest> = self
+            .refresh_timer
+            .take()
+            .iter()
+            .map(|t| t.cancel_request())
+            .collect();
+        if let (Some(minutes), TeamRole::Runner) = (config.challenge_refresh_minutes, self.role) {
+            let timer = TimerHook::new(
+                timer_tracker,
+                now + chrono::TimeDelta::minutes(minutes as i64),
+                EngineCommand {
+                    session: Some(session_id),
+                    action: EngineAction::RefreshChallenges { team: index },
+                },
+                format!("challenge refresh of team {}", self.name),
+            );
+            requests.push(timer.create_request(now));
+            self.refresh_timer = Some(timer);
+        }
+        requests
+    }
+
+    // The catcher collects the bounty of the caught team and the two swap roles. The new runners
+    // carry on from where the caught team was.
+    fn have_caught(
+        &mut self,
+        index: usize,
+        caught: &mut TeamEntry,
+        caught_index: usize,
+        config: &Config,
+        context: &EngineContext,
+    ) {
+        let bounty = caught.bounty;
+        self.catch_streak += 1;
+        let flat_bonus = config.catch_flat_bonus;
+        let streak_multiplier = config
+            .catch_streak_multiplier
+            .powi(self.catch_streak.saturating_sub(1).min(i32::MAX as u64) as i32);
+        let streak_bonus = (flat_bonus as f64 * (streak_multiplier - 1.0)).max(0.0) as u64;
+        self.catcher_periods.push(CatcherPeriod {
+            caught_team: caught_index as u64,
+            bounty,
+            position_start_index: self.last_period_end(),
+            position_end_index: self.location_count(),
+            time: Some(context.clock.now().time()),
+            flat_bonus,
+            streak: self.catch_streak,
+            streak_bonus,
+        });
+        caught.caught_periods.push(CaughtPeriod {
+            catcher_team: index as u64,
+            bounty,
+            position_start_index: caught.last_period_end(),
+            position_end_index: caught.location_count(),
+        });
+        self.points += bounty + flat_bonus + streak_bonus;
+        caught.bounty = 0;
+
+        self.role = TeamRole::Runner;
+        caught.role = TeamRole::Catcher;
+        caught.challenges.clear();
+        if caught.current_zone_id.is_some() {
+            self.current_zone_id = caught.current_zone_id;
+        }
+        let period = generation_period(config, context.clock.now().time());
+        self.generate_challenges(config, period, context);
+    }
+
+    // The last location before the end of a period, `end` counting from the oldest location.
+    // Archived locations count as missing.
+    fn location_before(&self, end: u64) -> Option<(f64, f64, NaiveTime)> {
+        let len = self.location_count() as usize;
+        match end as usize {
+            0 => None,
+            end if end > len => None,
+            end => self.locations.get(len - end).copied(),
+        }
+    }
+
+    // Catches from before their time was recorded fall back to the last location's time
+    fn catch_event(&self, index: usize, period: &CatcherPeriod) -> Option<Event> {
+        let location = self.location_before(period.position_end_index);
+        Some(Event {
+            time: period.time.or(location.map(|l| l.2))?,
+            location: location.map(|l| (l.0, l.1)),
+            kind: EventKind::Catch {
+                catcher: index,
+                caught: period.caught_team as usize,
+                bounty: period.bounty,
+            },
+        })
+    }
+
+    fn complete_event(&self, index: usize, period: &ChompletedChallengePeriod) -> Event {
+        Event {
+            time: period.time,
+            location: self
+                .location_before(period.position_end_index)
+                .map(|l| (l.0, l.1)),
+            kind: EventKind::Complete {
+                team: index,
+                title: period.title.clone(),
+                points: period.points,
+            },
+        }
+    }
+
+    // Where the location history of the next period starts
+    fn last_period_end(&self) -> u64 {
+        self.period_ranges().last().map(|r| r.1).unwrap_or(0)
+    }
+
+    // Location index ranges of all periods, in the order they happened
+    fn period_ranges(&self) -> Vec<(u64, u64)> {
+        let completed = self
+            .completed_challenges
+            .iter()
+            .map(|p| (p.position_start_index, p.position_end_index));
+        let catcher = self
+            .catcher_periods
+            .iter()
+            .map(|p| (p.position_start_index, p.position_end_index));
+        let caught = self
+            .caught_periods
+            .iter()
+            .map(|p| (p.position_start_index, p.position_end_index));
+        let trophy = self
+            .trophy_periods
+            .iter()
+            .map(|p| (p.position_start_index, p.position_end_index));
+        let mut ranges: Vec<(u64, u64)> = completed
+            .chain(catcher)
+            .chain(caught)
+            .chain(trophy)
+            .collect();
+        ranges.sort_unstable_by_key(|&(start, end)| (end, start));
+        ranges
+    }
+
+    // The locations of a period, oldest first. Ranges reaching past the recorded locations are
+    // cut short instead of panicking, since the indices are only bookkeeping.
+    fn period_path(&self, period: usize) -> Option<Vec<MinimalLocation>> {
+        let (start, end) = *self.period_ranges().get(period)?;
+        Some(self.path_between(start, end))
+    }
+
+    // The locations with indices from `start` up to `end`, oldest first
+    fn path_between(&self, start: u64, end: u64) -> Vec<MinimalLocation> {
+        let len = self.location_count() as usize;
+        let archived = self.archived_locations as usize;
+        let start = (start as usize).min(len);
+        let end = (end as usize).clamp(start, len);
+        self.locations
+            .iter()
+            .rev()
+            .skip(start.saturating_sub(archived))
+            .take(end.saturating_sub(start.max(archived)))
+            .map(|&(latitude, longitude, time)| MinimalLocation {
+                latitude,
+                longitude,
+                time,
+            })
+            .collect()
+    }
+
+    // Minutes between consecutive completions in different zones, as (from zone, to zone,
+    // minutes). If the zones have boundaries, the time spent in them is left out, the trip
+    // starting with the last location in the first zone and ending with the first one in the
+    // second. Otherwise it goes from completion to completion, which also counts the time spent
+    // on the second challenge, so those suggestions come out on the long side.
+    fn travel_times(&self, zones: &[DBEntry<ZoneEntry>]) -> Vec<(u64, u64, u64)> {
+        let inside = |zone: u64, l: &MinimalLocation| {
+            zones.iter().any(|z| {
+                z.id == zone && z.contents.contains((l.latitude, l.longitude)) == Some(true)
+            })
+        };
+        self.completed_challenges
+            .windows(2)
+            .filter_map(|pair| {
+                let (from, to) = (pair[0].zone?, pair[1].zone?);
+                if from == to {
+                    return None;
+                }
+                let path =
+                    self.path_between(pair[1].position_start_index, pair[1].position_end_index);
+                let left = path.iter().rposition(|l| inside(from, l));
+                let arrived = path
+                    .iter()
+                    .skip(left.unwrap_or(0))
+                    .find(|l| inside(to, l))
+                    .map_or(pair[1].time, |l| l.time);
+                let left = left.map_or(pair[0].time, |i| path[i].time);
+                // a trip past midnight
+                let minutes = (arrived - left).num_minutes().rem_euclid(24 * 60);
+                Some((from, to, minutes as u64))
+            })
+            .collect()
+    }
+
+    fn player_contributions(&self) -> Vec<PlayerContribution> {
+        let mut contributions: Vec<PlayerContribution> = Vec::new();
+        for period in &self.completed_challenges {
+            match contributions.iter_mut().find(|c| c.player == period.player) {
+                Some(contribution) => {
+                    contribution.challenges += 1;
+                    contribution.points += period.points;
+                }
+                None => contributions.push(PlayerContribution {
+                    player: period.player,
+                    challenges: 1,
+                    points: period.points,
+                }),
+            }
+        }
+        contributions
+    }
+
+    fn catch_stats(&self, index: usize) -> TeamCatchStats {
+        TeamCatchStats {
+            team: index,
+            catches: self.catcher_periods.len() as u64,
+            times_caught: self.caught_periods.len() as u64,
+            bounty_collected: self.catcher_periods.iter().map(|p| p.bounty).sum(),
+            bounty_lost: self.caught_periods.iter().map(|p| p.bounty).sum(),
+            catch_bonuses: self
+                .catcher_periods
+                .iter()
+                .map(|p| p.flat_bonus + p.streak_bonus)
+                .sum(),
+        }
+    }
+
+    fn period_summary(
+        &self,
+        context: &EngineContext,
+        index: usize,
+        points_before: u64,
+    ) -> PeriodSummary {
+        PeriodSummary {
+            team: index,
+            points_gained: self.points.saturating_sub(points_before),
+            points: self.points,
+            bounty: self.bounty,
+            challenges: self
+                .challenges
+                .iter()
+                .map(|c| c.to_sendable(context.clock))
+                .collect(),
+            catch: None,
+            milestones: Vec::new(),
+        }
+    }
+
+    // Replaces the open challenges with `config.num_challenges` new ones fitting the period.
+    fn generate_challenges(
+        &mut self,
+        config: &Config,
+        period: GenerationPeriod,
+        context: &EngineContext,
+    ) {
+        self.generate_challenges_avoiding(config, period, context, &[]);
+    }
+
+    // Like `generate_challenges`, but prefers challenges that don't share a challenge or zone
+    // with `avoid`, e.g. the challenges other teams got at the same time.
+    fn generate_challenges_avoiding(
+        &mut self,
+        config: &Config,
+        period: GenerationPeriod,
+        context: &EngineContext,
+        avoid: &[InOpenChallenge],
+    ) {
+        self.generate_challenges_keeping(config, period, context, avoid, Vec::new());
+    }
+
+    // Like `generate_challenges_avoiding`, but only fills up the slots next to `keep`
+    fn generate_challenges_keeping(
+        &mut self,
+        config: &Config,
+        period: GenerationPeriod,
+        context: &EngineContext,
+        avoid: &[InOpenChallenge],
+        keep: Vec<InOpenChallenge>,
+    ) {
+        // hand-picked challenges stay as they are, completed ones aren't replaced
+        if config.manual_challenges {
+            return;
+        }
+        let mut challenges = keep;
+        for _ in challenges.len()..config.num_challenges as usize {
+            match self.select_challenge(config, period, &challenges, avoid, context) {
+                Some(challenge) => challenges.push(challenge),
+                None => break,
+            }
+        }
+        if challenges.len() < config.num_challenges as usize {
+            eprintln!(
+                "Engine: Couldn't find enough challenges for team {}, keeping the previous ones",
+                self.name
+            );
+            for previous in &self.challenges {
+                if challenges.len() >= config.num_challenges as usize {
+                    break;
+                }
+                if previous.id.is_none() || !challenges.iter().any(|c| c.id == previous.id) {
+                    challenges.push(previous.clone());
+                }
+            }
+            if challenges.is_empty() {
+                challenges.push(InOpenChallenge::placeholder(config));
+            }
+            self.generation_failed = Some(period);
+        } else {
+            self.generation_failed = None;
+        }
+        self.challenges = challenges;
+    }
+
+    fn select_challenge(
+        &self,
+        config: &Config,
+        period: GenerationPeriod,
+        selected: &[InOpenChallenge],
+        avoid: &[InOpenChallenge],
+        context: &EngineContext,
+    ) -> Option<InOpenChallenge> {
+        let (_, candidates) = self.candidates(config, period, selected, avoid, context)?;
+        let entry = candidates.choose(&mut thread_rng())?;
+        Some(entry.contents.challenge(
+            entry.id,
+            config,
+            period == GenerationPeriod::Specific,
+            self.current_zone_id,
+            context,
+            self.language(config, context).as_deref(),
+        ))
+    }
+
+    // Candidates are narrowed down by filters. If nothing survives, the configured fallbacks are
+    // tried one by one and if even that fails, there are no candidates. Also returns the tier
+    // the candidates come from, 0 being the strict filter and i the i-th fallback.
+    fn candidates<'c>(
+        &self,
+        config: &Config,
+        period: GenerationPeriod,
+        selected: &[InOpenChallenge],
+        avoid: &[InOpenChallenge],
+        context: &EngineContext<'c>,
+    ) -> Option<(usize, Vec<&'c DBEntry<ChallengeEntry>>)> {
+        let is_used = |id| {
+            self.challenges.iter().any(|c| c.id == Some(id))
+                || self.completed_challenges.iter().any(|c| c.id == Some(id))
+        };
+        // zones with unknown distances don't count as too close
+        let is_spread = |c: &DBEntry<ChallengeEntry>| match config.min_challenge_zone_distance {
+            None => true,
+            Some(min) => selected.iter().filter_map(|s| s.zone).all(|other| {
+                c.contents.zone.iter().all(|&z| {
+                    zone_distance(context.zone_entries, z, other).is_none_or(|d| d >= min)
+                })
+            }),
+        };
+        let centre = nearest_centre(config, context.zone_entries, self.current_zone_id);
+        let fits = |c: &DBEntry<ChallengeEntry>| {
+            !is_used(c.id)
+                && c.contents
+                    .fits_period(period, config, centre, context.zone_entries)
+        };
+        let is_uncrowded = |c: &DBEntry<ChallengeEntry>| {
+            !avoid
+                .iter()
+                .any(|a| a.id == Some(c.id) || a.zone.is_some_and(|z| c.contents.zone.contains(&z)))
+        };
+        let strict: ChallengeFilter = &|c| fits(c) && is_spread(c) && is_uncrowded(c);
+        let crowded: ChallengeFilter = &|c| fits(c) && is_spread(c);
+        let unspread: ChallengeFilter = &|c| fits(c);
+        let out_of_period: ChallengeFilter = &|c| !is_used(c.id);
+        let repeated: ChallengeFilter = &|_| true;
+        let tiers =
+            std::iter::once(strict).chain(config.challenge_fallbacks.iter().map(|fallback| {
+                match fallback {
+                    ChallengeFallback::Crowded => crowded,
+                    ChallengeFallback::Unspread => unspread,
+                    ChallengeFallback::OutOfPeriod => out_of_period,
+                    ChallengeFallback::Repeated => repeated,
+                }
+            }));
+        // prerequisites hold in every tier, or story chains would get out of order
+        let is_unlocked = |c: &DBEntry<ChallengeEntry>| {
+            c.contents
+                .requires_completed
+                .iter()
+                .all(|&id| self.completed_challenges.iter().any(|p| p.id == Some(id)))
+        };
+        let selected_groups: Vec<u64> = selected
+            .iter()
+            .filter_map(|s| s.id)
+            .filter_map(|id| context.challenge_entries.iter().find(|c| c.id == id))
+            .filter_map(|c| c.contents.exclusive_group)
+            .collect();
+        let is_exclusive = |c: &DBEntry<ChallengeEntry>| {
+            c.contents
+                .exclusive_group
+                .is_none_or(|group| !selected_groups.contains(&group))
+        };
+        for (tier, filter) in tiers.enumerate() {
+            let candidates: Vec<_> = context
+                .challenge_entries
+                .iter()
+                .filter(|c| c.contents.status == ChallengeStatus::Approved)
+                .filter(|c| is_unlocked(c))
+                .filter(|c| c.contents.allowed_by_set_rules(config))
+                .filter(|c| c.contents.is_rideable(context.zone_entries))
+                .filter(|c| {
+                    c.contents
+                        .within_endgame_radius(period, config, centre, context.zone_entries)
+                })
+                .filter(|c| !selected.iter().any(|s| s.id == Some(c.id)))
+                .filter(|c| is_exclusive(c))
+                .filter(|c| filter(c))
+                .collect();
+            if !candidates.is_empty() {
+                return Some((tier, candidates));
+            }
+        }
+        None
+    }
+
+    // The language most players of the team speak, players without a preference count as
+    // speaking the default language. Ties go to the language of the player listed first.
+    fn language(&self, config: &Config, context: &EngineContext) -> Option<String> {
+        let languages: Vec<Option<&String>> = self
+            .players
+            .iter()
+            .map(|id| {
+                context
+                    .player_entries
+                    .iter()
+                    .find(|p| p.id == *id)
+                    .and_then(|p| p.contents.language.as_ref())
+                    .or(config.default_language.as_ref())
+            })
+            .collect();
+        let count = |language| languages.iter().filter(|l| **l == language).count();
+        languages
+            .iter()
+            .copied()
+            .reduce(|best, l| if count(l) > count(best) { l } else { best })
+            .flatten()
+            .cloned()
+    }
+
+    // To be sent whenever new challenges were generated: the new challenges themselves and the
+    // catcher messages of all traps among them.
+    fn generation_broadcasts(
+        &self,
+        context: &EngineContext,
+        session: u64,
+        index: usize,
+    ) -> Vec<RuntimeRequest> {
+        let changed = RuntimeRequest::Broadcast(BroadcastAction::ChallengesChanged {
+            session,
+            team: index,
+            challenges: self
+                .challenges
+                .iter()
+                .map(|c| c.to_sendable(context.clock))
+                .collect(),
+        });
+        let traps = self.challenges.iter().filter_map(|c| match &c.action {
+            Some(ChallengeAction::Trap {
+                completable_after: _,
+                catcher_message: Some(message),
+            }) => Some(RuntimeRequest::Broadcast(BroadcastAction::TrapBaited {
+                session,
+                team: index,
+                message: message.clone(),
+            })),
+            _ => None,
+        });
+        let failed = self.generation_failed.map(|category| {
+            RuntimeRequest::Broadcast(BroadcastAction::GenerationFailed {
+                session,
+                team: index,
+                category,
+            })
+        });
+        std::iter::once(changed)
+            .chain(traps)
+            .chain(failed)
+            .collect()
+    }
+
+    // The acceptance check every location goes through. Rejected locations return the speed
+    // the team would have needed to get there. Exact repeats of the latest location are
+    // accepted, but not stored twice.
+    fn add_location(
+        &mut self,
+        location: (f64, f64),
+        time: NaiveTime,
+        config: &Config,
+        zones: &[DBEntry<ZoneEntry>],
+    ) -> Result<(), f64> {
+        if self.locations.first() == Some(&(location.0, location.1, time)) {
+            return Ok(());
+        }
+        if !config.accept_all_locations {
+            if let Some(implied_speed) = self.implausible_speed(location, time, config) {
+                return Err(implied_speed);
+            }
+        }
+        self.locations.insert(0, (location.0, location.1, time));
+        if config.zone_from_location {
+            if let Some(zone) = nearest_zone(zones, location) {
+                self.current_zone_id = Some(zone.id);
+            }
+        }
+        Ok(())
+    }
+
+    // The speed in km/h the team would have needed to get from its latest location to the new
+    // one, if that is implausibly fast
+    fn implausible_speed(
+        &self,
+        location: (f64, f64),
+        time: NaiveTime,
+        config: &Config,
+    ) -> Option<f64> {
+        let &(lat, lon, last_time) = self.locations.first()?;
+        let distance = location_distance((lat, lon), location);
+        // a jump past midnight
+        let seconds = (time - last_time)
+            .num_seconds()
+            .rem_euclid(24 * 60 * 60)
+            .max(1) as f64;
+        let speed = distance / seconds * 3.6;
+        (distance >= config.min_suspicious_jump && speed > config.max_plausible_speed)
+            .then_some(speed)
+    }
+
+    // For how many minutes the team has stayed within `stationary_radius` of its latest location.
+    // Locations are stored newest first.
+    fn stationary_minutes(&self, config: &Config) -> u64 {
+        match self.locations.first() {
+            None => 0,
+            Some(&(lat, lon, latest)) => self
+                .locations
+                .iter()
+                .take_while(|l| {
+                    location_distance((lat, lon), (l.0, l.1)) <= config.stationary_radius
+                })
+                .last()
+                .map(|l| (latest - l.2).num_minutes().max(0) as u64)
+                .unwrap_or(0),
         }
     }
 
-    fn to_sendable(&self, player_entries: &[DBEntry<PlayerEntry>], index: usize) -> truinlag::Team {
+    fn to_sendable(&self, context: &EngineContext, index: usize) -> truinlag::Team {
+        self.to_partial_sendable(context, index, true, true)
+    }
+
+    // Leaves out the completed challenges and location unless asked for, for `GetStatePaged`
+    fn to_partial_sendable(
+        &self,
+        context: &EngineContext,
+        index: usize,
+        include_completed: bool,
+        include_locations: bool,
+    ) -> truinlag::Team {
         truinlag::Team {
             colour: self.colour,
             role: self.role,
@@ -825,7 +2186,8 @@ impl TeamEntry {
                 .players
                 .iter()
                 .map(|p| {
-                    player_entries
+                    context
+                        .player_entries
                         .iter()
                         .find(|pp| &pp.id == p)
                         .expect("PlayerEntry not found in db while making team sendable")
@@ -833,17 +2195,31 @@ impl TeamEntry {
                         .to_sendable(*p)
                 })
                 .collect(),
-            challenges: self.challenges.iter().map(|c| c.to_sendable()).collect(),
-            completed_challenges: self
-                .completed_challenges
+            challenges: self
+                .challenges
                 .iter()
-                .map(|c| c.to_sendable())
+                .map(|c| c.to_sendable(context.clock))
                 .collect(),
-            location: if !self.locations.is_empty() {
+            completed_challenges: if include_completed {
+                self.completed_challenges
+                    .iter()
+                    .map(|c| c.to_sendable())
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            location: if include_locations && !self.locations.is_empty() {
                 Some((self.locations[0].0, self.locations[0].1))
             } else {
                 None
             },
+            current_zone: self.current_zone_id.and_then(|id| {
+                context
+                    .zone_entries
+                    .iter()
+                    .find(|z| z.id == id)
+                    .map(|z| z.contents.zone)
+            }),
         }
     }
 }
@@ -864,28 +2240,51 @@ pub struct InOpenChallenge {
     points: u64,
     action: Option<ChallengeAction>,
     zone: Option<u64>, // id for ZoneEntry collection in db
+    #[serde(default)]
+    id: Option<u64>, // id for ChallengeEntry collection in db
+    #[serde(default)]
+    breakdown: Option<PointBreakdown>, // only kept if the config asks for it
 }
 
 impl InOpenChallenge {
-    #[allow(dead_code)]
-    fn completable(&self) -> bool {
+    // The default challenge from the config, for teams nothing else could be found for
+    fn placeholder(config: &Config) -> Self {
+        InOpenChallenge {
+            title: config.default_challenge_title.clone(),
+            description: config.default_challenge_description.clone(),
+            points: 0,
+            action: None,
+            zone: None,
+            id: None,
+            breakdown: None,
+        }
+    }
+
+    fn completable_at(&self) -> Option<chrono::DateTime<chrono::Local>> {
         match &self.action {
-            None => true,
+            None => None,
             Some(action) => match action {
-                ChallengeAction::UncompletableMinutes(t) => &chrono::Local::now() > t,
+                ChallengeAction::UncompletableMinutes(t) => Some(*t),
                 ChallengeAction::Trap {
                     completable_after: t,
                     catcher_message: _,
-                } => &chrono::Local::now() > t,
+                } => Some(*t),
             },
         }
     }
 
-    pub fn to_sendable(&self) -> truinlag::Challenge {
+    fn completable_by(&self, now: chrono::DateTime<chrono::Local>) -> bool {
+        self.completable_at().is_none_or(|t| now > t)
+    }
+
+    fn to_sendable(&self, clock: &dyn Clock) -> truinlag::Challenge {
         truinlag::Challenge {
             title: self.title.clone(),
             points: self.points,
             description: self.description.clone(),
+            completable: self.completable_by(clock.now()),
+            completable_at: self.completable_at(),
+            breakdown: self.breakdown.clone(),
         }
     }
 }
@@ -896,14 +2295,116 @@ impl PartialEq for InOpenChallenge {
     }
 }
 
+// A timer that is stored in the db, so it can be recreated when the engine restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimerHook {
+    id: u64,
+    end_time: chrono::DateTime<chrono::Local>,
+    command: EngineCommand,
+    description: String,
+}
+
+impl TimerHook {
+    fn new(
+        tracker: &mut TimerTracker,
+        end_time: chrono::DateTime<chrono::Local>,
+        command: EngineCommand,
+        description: impl Into<String>,
+    ) -> Self {
+        TimerHook {
+            id: tracker.next_id(),
+            end_time,
+            command,
+            description: description.into(),
+        }
+    }
+
+    // Timers that should already have fired, e.g. because the engine was down, fire right away.
+    fn create_request(&self, now: chrono::DateTime<chrono::Local>) -> RuntimeRequest {
+        let duration = match (self.end_time - now).to_std() {
+            Ok(duration) => duration,
+            Err(_) => {
+                println!(
+                    "Engine: timer {} ({}) should have fired at {}, firing it now",
+                    self.id, self.description, self.end_time
+                );
+                Duration::ZERO
+            }
+        };
+        RuntimeRequest::CreateTimer {
+            id: self.id,
+            duration,
+            payload: InternEngineCommand::Command(self.command.clone()),
+        }
+    }
+
+    fn cancel_request(&self) -> RuntimeRequest {
+        RuntimeRequest::CancelTimer(self.id)
+    }
+
+    fn to_sendable(&self, session: u64) -> Timer {
+        Timer {
+            id: self.id,
+            session,
+            end_time: self.end_time,
+            description: self.description.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InGame {
     name: String,
     date: chrono::NaiveDate,
     mode: Mode,
+    start_time: chrono::DateTime<chrono::Local>,
+    timer: Option<TimerHook>, // stops the game at the configured end time
+    #[serde(default)]
+    backstop: Option<TimerHook>, // stops the game after `max_game_hours` in case the end time is off
+    #[serde(default)]
+    milestones_reached: Vec<Milestone>,
 }
 
 impl InGame {
+    // Awards the bonuses of the configured milestones among `reached` that no team has reached yet
+    fn reach_milestones(
+        &mut self,
+        reached: &[Milestone],
+        team: &mut TeamEntry,
+        config: &Config,
+        time: NaiveTime,
+    ) -> Vec<(Milestone, u64)> {
+        let mut awarded = Vec::new();
+        for &(milestone, points) in &config.milestone_bonuses {
+            if reached.contains(&milestone) && !self.milestones_reached.contains(&milestone) {
+                self.milestones_reached.push(milestone);
+                team.points += points;
+                team.milestone_bonuses.push(MilestoneBonus {
+                    milestone,
+                    points,
+                    time,
+                });
+                awarded.push((milestone, points));
+            }
+        }
+        awarded
+    }
+
+    fn milestone_broadcasts(
+        session_id: u64,
+        team: usize,
+        awarded: &[(Milestone, u64)],
+    ) -> impl Iterator<Item = RuntimeRequest> + '_ {
+        awarded.iter().map(move |&(milestone, points)| {
+            RuntimeRequest::Broadcast(BroadcastAction::MilestoneReached {
+                session: session_id,
+                team,
+                milestone,
+                points,
+            })
+        })
+    }
+
     pub fn to_sendable(&self) -> truinlag::Game {
         truinlag::Game {
             name: self.name.clone(),
@@ -1087,11 +2588,20 @@ struct Session {
     discord_game_channel: Option<u64>,
     discord_admin_channel: Option<u64>,
     game: Option<InGame>,
+    #[serde(default)]
+    score_snapshots: Vec<ScoreSnapshot>,
+    #[serde(default)]
+    version: u64, // bumped by every applied mutation, see `GetStateDiff`
+    #[serde(default)]
+    uploads: HashMap<u64, Vec<u64>>, // upload token -> picture ids
+    #[serde(default)]
+    game_ended: bool, // whether the last game was stopped, for telling the app why there's none
 }
 
 impl Session {
-    fn config(&self) -> Config {
-        let mut cfg = Config::default();
+    // The session's own settings on top of the engine wide defaults
+    fn config(&self, defaults: &Config) -> Config {
+        let mut cfg = defaults.clone();
         cfg.apply_some(self.config.clone());
         cfg
     }
@@ -1106,40 +2616,409 @@ impl Session {
             discord_game_channel: None,
             discord_admin_channel: None,
             game: None,
+            score_snapshots: Vec::new(),
+            version: 0,
+            uploads: HashMap::new(),
+            game_ended: false,
         }
     }
 
-    fn to_sendable(&self, id: u64) -> GameSession {
-        GameSession {
-            name: self.name.clone(),
-            mode: self.mode,
-            id,
+    // Games starting in the specific period hand out specific challenges only. If there aren't
+    // enough of them near where a team starts, the team would open with placeholders. Without
+    // generation, every runner team needs challenges assigned before the start.
+    fn missing_start_challenges(&self, config: &Config, context: &EngineContext) -> Option<String> {
+        if config.manual_challenges {
+            return self
+                .teams
+                .iter()
+                .find(|t| t.role == TeamRole::Runner && t.challenges.is_empty())
+                .map(|t| format!("team {} has no challenges assigned", t.name));
         }
+        if generation_period(config, context.clock.now().time()) != GenerationPeriod::Specific {
+            return None;
+        }
+        for team in self.teams.iter().filter(|t| t.role == TeamRole::Runner) {
+            let start = team
+                .current_zone_id
+                .or(nearest_centre(config, context.zone_entries, None));
+            let reachable = |c: &ChallengeEntry| match (config.max_start_challenge_distance, start)
+            {
+                (Some(max), Some(start)) => {
+                    c.random_place.is_some()
+                        || matches!(c.kind, ChallengeType::Zoneable)
+                        || c.is_within(start, max, context.zone_entries)
+                }
+                _ => true,
+            };
+            let available = context
+                .challenge_entries
+                .iter()
+                .map(|c| &c.contents)
+                .filter(|c| c.status == ChallengeStatus::Approved)
+                .filter(|c| c.is_specific() && c.requires_completed.is_empty())
+                .filter(|c| reachable(c))
+                .count() as u64;
+            if available < config.num_challenges {
+                return Some(format!(
+                    "only {} specific challenges are available near the start of team {}, {} are needed",
+                    available, team.name, config.num_challenges
+                ));
+            }
+        }
+        None
     }
 
-    fn vroom(
+    fn colour_palette(&self, global_config: &Config) -> ColourPalette {
+        let (used, available) = self
+            .config(global_config)
+            .team_colours
+            .into_iter()
+            .partition(|&c| self.teams.iter().any(|t| t.colour == c));
+        ColourPalette { used, available }
+    }
+
+    fn readiness(&self, player_entries: &[DBEntry<PlayerEntry>]) -> Vec<TeamReadiness> {
+        self.teams
+            .iter()
+            .enumerate()
+            .map(|(index, team)| {
+                let (ready, unready) = team.players.iter().partition(|&&id| {
+                    player_entries
+                        .iter()
+                        .any(|p| p.id == id && p.contents.ready)
+                });
+                TeamReadiness {
+                    team: index,
+                    ready,
+                    unready,
+                }
+            })
+            .collect()
+    }
+
+    // Why a command that needs a running game can't be run
+    fn no_game_error(&self) -> commands::Error {
+        if self.game_ended {
+            commands::Error::GameEnded
+        } else {
+            commands::Error::GameNotStarted
+        }
+    }
+
+    // `pictures` are the ids of the already stored pictures that go with the completion
+    fn complete(
         &mut self,
-        command: EngineAction,
         session_id: u64,
-        player_entries: &[DBEntry<PlayerEntry>],
+        completer: usize,
+        completed: usize,
+        pictures: Vec<u64>,
+        context: &EngineContext,
+        timer_tracker: &mut TimerTracker,
     ) -> InternEngineResponsePackage {
         use commands::Error::*;
         use BroadcastAction::*;
-        use EngineAction::*;
         use ResponseAction::*;
-        match command {
-            AddChallengeToTeam { team, challenge } => match self.teams.get_mut(team) {
-                // THIS METHOD SHOULD BE TEMPORARY AND EXISTS ONLY FOR TESTING PURPOSES
-                None => Error(NotFound).into(),
-                Some(team) => {
-                    team.challenges.push(InOpenChallenge {
-                        title: challenge.title,
-                        description: challenge.description,
-                        points: challenge.points,
-                        action: None,
-                        zone: None,
-                    });
-                    Success.into()
+        if self.game.is_none() {
+            return Error(self.no_game_error()).into();
+        }
+        let config = self.config(context.config);
+        let completions: u64 = self
+            .teams
+            .iter()
+            .map(|t| t.completed_challenges.len() as u64)
+            .sum();
+        let Some(team) = self.teams.get_mut(completer) else {
+            return Error(NotFound).into();
+        };
+        let requires_photo = team
+            .challenges
+            .get(completed)
+            .and_then(|c| c.id)
+            .and_then(|id| context.challenge_entries.iter().find(|e| e.id == id))
+            .is_some_and(|e| e.contents.requires_photo);
+        if requires_photo && pictures.is_empty() {
+            return Error(PhotoRequired).into();
+        }
+        if config.require_location_for_completion {
+            let zone = team
+                .challenges
+                .get(completed)
+                .and_then(|c| c.zone)
+                .and_then(|id| context.zone_entries.iter().find(|z| z.id == id));
+            // a team that never sent a location isn't near anything
+            let is_near = match (zone, team.locations.first()) {
+                (Some(zone), Some(l)) => {
+                    zone.contents.is_near((l.0, l.1), config.completion_radius)
+                }
+                (Some(zone), None) => zone.contents.has_geography().then_some(false),
+                (None, _) => None,
+            };
+            if is_near == Some(false) {
+                return Error(NotAtChallengeLocation).into();
+            }
+        }
+        let points_before = team.points;
+        match team.complete_challenge(completed, &config, context) {
+            Err(err) => Error(err).into(),
+            Ok(challenge) => {
+                let reached = [
+                    Milestone::FirstCompletion,
+                    Milestone::Completions(completions + 1),
+                ];
+                let milestones = self
+                    .game
+                    .as_mut()
+                    .map(|game| {
+                        game.reach_milestones(&reached, team, &config, context.clock.now().time())
+                    })
+                    .unwrap_or_default();
+                let mut requests = team.generation_broadcasts(context, session_id, completer);
+                requests.extend(InGame::milestone_broadcasts(
+                    session_id,
+                    completer,
+                    &milestones,
+                ));
+                requests.append(&mut team.reset_refresh_timer(
+                    completer,
+                    session_id,
+                    &config,
+                    timer_tracker,
+                    context.clock.now(),
+                ));
+                if let Some(period) = team.completed_challenges.last_mut() {
+                    period.pictures = pictures;
+                }
+                if let Some(period) = team.completed_challenges.last() {
+                    requests.push(RuntimeRequest::Broadcast(NewEvent {
+                        session: session_id,
+                        event: team.complete_event(completer, period),
+                    }));
+                }
+                InternEngineResponsePackage {
+                    response: EngineResponse {
+                        response_action: SendPeriodSummary(PeriodSummary {
+                            milestones,
+                            ..team.period_summary(context, completer, points_before)
+                        }),
+                        broadcast_action: Some(Completed {
+                            completer: team.to_sendable(context, completer),
+                            completed: challenge.to_sendable(context.clock),
+                        }),
+                    }
+                    .into(),
+                    runtime_requests: Some(requests),
+                }
+            }
+        }
+    }
+
+    // The teams a mutating command can change, `None` meaning possibly all of them. This has to
+    // be determined before the command is applied, e.g. to know which team a player left.
+    fn touched_teams(&self, action: &EngineAction) -> Option<Vec<usize>> {
+        use EngineAction::*;
+        let team_of = |player: &u64| self.teams.iter().position(|t| t.players.contains(player));
+        match action {
+            Catch { catcher, caught } => Some(vec![*catcher, *caught]),
+            UploadPeriodPictures { team, .. } => Some(vec![*team]),
+            Complete {
+                completer,
+                completed: _,
+                pictures: _,
+                player: _,
+            } => Some(vec![*completer]),
+            SendLocation {
+                player,
+                location: _,
+            } => Some(team_of(player).into_iter().collect()),
+            AssignPlayerToTeam { player, team } => {
+                Some(team_of(player).into_iter().chain(*team).collect())
+            }
+            MakeTeamCatcher(team)
+            | MakeTeamRunner(team)
+            | AddChallengeToTeam { team, challenge: _ }
+            | RefreshChallenges { team }
+            | RegenerateChallengesExcept {
+                team,
+                keep_indices: _,
+            }
+            | RenameTeam { team, new_name: _ } => Some(vec![*team]),
+            AddTeam {
+                name: _,
+                discord_channel: _,
+                colour: _,
+            } => Some(vec![self.teams.len()]),
+            SetGameEndTime { new_time: _ }
+            | SetSessionMode { mode: _ }
+            | SnapshotScores
+            | CancelTimer { id: _ } => Some(Vec::new()),
+            _ => None,
+        }
+    }
+
+    fn mark_changed(&mut self, teams: Option<Vec<usize>>) {
+        self.version += 1;
+        let version = self.version;
+        match teams {
+            Some(teams) => {
+                for index in teams {
+                    if let Some(team) = self.teams.get_mut(index) {
+                        team.changed_at = version;
+                    }
+                }
+            }
+            None => self.teams.iter_mut().for_each(|t| t.changed_at = version),
+        }
+    }
+
+    fn to_sendable(&self, id: u64) -> GameSession {
+        GameSession {
+            name: self.name.clone(),
+            mode: self.mode,
+            id,
+        }
+    }
+
+    fn to_details(&self, id: u64, player_entries: &[DBEntry<PlayerEntry>]) -> SessionDetails {
+        SessionDetails {
+            id,
+            name: self.name.clone(),
+            mode: self.mode,
+            game_running: self.game.is_some(),
+            start_time: self.game.as_ref().map(|g| g.start_time),
+            team_count: self.teams.len(),
+            player_count: player_entries
+                .iter()
+                .filter(|p| p.contents.session == Some(id))
+                .count(),
+        }
+    }
+
+    fn team_scores(&self) -> Vec<TeamScore> {
+        self.teams
+            .iter()
+            .enumerate()
+            .map(|(index, t)| TeamScore {
+                team: index,
+                name: t.name.clone(),
+                points: t.points,
+                bounty: t.bounty,
+                role: t.role,
+            })
+            .collect()
+    }
+
+    fn summary(&self, session_id: u64) -> GameSummary {
+        let mut standings = self.team_scores();
+        standings.sort_by_key(|s| std::cmp::Reverse(s.points));
+        GameSummary {
+            session: session_id,
+            winner: standings.first().map(|s| s.team),
+            standings,
+            catches: self.teams.iter().map(|t| t.catcher_periods.len()).sum(),
+            completed_challenges: self
+                .teams
+                .iter()
+                .map(|t| t.completed_challenges.len())
+                .sum(),
+        }
+    }
+
+    // Catches are only counted from the catcher's side, so every catch shows up once
+    fn gather_events(&self) -> Vec<Event> {
+        let mut events: Vec<Event> = self
+            .teams
+            .iter()
+            .enumerate()
+            .flat_map(|(i, t)| {
+                let catches = t
+                    .catcher_periods
+                    .iter()
+                    .filter_map(move |p| t.catch_event(i, p));
+                let completions = t
+                    .completed_challenges
+                    .iter()
+                    .map(move |p| t.complete_event(i, p));
+                catches.chain(completions)
+            })
+            .collect();
+        events.sort_by_key(|e| e.time);
+        events
+    }
+
+    fn timers(&self) -> Vec<&TimerHook> {
+        self.game
+            .iter()
+            .flat_map(|g| g.timer.iter().chain(g.backstop.iter()))
+            .chain(self.teams.iter().filter_map(|t| t.refresh_timer.as_ref()))
+            .collect()
+    }
+
+    // Forgets the timer, so it isn't recreated on restart. The caller has to cancel it.
+    fn remove_timer(&mut self, id: u64) -> Option<TimerHook> {
+        match &mut self.game {
+            Some(game) if game.timer.as_ref().is_some_and(|t| t.id == id) => game.timer.take(),
+            Some(game) if game.backstop.as_ref().is_some_and(|t| t.id == id) => {
+                game.backstop.take()
+            }
+            _ => self
+                .teams
+                .iter_mut()
+                .find(|t| t.refresh_timer.as_ref().is_some_and(|t| t.id == id))
+                .and_then(|t| t.refresh_timer.take()),
+        }
+    }
+
+    fn end_timer(
+        &self,
+        timer_tracker: &mut TimerTracker,
+        session_id: u64,
+        end_time: chrono::DateTime<chrono::Local>,
+    ) -> TimerHook {
+        TimerHook::new(
+            timer_tracker,
+            end_time,
+            EngineCommand {
+                session: Some(session_id),
+                action: EngineAction::Stop,
+            },
+            format!("end of the game in session {}", self.name),
+        )
+    }
+
+    // Timers that have to survive a restart, to be recreated when the engine starts
+    fn setup(&self, now: chrono::DateTime<chrono::Local>) -> Vec<RuntimeRequest> {
+        self.timers()
+            .iter()
+            .map(|t| t.create_request(now))
+            .collect()
+    }
+
+    fn vroom(
+        &mut self,
+        command: EngineAction,
+        session_id: u64,
+        context: &EngineContext,
+        timer_tracker: &mut TimerTracker,
+    ) -> InternEngineResponsePackage {
+        use commands::Error::*;
+        use BroadcastAction::*;
+        use EngineAction::*;
+        use ResponseAction::*;
+        match command {
+            AddChallengeToTeam { team, challenge } => match self.teams.get_mut(team) {
+                // THIS METHOD SHOULD BE TEMPORARY AND EXISTS ONLY FOR TESTING PURPOSES
+                None => Error(NotFound).into(),
+                Some(team) => {
+                    team.challenges.push(InOpenChallenge {
+                        title: challenge.title,
+                        description: challenge.description,
+                        points: challenge.points,
+                        action: None,
+                        zone: None,
+                        id: None,
+                        breakdown: challenge.breakdown,
+                    });
+                    Success.into()
                 }
             },
             RenameTeam { team, new_name } => match self.teams.get_mut(team) {
@@ -1157,9 +3036,7 @@ impl Session {
                         team.role = TeamRole::Catcher;
                         EngineResponse {
                             response_action: Success,
-                            broadcast_action: Some(TeamMadeCatcher(
-                                team.to_sendable(player_entries, id),
-                            )),
+                            broadcast_action: Some(TeamMadeCatcher(team.to_sendable(context, id))),
                         }
                         .into()
                     }
@@ -1173,9 +3050,7 @@ impl Session {
                         team.role = TeamRole::Runner;
                         EngineResponse {
                             response_action: Success,
-                            broadcast_action: Some(TeamMadeRunner(
-                                team.to_sendable(player_entries, id),
-                            )),
+                            broadcast_action: Some(TeamMadeRunner(team.to_sendable(context, id))),
                         }
                         .into()
                     }
@@ -1190,10 +3065,27 @@ impl Session {
                 {
                     None => Error(NotFound).into(),
                     Some(team) => {
-                        self.teams[team].locations.insert(
-                            0,
-                            (location.0, location.1, chrono::offset::Local::now().time()),
-                        );
+                        let now = context.clock.now().time();
+                        let config = self.config(context.config);
+                        if let Err(implied_speed) = self.teams[team].add_location(
+                            location,
+                            now,
+                            &config,
+                            context.zone_entries,
+                        ) {
+                            return EngineResponse {
+                                response_action: Error(BadData(format!(
+                                    "location rejected, implied speed of {:.0} km/h",
+                                    implied_speed
+                                ))),
+                                broadcast_action: Some(SuspiciousLocation {
+                                    session: session_id,
+                                    team,
+                                    implied_speed,
+                                }),
+                            }
+                            .into();
+                        }
                         //println!("Engine: done with SendLocation");
                         EngineResponse {
                             response_action: Success,
@@ -1204,6 +3096,17 @@ impl Session {
                 }
             }
             AssignPlayerToTeam { player, team } => {
+                if let Some(index) = team {
+                    let Some(target) = self.teams.get(index) else {
+                        return Error(NotFound).into();
+                    };
+                    let max = self.config(context.config).max_team_size;
+                    if !target.players.contains(&player)
+                        && max.is_some_and(|max| target.players.len() >= max)
+                    {
+                        return Error(TeamFull).into();
+                    }
+                }
                 let mut old_team = None;
                 self.teams.iter_mut().enumerate().for_each(|(index, t)| {
                     if let Some(i) = t.players.iter().position(|p| p == &player) {
@@ -1240,30 +3143,275 @@ impl Session {
                     .into(),
                 }
             }
-            Catch {
-                catcher: _,
-                caught: _,
-            } => Error(NotImplemented).into(), // TODO:
+            Catch { catcher, caught } => {
+                if self.game.is_none() {
+                    return Error(self.no_game_error()).into();
+                }
+                if catcher == caught {
+                    return Error(BadData("a team can't catch itself".into())).into();
+                }
+                let config = self.config(context.config);
+                match self.teams.get_disjoint_mut([catcher, caught]) {
+                    Err(_) => Error(NotFound).into(),
+                    Ok([catcher_team, caught_team]) => {
+                        if catcher_team.role != TeamRole::Catcher
+                            || caught_team.role != TeamRole::Runner
+                        {
+                            return Error(BadData("only catchers can catch runners".into())).into();
+                        }
+                        let points_before = catcher_team.points;
+                        catcher_team.have_caught(catcher, caught_team, caught, &config, context);
+                        let milestones = self
+                            .game
+                            .as_mut()
+                            .map(|game| {
+                                game.reach_milestones(
+                                    &[Milestone::FirstCatch],
+                                    catcher_team,
+                                    &config,
+                                    context.clock.now().time(),
+                                )
+                            })
+                            .unwrap_or_default();
+                        let mut requests =
+                            catcher_team.generation_broadcasts(context, session_id, catcher);
+                        requests.extend(InGame::milestone_broadcasts(
+                            session_id,
+                            catcher,
+                            &milestones,
+                        ));
+                        let now = context.clock.now();
+                        requests.append(&mut catcher_team.reset_refresh_timer(
+                            catcher,
+                            session_id,
+                            &config,
+                            timer_tracker,
+                            now,
+                        ));
+                        requests.append(&mut caught_team.reset_refresh_timer(
+                            caught,
+                            session_id,
+                            &config,
+                            timer_tracker,
+                            now,
+                        ));
+                        if let Some(event) = catcher_team
+                            .catcher_periods
+                            .last()
+                            .and_then(|p| catcher_team.catch_event(catcher, p))
+                        {
+                            requests.push(RuntimeRequest::Broadcast(NewEvent {
+                                session: session_id,
+                                event,
+                            }));
+                        }
+                        InternEngineResponsePackage {
+                            response: EngineResponse {
+                                response_action: SendPeriodSummary(PeriodSummary {
+                                    catch: catcher_team
+                                        .catcher_periods
+                                        .last()
+                                        .map(|p| p.breakdown()),
+                                    milestones,
+                                    ..catcher_team.period_summary(context, catcher, points_before)
+                                }),
+                                broadcast_action: Some(Caught {
+                                    catcher: catcher_team.to_sendable(context, catcher),
+                                    caught: caught_team.to_sendable(context, caught),
+                                }),
+                            }
+                            .into(),
+                            runtime_requests: Some(requests),
+                        }
+                    }
+                }
+            }
             Complete {
-                completer,
-                completed,
-            } => match self.teams.get_mut(completer) {
-                Some(completer) => match completer.challenges.get_mut(completed) {
-                    Some(_completed) => todo!(),
-                    None => todo!(),
-                },
-                None => todo!(),
-            },
+                completer: _,
+                completed: _,
+                pictures: _,
+                player: _,
+            } => Error(InternalError).into(), // handled by `Engine::complete`, which stores the pictures
+            RegenerateChallengesExcept { team, keep_indices } => {
+                if self.game.is_none() {
+                    return Error(self.no_game_error()).into();
+                }
+                let config = self.config(context.config);
+                let Some(entry) = self.teams.get_mut(team) else {
+                    return Error(NotFound).into();
+                };
+                if entry.role != TeamRole::Runner {
+                    return Error(BadData("catchers don't have challenges".into())).into();
+                }
+                if keep_indices.iter().any(|&i| i >= entry.challenges.len()) {
+                    return Error(NotFound).into();
+                }
+                let keep = entry
+                    .challenges
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| keep_indices.contains(i))
+                    .map(|(_, c)| c.clone())
+                    .collect();
+                let period = generation_period(&config, context.clock.now().time());
+                entry.generate_challenges_keeping(&config, period, context, &[], keep);
+                InternEngineResponsePackage {
+                    response: Success.into(),
+                    runtime_requests: Some(entry.generation_broadcasts(context, session_id, team)),
+                }
+            }
+            // usually sent by the team's refresh timer, see `Config::challenge_refresh_minutes`
+            RefreshChallenges { team } => {
+                if self.game.is_none() {
+                    return Error(self.no_game_error()).into();
+                }
+                let config = self.config(context.config);
+                let Some(entry) = self.teams.get_mut(team) else {
+                    return Error(NotFound).into();
+                };
+                let now = context.clock.now();
+                let mut requests =
+                    entry.reset_refresh_timer(team, session_id, &config, timer_tracker, now);
+                if entry.role == TeamRole::Runner {
+                    entry.generate_challenges(
+                        &config,
+                        generation_period(&config, now.time()),
+                        context,
+                    );
+                    requests.append(&mut entry.generation_broadcasts(context, session_id, team));
+                }
+                InternEngineResponsePackage {
+                    response: Success.into(),
+                    runtime_requests: Some(requests),
+                }
+            }
             GetState => SendState {
                 teams: self
                     .teams
                     .iter()
                     .enumerate()
-                    .map(|(i, t)| t.to_sendable(player_entries, i))
+                    .map(|(i, t)| t.to_sendable(context, i))
+                    .collect(),
+                game: self.game.clone().map(|g| g.to_sendable()),
+            }
+            .into(),
+            GetStatePaged {
+                include_completed,
+                include_locations,
+                teams_offset,
+                teams_limit,
+            } => SendStatePage {
+                teams: self
+                    .teams
+                    .iter()
+                    .enumerate()
+                    .skip(teams_offset)
+                    .take(teams_limit.unwrap_or(usize::MAX))
+                    .map(|(i, t)| {
+                        t.to_partial_sendable(context, i, include_completed, include_locations)
+                    })
+                    .collect(),
+                game: self.game.clone().map(|g| g.to_sendable()),
+                total_teams: self.teams.len(),
+            }
+            .into(),
+            GetStateDiff { since_version } => SendStateDiff {
+                version: self.version,
+                teams: self
+                    .teams
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, t)| since_version == 0 || t.changed_at > since_version)
+                    .map(|(i, t)| t.to_sendable(context, i))
                     .collect(),
                 game: self.game.clone().map(|g| g.to_sendable()),
             }
             .into(),
+            GetEvents => SendEvents(self.gather_events()).into(),
+            GetReadiness => SendReadiness(self.readiness(context.player_entries)).into(),
+            GetAvailableColours => SendColours(self.colour_palette(context.config)).into(),
+            GetPlayerContributions { team } => match self.teams.get(team) {
+                None => Error(NotFound).into(),
+                Some(entry) => SendPlayerContributions(entry.player_contributions()).into(),
+            },
+            GetTeamCatchStats { team } => match self.teams.get(team) {
+                None => Error(NotFound).into(),
+                Some(entry) => SendTeamCatchStats(entry.catch_stats(team)).into(),
+            },
+            // handled by the engine, since the pictures are stored in the db right away
+            UploadPeriodPictures {
+                team: _,
+                period: _,
+                pictures: _,
+                token: _,
+            } => Error(InternalError).into(),
+            IsGameRunning => GameRunning(self.game.is_some()).into(),
+            SnapshotScores => {
+                if self.game.is_none() {
+                    return Error(self.no_game_error()).into();
+                }
+                let teams = self.team_scores();
+                self.score_snapshots.push(ScoreSnapshot {
+                    time: context.clock.now(),
+                    teams,
+                });
+                Success.into()
+            }
+            GetScoreSnapshots => SendScoreSnapshots(self.score_snapshots.clone()).into(),
+            // the running game keeps its own copy of the mode, so changing it is pointless then
+            SetSessionMode { mode } => {
+                if self.game.is_some() {
+                    return Error(GameInProgress).into();
+                }
+                self.mode = mode;
+                Success.into()
+            }
+            SetGameEndTime { new_time } => {
+                if self.game.is_none() {
+                    return Error(self.no_game_error()).into();
+                }
+                let now = context.clock.now();
+                let config = self.config(context.config);
+                let started = self.game.as_ref().map_or(now, |g| g.start_time);
+                let Some(end_time) = game_end(&config, started, new_time) else {
+                    return Error(BadData("the new end time doesn't exist today".into())).into();
+                };
+                if end_time <= now {
+                    return Error(BadData("the new end time has already passed".into())).into();
+                }
+                let timer = self.end_timer(timer_tracker, session_id, end_time);
+                let mut runtime_requests = vec![timer.create_request(now)];
+                if let Some(game) = &mut self.game {
+                    if let Some(old) = game.timer.replace(timer) {
+                        runtime_requests.push(old.cancel_request());
+                    }
+                }
+                InternEngineResponsePackage {
+                    response: EngineResponse {
+                        response_action: Success,
+                        broadcast_action: Some(GameEndTimeChanged {
+                            session: session_id,
+                            end_time,
+                        }),
+                    }
+                    .into(),
+                    runtime_requests: Some(runtime_requests),
+                }
+            }
+            GetPeriodPath { team, period } => {
+                match self.teams.get(team).and_then(|t| t.period_path(period)) {
+                    None => Error(NotFound).into(),
+                    Some(path) => SendLocations(path).into(),
+                }
+            }
+            GetActiveTimers => SendTimers(
+                self.timers()
+                    .iter()
+                    .map(|t| t.to_sendable(session_id))
+                    .collect(),
+            )
+            .into(),
+            CancelTimer { id: _ } => Error(SessionSupplied).into(),
             AddTeam {
                 name,
                 discord_channel,
@@ -1277,45 +3425,186 @@ impl Session {
                 {
                     Error(TeamExists(nom)).into()
                 } else {
-                    let colour = match colour {
-                        Some(c) => c,
-                        None => {
-                            match self
-                                .config()
-                                .team_colours
-                                .iter()
-                                .find(|&&c| !self.teams.iter().any(|t| t.colour == c))
-                            {
-                                Some(&colour) => colour,
-                                None => Colour { r: 0, g: 0, b: 0 },
-                            }
-                        }
+                    let (colour, exhausted) = match colour {
+                        Some(c) => (c, false),
+                        None => match self.colour_palette(context.config).available.first() {
+                            Some(&colour) => (colour, false),
+                            None => (Colour { r: 0, g: 0, b: 0 }, true),
+                        },
                     };
                     self.teams
                         .push(TeamEntry::new(name, Vec::new(), discord_channel, colour));
-                    Success.into()
+                    EngineResponse {
+                        response_action: Success,
+                        broadcast_action: exhausted.then(|| TeamColoursExhausted {
+                            session: session_id,
+                            team: self.teams.len() - 1,
+                        }),
+                    }
+                    .into()
                 }
             }
             Start => match self.game {
                 Some(_) => Error(GameInProgress).into(),
                 None => {
-                    todo!(); // TODO:
+                    let config = self.config(context.config);
+                    if let Some(team) = self
+                        .teams
+                        .iter()
+                        .find(|t| t.players.len() < config.min_team_size)
+                    {
+                        return Error(BadData(format!(
+                            "team {} has fewer than {} players",
+                            team.name, config.min_team_size
+                        )))
+                        .into();
+                    }
+                    if let Some(problem) = self.missing_start_challenges(&config, context) {
+                        return Error(BadData(problem)).into();
+                    }
+                    let now = context.clock.now();
+                    let Some(end_time) = game_end(&config, now, config.end_time) else {
+                        return Error(BadData("the end time doesn't exist today".into())).into();
+                    };
+                    let period = generation_period(&config, now.time());
+                    let mut runtime_requests = Vec::new();
+                    // challenges already handed out, so the teams don't all head the same way
+                    let mut taken = Vec::new();
+                    let spread =
+                        config.spread_start_challenges && period == GenerationPeriod::Specific;
+                    for (index, team) in self.teams.iter_mut().enumerate() {
+                        team.catch_streak = 0;
+                        team.stationary = false;
+                        runtime_requests.append(&mut team.reset_refresh_timer(
+                            index,
+                            session_id,
+                            &config,
+                            timer_tracker,
+                            now,
+                        ));
+                        if team.role == TeamRole::Catcher {
+                            continue;
+                        }
+                        if team.current_zone_id.is_none() {
+                            team.current_zone_id =
+                                nearest_centre(&config, context.zone_entries, None);
+                        }
+                        if spread {
+                            team.generate_challenges_avoiding(&config, period, context, &taken);
+                            taken.extend(team.challenges.iter().cloned());
+                        } else {
+                            team.generate_challenges(&config, period, context);
+                        }
+                        runtime_requests
+                            .append(&mut team.generation_broadcasts(context, session_id, index));
+                    }
+                    // a warning only, organizers know best who's actually there
+                    let unready: Vec<TeamReadiness> = self
+                        .readiness(context.player_entries)
+                        .into_iter()
+                        .filter(|r| !r.unready.is_empty())
+                        .collect();
+                    if !unready.is_empty() {
+                        runtime_requests.push(RuntimeRequest::Broadcast(UnreadyAtStart {
+                            session: session_id,
+                            teams: unready,
+                        }));
+                    }
+                    let timer = self.end_timer(timer_tracker, session_id, end_time);
+                    runtime_requests.push(timer.create_request(now));
+                    let backstop = TimerHook::new(
+                        timer_tracker,
+                        now + chrono::TimeDelta::hours(config.max_game_hours as i64),
+                        EngineCommand {
+                            session: Some(session_id),
+                            action: EngineAction::Stop,
+                        },
+                        format!("maximum duration of the game in session {}", self.name),
+                    );
+                    runtime_requests.push(backstop.create_request(now));
+                    self.game_ended = false;
+                    self.game = Some(InGame {
+                        name: self.name.clone(),
+                        date: now.date_naive(),
+                        mode: self.mode,
+                        start_time: now,
+                        timer: Some(timer),
+                        backstop: Some(backstop),
+                        milestones_reached: Vec::new(),
+                    });
+                    InternEngineResponsePackage {
+                        response: EngineResponse {
+                            response_action: Success,
+                            broadcast_action: Some(Started),
+                        }
+                        .into(),
+                        runtime_requests: Some(runtime_requests),
+                    }
+                }
+            },
+            Stop => match self.game.take() {
+                None => Error(self.no_game_error()).into(),
+                Some(game) => {
+                    self.game_ended = true;
+                    if game
+                        .backstop
+                        .as_ref()
+                        .is_some_and(|b| b.end_time <= context.clock.now())
+                    {
+                        println!(
+                            "Engine: the game in session {} ran for its maximum duration without \
+                             reaching its end time, stopping it",
+                            self.name
+                        );
+                    }
+                    InternEngineResponsePackage {
+                        response: EngineResponse {
+                            response_action: Success,
+                            broadcast_action: Some(Ended {
+                                summary: self.summary(session_id),
+                            }),
+                        }
+                        .into(),
+                        runtime_requests: Some(
+                            game.timer
+                                .into_iter()
+                                .chain(game.backstop)
+                                .chain(self.teams.iter_mut().filter_map(|t| t.refresh_timer.take()))
+                                .map(|t| t.cancel_request())
+                                .collect(),
+                        ),
+                    }
                 }
             },
-            Stop => Error(NotImplemented).into(), // TODO:
             AddSession { name: _, mode: _ } => Error(SessionSupplied).into(),
             Ping(_) => Error(SessionSupplied).into(),
+            Announce { message } => announcement(Some(session_id), message).into(),
+            SetConnectionRole(_) => Error(InternalError).into(),
+            Authenticate { secret: _ } => Error(InternalError).into(),
             GetPlayerByPassphrase(_) => Error(SessionSupplied).into(),
             RemovePlayer { player: _ } => Error(SessionSupplied).into(),
             SetPlayerSession {
                 player: _,
                 session: _,
             } => Error(SessionSupplied).into(),
+            MovePlayer {
+                player: _,
+                to_session: _,
+                to_team: _,
+            } => Error(SessionSupplied).into(),
             SetPlayerName { player: _, name: _ } => Error(SessionSupplied).into(),
             SetPlayerPassphrase {
                 player: _,
                 passphrase: _,
             } => Error(SessionSupplied).into(),
+            SetPlayerLanguage {
+                player: _,
+                language: _,
+            } => Error(SessionSupplied).into(),
+            SetPlayerReady {
+                player: _,
+                ready: _,
+            } => Error(SessionSupplied).into(),
             AddPlayer {
                 name: _,
                 discord_id: _,
@@ -1323,13 +3612,199 @@ impl Session {
                 session: _,
             } => Error(SessionSupplied).into(),
             GetRawChallenges => Error(SessionSupplied).into(),
+            GetSessionsDetailed => Error(SessionSupplied).into(),
             SetRawChallenge(_) => Error(SessionSupplied).into(),
             AddRawChallenge(_) => Error(SessionSupplied).into(),
+            GetZone(_) => Error(SessionSupplied).into(),
+            GetZoneByNumber(_) => Error(SessionSupplied).into(),
+            DeleteZone { .. } => Error(SessionSupplied).into(),
+            SetZoneDistances {
+                from_zone: _,
+                distances: _,
+            } => Error(SessionSupplied).into(),
+            ComputeZoneDistances => Error(SessionSupplied).into(),
+            SetZoneCentre { zone: _, centre: _ } => Error(SessionSupplied).into(),
+            SetZoneBoundary {
+                zone: _,
+                boundary: _,
+            } => Error(SessionSupplied).into(),
+            GetZoneAt { location: _ } => Error(SessionSupplied).into(),
+            ReloadConfig => Error(SessionSupplied).into(),
+            ExportSnapshot => Error(SessionSupplied).into(),
+            ImportSnapshot(_) => Error(SessionSupplied).into(),
+            CheckIntegrity => Error(SessionSupplied).into(),
+            RepairIntegrity => Error(SessionSupplied).into(),
+            GetActionLog {
+                session: _,
+                since: _,
+            } => Error(SessionSupplied).into(),
+            GetMetrics => Error(SessionSupplied).into(),
+            GetRecentBroadcasts { since: _, limit: _ } => Error(SessionSupplied).into(),
+            SuggestZoneDistances { min_samples: _ } => Error(SessionSupplied).into(),
+            #[cfg(feature = "debug-time")]
+            AdvanceTime { minutes: _ } => Error(SessionSupplied).into(),
+            // as if the team's players had sent them, for developing map features against
+            // recorded traces. Responds with the locations that didn't pass.
+            #[cfg(feature = "debug-locations")]
+            IngestLocations { team, locations } => {
+                let config = self.config(context.config);
+                match self.teams.get_mut(team) {
+                    None => Error(NotFound).into(),
+                    Some(entry) => SendLocations(
+                        locations
+                            .into_iter()
+                            .filter(|l| {
+                                entry
+                                    .add_location(
+                                        (l.latitude, l.longitude),
+                                        l.time,
+                                        &config,
+                                        context.zone_entries,
+                                    )
+                                    .is_err()
+                            })
+                            .collect(),
+                    )
+                    .into(),
+                }
+            }
+            GetPictureMetadata(_) => Error(SessionSupplied).into(),
+            SimulateGame {
+                games,
+                challenge_set,
+            } => match simulate_game(&self.config(context.config), games, challenge_set, context) {
+                Ok(periods) => SendSimulation(periods).into(),
+                Err(err) => Error(err).into(),
+            },
+            GetCandidateChallenges { team, period } => match self.teams.get(team) {
+                None => Error(NotFound).into(),
+                Some(team) => {
+                    let config = self.config(context.config);
+                    let period = period
+                        .unwrap_or_else(|| generation_period(&config, context.clock.now().time()));
+                    let (tier, challenges) = team
+                        .candidates(&config, period, &[], &[], context)
+                        .map(|(tier, candidates)| (tier, candidates.iter().map(|c| c.id).collect()))
+                        .unwrap_or_default();
+                    SendCandidateChallenges(CandidateChallenges {
+                        period,
+                        tier,
+                        challenges,
+                    })
+                    .into()
+                }
+            },
+            AnalyzeChallengePoints {
+                challenge_id,
+                from_zone,
+                samples,
+            } => match context
+                .challenge_entries
+                .iter()
+                .find(|c| c.id == challenge_id)
+            {
+                None => Error(NotFound).into(),
+                Some(entry) => {
+                    let config = self.config(context.config);
+                    let points: Vec<u64> = (0..samples.min(config.max_point_samples))
+                        .map(|_| {
+                            entry
+                                .contents
+                                .challenge(entry.id, &config, false, from_zone, context, None)
+                                .points
+                        })
+                        .collect();
+                    match PointDistribution::from_samples(&points) {
+                        Some(distribution) => SendPointDistribution(distribution).into(),
+                        None => Error(BadData("samples must be at least 1".into())).into(),
+                    }
+                }
+            },
+        }
+    }
+}
+
+type ChallengeFilter<'a> = &'a dyn Fn(&DBEntry<ChallengeEntry>) -> bool;
+
+// Everything a session needs from the engine to process a command
+struct EngineContext<'a> {
+    config: &'a Config, // the engine wide defaults, use `Session::config` for the actual config
+    player_entries: &'a [DBEntry<PlayerEntry>],
+    zone_entries: &'a [DBEntry<ZoneEntry>],
+    challenge_entries: &'a [DBEntry<ChallengeEntry>],
+    scoring: &'a dyn ScoringStrategy,
+    clock: &'a dyn Clock,
+}
+
+// Plays `games` synthetic games for a single team, without any clients or db involved. Every
+// half hour of game time, the team gets new challenges, completes one of them at random and
+// moves to its zone. The points of every generated challenge are recorded per period. With a
+// `challenge_set`, only the challenges in that set are generated.
+fn simulate_game(
+    config: &Config,
+    games: u64,
+    challenge_set: Option<u64>,
+    context: &EngineContext,
+) -> Result<Vec<SimulatedPeriod>, commands::Error> {
+    const STEP_MINUTES: i64 = 30;
+    let challenges: Vec<DBEntry<ChallengeEntry>> = context
+        .challenge_entries
+        .iter()
+        .filter(|c| challenge_set.is_none_or(|set| c.contents.sets.contains(&set)))
+        .cloned()
+        .collect();
+    let context = &EngineContext {
+        challenge_entries: &challenges,
+        ..*context
+    };
+    let mut game_minutes = (config.end_time - config.start_time).num_minutes();
+    if game_minutes <= 0 {
+        game_minutes += 24 * 60;
+    }
+    // game time, so time-dependent challenge texts read as they would in a real game
+    let Some(start) = context.clock.now().with_time(config.start_time).earliest() else {
+        return Err(commands::Error::BadData(
+            "the start time doesn't exist today".into(),
+        ));
+    };
+    let mut results: Vec<SimulatedPeriod> = Vec::new();
+    for _ in 0..games.min(config.max_simulated_games) {
+        let mut team = TeamEntry::new(
+            "Simulation".into(),
+            Vec::new(),
+            None,
+            Colour { r: 0, g: 0, b: 0 },
+        );
+        team.current_zone_id = nearest_centre(config, context.zone_entries, None);
+        for minute in (0..game_minutes).step_by(STEP_MINUTES as usize) {
+            let clock = FixedClock(start + chrono::Duration::minutes(minute));
+            let context = EngineContext {
+                clock: &clock,
+                ..*context
+            };
+            let period = generation_period(config, clock.now().time());
+            team.generate_challenges(config, period, &context);
+            let points = team.challenges.iter().map(|c| c.points);
+            match results.iter_mut().find(|r| r.period == period) {
+                Some(result) => result.points.extend(points),
+                None => results.push(SimulatedPeriod {
+                    period,
+                    points: points.collect(),
+                }),
+            }
+            if let Some(zone) = team
+                .challenges
+                .choose(&mut thread_rng())
+                .and_then(|c| c.zone)
+            {
+                team.current_zone_id = Some(zone);
+            }
         }
     }
+    Ok(results)
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct DBEntry<T>
 where
     T: SerializedCollection<Contents = T, PrimaryKey = u64>,
@@ -1355,10 +3830,200 @@ where
     }
 }
 
-#[allow(dead_code)]
-pub struct Engine {
-    db: Database,
-    changes_since_save: bool,
+fn vec_overwrite_in_transaction<T>(
+    entries: Vec<DBEntry<T>>,
+    transaction: &mut Transaction,
+) -> Result<(), bonsaidb::core::Error>
+where
+    T: SerializedCollection<Contents = T, PrimaryKey = u64> + 'static,
+{
+    let mut ret = Ok(());
+    for entry in entries {
+        match entry
+            .contents
+            .overwrite_in_transaction(&entry.id, transaction)
+        {
+            Ok(()) => (),
+            Err(err) => {
+                println!(
+                    "Engine: something went wrong during overwrite_in_transaction: {}",
+                    err
+                );
+                ret = Err(err);
+            }
+        }
+    }
+    ret
+}
+
+const SNAPSHOT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotContents {
+    sessions: Vec<DBEntry<Session>>,
+    challenges: Vec<DBEntry<ChallengeEntry>>,
+    challenge_sets: Vec<DBEntry<ChallengeSetEntry>>,
+    zones: Vec<DBEntry<ZoneEntry>>,
+    players: Vec<DBEntry<PlayerEntry>>,
+    past_games: Vec<DBEntry<PastGame>>,
+    location_archives: Vec<DBEntry<LocationArchiveEntry>>, // since version 2
+}
+
+// Issues the ids timers are cancelled by. The counter is saved along with everything else, so
+// ids of timers stored in the db are never handed out again after a restart. It is stored as
+// the single document with id `TimerTracker::DB_ID`.
+#[derive(Debug, Clone, Default, Collection, Serialize, Deserialize)]
+#[collection(name = "timer tracker")]
+struct TimerTracker {
+    current_id: u64,
+}
+
+impl TimerTracker {
+    const DB_ID: u64 = 1;
+
+    fn load(db: &Database) -> Self {
+        match TimerTracker::get(&Self::DB_ID, db).unwrap() {
+            Some(doc) => doc.contents,
+            None => TimerTracker::default(),
+        }
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.current_id = self
+            .current_id
+            .checked_add(1)
+            .expect("Engine: ran out of timer ids, which should take a few billion years");
+        self.current_id
+    }
+
+    fn timer(&mut self, duration: Duration, payload: InternEngineCommand) -> RuntimeRequest {
+        RuntimeRequest::CreateTimer {
+            id: self.next_id(),
+            duration,
+            payload,
+        }
+    }
+}
+
+// Processing times of one kind of command, kept in memory only
+#[derive(Debug, Default)]
+struct ActionMetrics {
+    count: u64,
+    total_micros: u64,
+    max_micros: u64,
+    histogram: [u64; ActionMetrics::BOUNDS.len()],
+}
+
+impl ActionMetrics {
+    // upper bounds of the histogram buckets in µs
+    const BOUNDS: [u64; 6] = [100, 1_000, 10_000, 100_000, 1_000_000, u64::MAX];
+
+    fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros().try_into().unwrap_or(u64::MAX);
+        self.count += 1;
+        self.total_micros = self.total_micros.saturating_add(micros);
+        self.max_micros = self.max_micros.max(micros);
+        let bucket = Self::BOUNDS
+            .iter()
+            .position(|&bound| micros <= bound)
+            .expect("the last bound is u64::MAX");
+        self.histogram[bucket] += 1;
+    }
+
+    fn to_sendable(&self, action: &str) -> CommandMetrics {
+        CommandMetrics {
+            action: action.into(),
+            count: self.count,
+            total_micros: self.total_micros,
+            max_micros: self.max_micros,
+            histogram: Self::BOUNDS.into_iter().zip(self.histogram).collect(),
+        }
+    }
+}
+
+// A successfully applied mutating command. Entries are only ever appended, which makes the log
+// usable for settling disputes and as the basis for undoing more than a single period. Only the
+// latest `action_log_length` entries are kept.
+#[derive(Debug, Clone, Collection, Serialize, Deserialize)]
+#[collection(name = "action log")]
+struct ActionLogEntry {
+    session: Option<u64>,
+    #[serde(default)]
+    connection: Option<u64>, // None for commands the engine sent itself, e.g. from timers
+    time: chrono::DateTime<chrono::Local>,
+    action: EngineAction,
+}
+
+impl ActionLogEntry {
+    fn to_sendable(&self, id: u64) -> LoggedAction {
+        LoggedAction {
+            id,
+            session: self.session,
+            connection: self.connection,
+            time: self.time,
+            action: self.action.clone(),
+        }
+    }
+}
+
+// Tags the db with the format its records are stored in, so records written by older versions
+// get upgraded in `Engine::migrate` instead of being silently misread. Dbs from before the
+// versioning have no such document and count as version 0. It is stored as the single document
+// with id `SchemaMeta::DB_ID`.
+#[derive(Debug, Clone, Collection, Serialize, Deserialize)]
+#[collection(name = "schema meta")]
+struct SchemaMeta {
+    version: u32,
+}
+
+impl SchemaMeta {
+    const DB_ID: u64 = 1;
+    const CURRENT_VERSION: u32 = 1;
+
+    fn load(db: &Database) -> Option<Self> {
+        SchemaMeta::get(&Self::DB_ID, db)
+            .unwrap()
+            .map(|doc| doc.contents)
+    }
+}
+
+// The runtime limits every connection to `commands_per_second`, with bursts of up to
+// `command_burst` commands. A broken config is reported by `Engine::init`, not here.
+pub fn command_rate_limit(config_path: &Path) -> (f64, u32) {
+    let config = load_config(config_path).unwrap_or_default();
+    (config.commands_per_second, config.command_burst)
+}
+
+// Reads the config overrides from a ron file, all fields are optional. A missing file is fine
+// and just means that the hardcoded defaults are used.
+fn load_config(path: &Path) -> Result<Config, String> {
+    let mut config = Config::default();
+    match std::fs::read_to_string(path) {
+        Ok(text) => {
+            let overrides: PartialConfig = ron::from_str(&text).map_err(|e| e.to_string())?;
+            config.apply_some(overrides);
+            Ok(config)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(config),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[allow(dead_code)]
+pub struct Engine {
+    db: Database,
+    changes_since_save: bool,
+    config: Config,
+    config_path: PathBuf,
+    timer_tracker: TimerTracker,
+    scoring: Box<dyn ScoringStrategy>,
+    clock: Box<dyn Clock>,
+    #[cfg(feature = "debug-time")]
+    time_offset: chrono::TimeDelta,
+    action_log: Vec<DBEntry<ActionLogEntry>>,
+    metrics: HashMap<&'static str, ActionMetrics>,
+    recent_broadcasts: VecDeque<SequencedBroadcast>,
+    broadcast_sequence: u64,
 
     sessions: Vec<DBEntry<Session>>,
     challenges: Vec<DBEntry<ChallengeEntry>>,
@@ -1371,7 +4036,7 @@ pub struct Engine {
 }
 
 impl Engine {
-    pub fn init(storage_path: &Path) -> Self {
+    pub fn init(storage_path: &Path, config_path: &Path) -> Self {
         let db = Storage::open(
             config::StorageConfiguration::new(storage_path)
                 .with_schema::<EngineSchema>()
@@ -1381,33 +4046,40 @@ impl Engine {
         .create_database::<EngineSchema>("engine", true)
         .unwrap();
 
-        fn make_entry_vector<T>(db: &Database) -> Vec<DBEntry<T>>
-        where
-            T: SerializedCollection<Contents = T, PrimaryKey = u64>,
-        {
-            T::all(db)
-                .query()
-                .unwrap()
-                .into_iter()
-                .map(|d| DBEntry {
-                    id: d.header.id,
-                    contents: d.contents,
-                })
-                .collect()
-        }
-
-        let challenges = make_entry_vector::<ChallengeEntry>(&db);
-        let challenge_sets = make_entry_vector::<ChallengeSetEntry>(&db);
-        let zones = make_entry_vector::<ZoneEntry>(&db);
-        let sessions = make_entry_vector::<Session>(&db);
-        let players = make_entry_vector::<PlayerEntry>(&db);
+        let challenges = DBEntry::<ChallengeEntry>::make_entry_vector(&db);
+        let challenge_sets = DBEntry::<ChallengeSetEntry>::make_entry_vector(&db);
+        let zones = DBEntry::<ZoneEntry>::make_entry_vector(&db);
+        let sessions = DBEntry::<Session>::make_entry_vector(&db);
+        let players = DBEntry::<PlayerEntry>::make_entry_vector(&db);
+        let action_log = DBEntry::<ActionLogEntry>::make_entry_vector(&db);
 
         let past_games = PastGame::all(&db).headers().unwrap();
         let pictures = PictureEntry::all(&db).headers().unwrap();
+        let timer_tracker = TimerTracker::load(&db);
+        let schema_meta = SchemaMeta::load(&db);
+
+        let config = load_config(config_path).unwrap_or_else(|err| {
+            panic!(
+                "Engine: couldn't load config from {}: {}",
+                config_path.display(),
+                err
+            )
+        });
 
-        Engine {
+        let mut engine = Engine {
             db,
             changes_since_save: false,
+            config,
+            config_path: config_path.into(),
+            timer_tracker,
+            scoring: Box::new(DefaultScoring),
+            clock: Box::new(SystemClock),
+            #[cfg(feature = "debug-time")]
+            time_offset: chrono::TimeDelta::zero(),
+            action_log,
+            metrics: HashMap::new(),
+            recent_broadcasts: VecDeque::new(),
+            broadcast_sequence: 0,
             challenges,
             challenge_sets,
             zones,
@@ -1415,16 +4087,86 @@ impl Engine {
             players,
             past_games,
             pictures,
+        };
+
+        let version = match &schema_meta {
+            Some(meta) => meta.version,
+            None if engine.is_empty() => SchemaMeta::CURRENT_VERSION,
+            None => 0,
+        };
+        if version > SchemaMeta::CURRENT_VERSION {
+            panic!(
+                "Engine: the db is at version {}, but this engine only knows up to version {}",
+                version,
+                SchemaMeta::CURRENT_VERSION
+            );
         }
+        if schema_meta.is_none() || version < SchemaMeta::CURRENT_VERSION {
+            engine.migrate(version);
+        }
+        engine
     }
 
-    pub fn setup(&self) -> InternEngineResponsePackage {
+    // Upgrades the records stored by an older version to the current format, one version at a
+    // time, and writes everything back in one go together with the new version tag.
+    fn migrate(&mut self, from: u32) {
+        for version in from..SchemaMeta::CURRENT_VERSION {
+            println!(
+                "Engine: migrating db from version {} to {}",
+                version,
+                version + 1
+            );
+            match version {
+                // everything added before versioning came with `#[serde(default)]`, so
+                // reading and writing back the records is all that's needed
+                0 => (),
+                _ => unreachable!(),
+            }
+        }
+        let mut transaction = Transaction::new();
+        let results = [
+            vec_overwrite_in_transaction(self.players.clone(), &mut transaction),
+            vec_overwrite_in_transaction(self.sessions.clone(), &mut transaction),
+            vec_overwrite_in_transaction(self.challenges.clone(), &mut transaction),
+            vec_overwrite_in_transaction(self.challenge_sets.clone(), &mut transaction),
+            vec_overwrite_in_transaction(self.zones.clone(), &mut transaction),
+            vec_overwrite_in_transaction(
+                vec![DBEntry {
+                    id: SchemaMeta::DB_ID,
+                    contents: SchemaMeta {
+                        version: SchemaMeta::CURRENT_VERSION,
+                    },
+                }],
+                &mut transaction,
+            ),
+        ];
+        for result in results {
+            if let Err(err) = result {
+                panic!("Engine: couldn't prepare the db migration: {}", err);
+            }
+        }
+        if let Err(err) = transaction.apply(&self.db) {
+            panic!("Engine: db migration failed: {}", err);
+        }
+    }
+
+    pub fn setup(&mut self) -> InternEngineResponsePackage {
+        let mut runtime_requests = vec![
+            self.timer_tracker.timer(
+                tokio::time::Duration::from_secs(10),
+                InternEngineCommand::AutoSave,
+            ),
+            self.timer_tracker.timer(
+                STATIONARY_CHECK_INTERVAL,
+                InternEngineCommand::CheckStationary,
+            ),
+        ];
+        for session in &self.sessions {
+            runtime_requests.append(&mut session.contents.setup(self.clock.now()));
+        }
         InternEngineResponsePackage {
             response: InternEngineResponse::DirectResponse(ResponseAction::Success.into()),
-            runtime_requests: Some(vec![RuntimeRequest::CreateTimer {
-                duration: tokio::time::Duration::from_secs(10),
-                payload: InternEngineCommand::AutoSave,
-            }]),
+            runtime_requests: Some(runtime_requests),
         }
     }
 
@@ -1480,7 +4222,711 @@ impl Engine {
         delete_from_db_and(&self.db, doc, on_success)
     }
 
-    pub fn vroom(&mut self, command: InternEngineCommand) -> InternEngineResponsePackage {
+    fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+            && self.challenges.is_empty()
+            && self.challenge_sets.is_empty()
+            && self.zones.is_empty()
+            && self.players.is_empty()
+            && self.past_games.is_empty()
+    }
+
+    // Observed travel times from the teams of all past games and of the sessions whose last game
+    // has ended, see `TeamEntry::travel_times`. Running games aren't over yet, so they're left out.
+    fn suggest_zone_distances(&self, min_samples: u64) -> ResponseAction {
+        let past_games = match PastGame::all(&self.db).query() {
+            Ok(docs) => docs,
+            Err(err) => {
+                eprintln!("Engine: Couldn't get past games for travel times: {}", err);
+                return ResponseAction::Error(commands::Error::InternalError);
+            }
+        };
+        let ended = self
+            .sessions
+            .iter()
+            .filter(|s| s.contents.game_ended)
+            .flat_map(|s| s.contents.teams.iter().cloned());
+        let teams = past_games
+            .into_iter()
+            .flat_map(|g| g.contents.teams)
+            .chain(ended);
+        let mut observed: HashMap<(u64, u64), Vec<u64>> = HashMap::new();
+        for mut team in teams {
+            if let Err(err) = LocationArchiveEntry::restore(&self.db, &mut team) {
+                eprintln!(
+                    "Engine: couldn't restore locations of {}: {}",
+                    team.name, err
+                );
+            }
+            for (from, to, minutes) in team.travel_times(&self.zones) {
+                observed.entry((from, to)).or_default().push(minutes);
+            }
+        }
+        let mut suggestions: Vec<TravelTimeSuggestion> = observed
+            .into_iter()
+            .filter(|(_, minutes)| minutes.len() as u64 >= min_samples)
+            .map(|((from_zone, to_zone), mut minutes)| {
+                minutes.sort_unstable();
+                TravelTimeSuggestion {
+                    from_zone,
+                    to_zone,
+                    configured_minutes: self
+                        .zones
+                        .iter()
+                        .find(|z| z.id == from_zone)
+                        .and_then(|z| z.contents.minutes_to.get(&to_zone).copied()),
+                    observed_minutes: minutes[minutes.len() / 2],
+                    samples: minutes.len() as u64,
+                }
+            })
+            .collect();
+        suggestions.sort_unstable_by_key(|s| (s.from_zone, s.to_zone));
+        ResponseAction::SendTravelTimeSuggestions(suggestions)
+    }
+
+    fn export_snapshot(&self) -> ResponseAction {
+        let past_games = match PastGame::all(&self.db).query() {
+            Ok(docs) => docs
+                .into_iter()
+                .map(|d| DBEntry {
+                    id: d.header.id,
+                    contents: d.contents,
+                })
+                .collect(),
+            Err(err) => {
+                eprintln!("Engine: Couldn't get past games for snapshot: {}", err);
+                return ResponseAction::Error(commands::Error::InternalError);
+            }
+        };
+        // teams only keep the ids of their archives, the locations themselves are in the db
+        let location_archives = match LocationArchiveEntry::all(&self.db).query() {
+            Ok(docs) => docs
+                .into_iter()
+                .map(|d| DBEntry {
+                    id: d.header.id,
+                    contents: d.contents,
+                })
+                .collect(),
+            Err(err) => {
+                eprintln!(
+                    "Engine: Couldn't get location archives for snapshot: {}",
+                    err
+                );
+                return ResponseAction::Error(commands::Error::InternalError);
+            }
+        };
+        let contents = SnapshotContents {
+            sessions: self.sessions.clone(),
+            challenges: self.challenges.clone(),
+            challenge_sets: self.challenge_sets.clone(),
+            zones: self.zones.clone(),
+            players: self.players.clone(),
+            past_games,
+            location_archives,
+        };
+        match ron::to_string(&contents) {
+            Ok(data) => ResponseAction::SendSnapshot(Snapshot {
+                version: SNAPSHOT_VERSION,
+                data,
+            }),
+            Err(err) => {
+                eprintln!("Engine: Couldn't serialize snapshot: {}", err);
+                ResponseAction::Error(commands::Error::InternalError)
+            }
+        }
+    }
+
+    // Locations come in every few seconds and are kept with the teams anyway, so they would
+    // only drown out the referee actions in the log. Pictures and snapshots are stored elsewhere
+    // too and would make every entry megabytes large, so they are left out.
+    fn log_action(&mut self, command: EngineCommand, connection: Option<u64>) {
+        let mut action = command.action;
+        match &mut action {
+            EngineAction::SendLocation { .. } | EngineAction::ImportSnapshot(_) => return,
+            #[cfg(feature = "debug-locations")]
+            EngineAction::IngestLocations { .. } => return,
+            EngineAction::Complete { pictures, .. }
+            | EngineAction::UploadPeriodPictures { pictures, .. } => pictures.clear(),
+            _ => (),
+        }
+        add_into(
+            &mut self.action_log,
+            ActionLogEntry {
+                session: command.session,
+                connection,
+                time: chrono::Local::now(),
+                action,
+            },
+        );
+        let excess = self
+            .action_log
+            .len()
+            .saturating_sub(self.config.action_log_length);
+        // autosave only overwrites, so the dropped entries have to be removed from the db here
+        for entry in self.action_log.drain(..excess) {
+            match ActionLogEntry::get(&entry.id, &self.db) {
+                Ok(Some(doc)) => {
+                    if let Err(err) = doc.delete(&self.db) {
+                        eprintln!(
+                            "Engine: couldn't delete logged action {}: {}",
+                            entry.id, err
+                        );
+                    }
+                }
+                Ok(None) => (), // never saved
+                Err(err) => eprintln!("Engine: couldn't get logged action {}: {}", entry.id, err),
+            }
+        }
+    }
+
+    // Moves the engine's clock, and with it every running timer, e.g. to watch generation go
+    // through the periods without waiting for hours
+    #[cfg(feature = "debug-time")]
+    fn advance_time(&mut self, minutes: i64) -> InternEngineResponsePackage {
+        use commands::Error::BadData;
+        let Some((by, offset)) = chrono::TimeDelta::try_minutes(minutes).and_then(|by| {
+            self.time_offset
+                .checked_add(&by)
+                .filter(|offset| chrono::Local::now().checked_add_signed(*offset).is_some())
+                .map(|offset| (by, offset))
+        }) else {
+            return ResponseAction::Error(BadData("the clock can't go that far".into())).into();
+        };
+        self.time_offset = offset;
+        self.clock = Box::new(ShiftedClock(self.time_offset));
+        let now = self.clock.now();
+        println!("Engine: advanced the clock by {} to {}", by, now);
+        let runtime_requests = self
+            .sessions
+            .iter()
+            .flat_map(|s| s.contents.timers())
+            .flat_map(|t| [t.cancel_request(), t.create_request(now)])
+            .collect();
+        InternEngineResponsePackage {
+            response: ResponseAction::Success.into(),
+            runtime_requests: Some(runtime_requests),
+        }
+    }
+
+    // `SetPlayerSession` and `AssignPlayerToTeam` in one go, so the player is never in the new
+    // session without their team. Like `AssignPlayerToTeam`, this also works while a game is
+    // running, which is how latecomers get into a game.
+    fn move_player(
+        &mut self,
+        player: u64,
+        to_session: u64,
+        to_team: Option<usize>,
+    ) -> InternEngineResponsePackage {
+        use commands::Error::*;
+        use ResponseAction::*;
+        let Some(player_entry) = self.players.iter_mut().find(|p| p.id == player) else {
+            return Error(NotFound).into();
+        };
+        let Some(target) = self.sessions.iter().find(|s| s.id == to_session) else {
+            return Error(NotFound).into();
+        };
+        if let Some(index) = to_team {
+            let Some(team) = target.contents.teams.get(index) else {
+                return Error(NotFound).into();
+            };
+            let max = target.contents.config(&self.config).max_team_size;
+            if !team.players.contains(&player) && max.is_some_and(|max| team.players.len() >= max) {
+                return Error(TeamFull).into();
+            }
+        }
+
+        let from_session = player_entry.contents.session;
+        let mut from_team = None;
+        for session in self
+            .sessions
+            .iter_mut()
+            .filter(|s| from_session == Some(s.id) || s.id == to_session)
+        {
+            for (index, team) in session.contents.teams.iter_mut().enumerate() {
+                if let Some(i) = team.players.iter().position(|p| p == &player) {
+                    team.players.remove(i);
+                    if from_session == Some(session.id) {
+                        from_team = Some(index);
+                    }
+                }
+            }
+            if session.id == to_session {
+                if let Some(team) = to_team.and_then(|index| session.contents.teams.get_mut(index))
+                {
+                    team.players.push(player);
+                }
+            }
+        }
+        player_entry.contents.session = Some(to_session);
+        EngineResponse {
+            response_action: Success,
+            broadcast_action: Some(BroadcastAction::PlayerMoved {
+                player: player_entry.contents.to_sendable(player),
+                from_session,
+                from_team,
+                to_session,
+                to_team,
+            }),
+        }
+        .into()
+    }
+
+    // Handled here instead of in `Session::vroom`, since the pictures go straight into the db.
+    // A retry with a known token gets the pictures of the first attempt instead of new copies.
+    fn upload_period_pictures(
+        &mut self,
+        session_id: u64,
+        action: EngineAction,
+    ) -> InternEngineResponsePackage {
+        use commands::Error::*;
+        use ResponseAction::*;
+        let EngineAction::UploadPeriodPictures {
+            team,
+            period,
+            pictures,
+            token,
+        } = action
+        else {
+            unreachable!("only called for UploadPeriodPictures")
+        };
+        let Some(session) = self.sessions.iter_mut().find(|s| s.id == session_id) else {
+            return Error(NotFound).into();
+        };
+        if let Some(ids) = session.contents.uploads.get(&token) {
+            return SendPictureIds(ids.clone()).into();
+        }
+        let Some(completed) = session
+            .contents
+            .teams
+            .get_mut(team)
+            .and_then(|t| t.completed_challenges.get_mut(period))
+        else {
+            return Error(NotFound).into();
+        };
+        let ids =
+            match PictureEntry::store_challenge_pictures(pictures, &self.db, &mut self.pictures) {
+                Ok(ids) => ids,
+                Err(err) => return Error(err).into(),
+            };
+        completed.pictures.extend(&ids);
+        session.contents.uploads.insert(token, ids.clone());
+        SendPictureIds(ids).into()
+    }
+
+    // Also handled here, since the pictures have to be stored before the completion can refer to
+    // them. They are removed again if the completion fails.
+    fn complete(&mut self, session_id: u64, action: EngineAction) -> InternEngineResponsePackage {
+        use commands::Error::*;
+        use ResponseAction::*;
+        let EngineAction::Complete {
+            completer,
+            completed,
+            pictures,
+            player,
+        } = action
+        else {
+            unreachable!("only called for Complete")
+        };
+        let Some(session) = self.sessions.iter_mut().find(|s| s.id == session_id) else {
+            return Error(NotFound).into();
+        };
+        if player.is_some_and(|p| {
+            session
+                .contents
+                .teams
+                .get(completer)
+                .is_some_and(|t| !t.players.contains(&p))
+        }) {
+            return Error(BadData(
+                "the credited player isn't in the completing team".into(),
+            ))
+            .into();
+        }
+        let ids =
+            match PictureEntry::store_challenge_pictures(pictures, &self.db, &mut self.pictures) {
+                Ok(ids) => ids,
+                Err(err) => return Error(err).into(),
+            };
+        let package = session.contents.complete(
+            session_id,
+            completer,
+            completed,
+            ids.clone(),
+            &EngineContext {
+                config: &self.config,
+                player_entries: &self.players,
+                zone_entries: &self.zones,
+                challenge_entries: &self.challenges,
+                scoring: self.scoring.as_ref(),
+                clock: self.clock.as_ref(),
+            },
+            &mut self.timer_tracker,
+        );
+        if let InternEngineResponse::DirectResponse(response) = &package.response {
+            if matches!(response.response_action, Error(_)) {
+                PictureEntry::delete_all(&ids, &self.db, &mut self.pictures);
+            } else if let Some(period) = session
+                .contents
+                .teams
+                .get_mut(completer)
+                .and_then(|t| t.completed_challenges.last_mut())
+            {
+                period.player = player;
+            }
+        }
+        package
+    }
+
+    // Moves the locations of all but the last `location_retention_periods` finished periods of
+    // every team into the db. They are only needed again for paths and events.
+    fn offload_locations(&mut self) {
+        for session in &mut self.sessions {
+            let Some(retention) = session
+                .contents
+                .config(&self.config)
+                .location_retention_periods
+            else {
+                continue;
+            };
+            for team in &mut session.contents.teams {
+                let ranges = team.period_ranges();
+                let Some(&(_, cut)) = ranges
+                    .len()
+                    .checked_sub(retention + 1)
+                    .and_then(|i| ranges.get(i))
+                else {
+                    continue;
+                };
+                let count = (cut.saturating_sub(team.archived_locations) as usize)
+                    .min(team.locations.len());
+                if count == 0 {
+                    continue;
+                }
+                let moved = team.locations.split_off(team.locations.len() - count);
+                if let Err(err) = LocationArchiveEntry::append(&self.db, team, moved.clone()) {
+                    eprintln!(
+                        "Engine: couldn't archive locations of {}: {}",
+                        team.name, err
+                    );
+                    team.locations.extend(moved);
+                }
+            }
+        }
+    }
+
+    // Answers read only commands that need the full location history, with a copy of the
+    // session that has its archived locations back if there are any
+    fn with_archived_locations(
+        &mut self,
+        session_id: u64,
+        action: EngineAction,
+    ) -> InternEngineResponsePackage {
+        let Some(session) = self.sessions.iter_mut().find(|s| s.id == session_id) else {
+            return ResponseAction::Error(commands::Error::NotFound).into();
+        };
+        let mut restored;
+        let session = if session
+            .contents
+            .teams
+            .iter()
+            .any(|t| t.archived_locations > 0)
+        {
+            restored = session.contents.clone();
+            for team in &mut restored.teams {
+                if let Err(err) = LocationArchiveEntry::restore(&self.db, team) {
+                    eprintln!(
+                        "Engine: couldn't restore locations of {}: {}",
+                        team.name, err
+                    );
+                    return ResponseAction::Error(commands::Error::InternalError).into();
+                }
+            }
+            &mut restored
+        } else {
+            &mut session.contents
+        };
+        session.vroom(
+            action,
+            session_id,
+            &EngineContext {
+                config: &self.config,
+                player_entries: &self.players,
+                zone_entries: &self.zones,
+                challenge_entries: &self.challenges,
+                scoring: self.scoring.as_ref(),
+                clock: self.clock.as_ref(),
+            },
+            &mut self.timer_tracker,
+        )
+    }
+
+    // Finds references between db entries that don't resolve, e.g. left behind by a crash
+    // between deleting something and cleaning up after it
+    fn integrity_problems(&self) -> Vec<IntegrityProblem> {
+        use IntegrityProblem::*;
+        let mut problems = Vec::new();
+        for player in &self.players {
+            if let Some(session) = player.contents.session {
+                if !self.sessions.iter().any(|s| s.id == session) {
+                    problems.push(PlayerInMissingSession {
+                        player: player.id,
+                        session,
+                    });
+                }
+            }
+        }
+        for session in &self.sessions {
+            for (index, team) in session.contents.teams.iter().enumerate() {
+                for &player in &team.players {
+                    match self.players.iter().find(|p| p.id == player) {
+                        None => problems.push(MissingTeamPlayer {
+                            session: session.id,
+                            team: index,
+                            player,
+                        }),
+                        Some(entry) if entry.contents.session != Some(session.id) => {
+                            problems.push(TeamPlayerInOtherSession {
+                                session: session.id,
+                                team: index,
+                                player,
+                            })
+                        }
+                        Some(_) => (),
+                    }
+                }
+                if let Some(zone) = team.current_zone_id {
+                    if !self.zones.iter().any(|z| z.id == zone) {
+                        problems.push(MissingTeamZone {
+                            session: session.id,
+                            team: index,
+                            zone,
+                        });
+                    }
+                }
+                for (period, completed) in team.completed_challenges.iter().enumerate() {
+                    for &picture in completed.photo.iter().chain(&completed.pictures) {
+                        if !self
+                            .pictures
+                            .iter()
+                            .any(|h| h.id.deserialize::<u64>().ok() == Some(picture))
+                        {
+                            problems.push(MissingPhoto {
+                                session: session.id,
+                                team: index,
+                                period,
+                                picture,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        for challenge in &self.challenges {
+            for &set in &challenge.contents.sets {
+                if !self.challenge_sets.iter().any(|s| s.id == set) {
+                    problems.push(MissingChallengeSet {
+                        challenge: challenge.id,
+                        set,
+                    });
+                }
+            }
+            for &zone in &challenge.contents.zone {
+                if !self.zones.iter().any(|z| z.id == zone) {
+                    problems.push(MissingChallengeZone {
+                        challenge: challenge.id,
+                        zone,
+                    });
+                }
+            }
+        }
+        problems
+    }
+
+    // Drops every dangling reference found by `integrity_problems` and returns what was fixed
+    fn repair_integrity(&mut self) -> Vec<IntegrityProblem> {
+        use IntegrityProblem::*;
+        let problems = self.integrity_problems();
+        for problem in &problems {
+            match *problem {
+                PlayerInMissingSession { player, session: _ } => {
+                    if let Some(entry) = self.players.iter_mut().find(|p| p.id == player) {
+                        entry.contents.session = None;
+                    }
+                }
+                MissingTeamPlayer {
+                    session,
+                    team,
+                    player,
+                }
+                | TeamPlayerInOtherSession {
+                    session,
+                    team,
+                    player,
+                } => {
+                    if let Some(entry) = self.sessions.iter_mut().find(|s| s.id == session) {
+                        entry.contents.teams[team].players.retain(|&p| p != player);
+                    }
+                }
+                MissingTeamZone {
+                    session,
+                    team,
+                    zone: _,
+                } => {
+                    if let Some(entry) = self.sessions.iter_mut().find(|s| s.id == session) {
+                        entry.contents.teams[team].current_zone_id = None;
+                    }
+                }
+                MissingPhoto {
+                    session,
+                    team,
+                    period,
+                    picture,
+                } => {
+                    if let Some(entry) = self.sessions.iter_mut().find(|s| s.id == session) {
+                        let completed =
+                            &mut entry.contents.teams[team].completed_challenges[period];
+                        if completed.photo == Some(picture) {
+                            completed.photo = None;
+                        }
+                        completed.pictures.retain(|&p| p != picture);
+                    }
+                }
+                MissingChallengeSet { challenge, set } => {
+                    if let Some(entry) = self.challenges.iter_mut().find(|c| c.id == challenge) {
+                        entry.contents.sets.remove(&set);
+                    }
+                }
+                MissingChallengeZone { challenge, zone } => {
+                    if let Some(entry) = self.challenges.iter_mut().find(|c| c.id == challenge) {
+                        entry.contents.zone.retain(|&z| z != zone);
+                    }
+                }
+            }
+        }
+        if !problems.is_empty() {
+            self.changes_since_save = true;
+        }
+        problems
+    }
+
+    // Only works on a fresh engine, merging two sets of ids is not worth the trouble.
+    fn import_snapshot(&mut self, snapshot: Snapshot) -> ResponseAction {
+        use commands::Error::*;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return ResponseAction::Error(BadData(format!(
+                "snapshot version {} is not supported, expected {}",
+                snapshot.version, SNAPSHOT_VERSION
+            )));
+        }
+        if !self.is_empty() {
+            return ResponseAction::Error(AlreadyExists);
+        }
+        let contents: SnapshotContents = match ron::from_str(&snapshot.data) {
+            Ok(contents) => contents,
+            Err(err) => return ResponseAction::Error(BadData(err.to_string())),
+        };
+
+        let mut transaction = Transaction::new();
+        let written = vec_overwrite_in_transaction(contents.sessions.clone(), &mut transaction)
+            .and(vec_overwrite_in_transaction(
+                contents.challenges.clone(),
+                &mut transaction,
+            ))
+            .and(vec_overwrite_in_transaction(
+                contents.challenge_sets.clone(),
+                &mut transaction,
+            ))
+            .and(vec_overwrite_in_transaction(
+                contents.zones.clone(),
+                &mut transaction,
+            ))
+            .and(vec_overwrite_in_transaction(
+                contents.players.clone(),
+                &mut transaction,
+            ))
+            .and(vec_overwrite_in_transaction(
+                contents.past_games,
+                &mut transaction,
+            ))
+            .and(vec_overwrite_in_transaction(
+                contents.location_archives,
+                &mut transaction,
+            ));
+        if let Err(err) = written.and_then(|_| transaction.apply(&self.db).map(|_| ())) {
+            eprintln!("Engine: Couldn't write imported snapshot to db: {}", err);
+            return ResponseAction::Error(InternalError);
+        }
+
+        self.sessions = contents.sessions;
+        self.challenges = contents.challenges;
+        self.challenge_sets = contents.challenge_sets;
+        self.zones = contents.zones;
+        self.players = contents.players;
+        self.past_games = PastGame::all(&self.db).headers().unwrap();
+        ResponseAction::Success
+    }
+
+    // `connection` is the client connection the command came from, if any
+    pub fn vroom(
+        &mut self,
+        command: InternEngineCommand,
+        connection: Option<u64>,
+    ) -> InternEngineResponsePackage {
+        let mut package = self.dispatch(command, connection);
+        self.sequence_broadcasts(&mut package);
+        package
+    }
+
+    // Keeps the broadcasts of a response around for `GetRecentBroadcasts`. Locations come in
+    // every few seconds and are outdated by the time anyone asks, so they are left out, as are
+    // pings, which would push the game's events out of the replay.
+    // The numbered broadcasts go out as `SequencedBroadcast` requests, so clients see the same
+    // numbers live as in a replay. The direct broadcast is moved behind the requested ones,
+    // which is the order the runtime used to send them in.
+    fn sequence_broadcasts(&mut self, package: &mut InternEngineResponsePackage) {
+        let direct = match &mut package.response {
+            InternEngineResponse::DirectResponse(response) => response.broadcast_action.take(),
+            InternEngineResponse::DelayedLoopback(_) => None,
+        };
+        let requests: Vec<RuntimeRequest> = package
+            .runtime_requests
+            .take()
+            .into_iter()
+            .flatten()
+            .chain(direct.map(RuntimeRequest::Broadcast))
+            .map(|request| match request {
+                RuntimeRequest::Broadcast(action)
+                    if !matches!(
+                        action,
+                        BroadcastAction::Location { .. } | BroadcastAction::Pinged(_)
+                    ) =>
+                {
+                    RuntimeRequest::SequencedBroadcast(self.record_broadcast(action))
+                }
+                other => other,
+            })
+            .collect();
+        package.runtime_requests = (!requests.is_empty()).then_some(requests);
+    }
+
+    fn record_broadcast(&mut self, action: BroadcastAction) -> SequencedBroadcast {
+        self.broadcast_sequence += 1;
+        if self.recent_broadcasts.len() >= BROADCAST_REPLAY_LENGTH {
+            self.recent_broadcasts.pop_front();
+        }
+        let broadcast = SequencedBroadcast {
+            sequence: self.broadcast_sequence,
+            action,
+        };
+        self.recent_broadcasts.push_back(broadcast.clone());
+        broadcast
+    }
+
+    fn dispatch(
+        &mut self,
+        command: InternEngineCommand,
+        connection: Option<u64>,
+    ) -> InternEngineResponsePackage {
         use commands::Error::*;
         use BroadcastAction::*;
         use EngineAction::*;
@@ -1488,13 +4934,41 @@ impl Engine {
         match command {
             InternEngineCommand::Command(command) => {
                 self.changes_since_save = true;
-                match command.session {
+                let to_log = command.action.is_mutating().then(|| command.clone());
+                let action_name = command.action.name();
+                let touched = command
+                    .session
+                    .and_then(|id| self.sessions.iter().find(|s| s.id == id))
+                    .and_then(|s| s.contents.touched_teams(&command.action));
+                let started = std::time::Instant::now();
+                let package = match command.session {
+                    Some(id) if matches!(command.action, UploadPeriodPictures { .. }) => {
+                        self.upload_period_pictures(id, command.action)
+                    }
+                    Some(id) if matches!(command.action, Complete { .. }) => {
+                        self.complete(id, command.action)
+                    }
+                    Some(id) if matches!(command.action, GetPeriodPath { .. } | GetEvents) => {
+                        self.with_archived_locations(id, command.action)
+                    }
                     Some(id) => match self.sessions.iter_mut().find(|s| s.id == id) {
-                        Some(session) => session.contents.vroom(command.action, id, &self.players),
+                        Some(session) => session.contents.vroom(
+                            command.action,
+                            id,
+                            &EngineContext {
+                                config: &self.config,
+                                player_entries: &self.players,
+                                zone_entries: &self.zones,
+                                challenge_entries: &self.challenges,
+                                scoring: self.scoring.as_ref(),
+                                clock: self.clock.as_ref(),
+                            },
+                            &mut self.timer_tracker,
+                        ),
                         None => Error(NotFound).into()
                     }
                     None => match command.action {
-                        GetRawChallenges => SendRawChallenges(self.challenges.iter().filter_map(|c| c.contents.to_sendable(c.id, &self.challenge_sets, &self.zones).ok()).collect()).into(),
+                        GetRawChallenges => SendRawChallenges(self.challenges.iter().map(|c| c.contents.to_sendable(c.id, &self.challenge_sets, &self.zones)).collect()).into(),
                         SetRawChallenge(challenge) => match challenge.id {
                             Some(id) => {
                                 match self.challenges.iter_mut().find(|c| c.id == id) {
@@ -1517,6 +4991,126 @@ impl Engine {
                             add_into(&mut self.challenges, entry);
                             Success.into()
                         }
+                        GetZone(id) => match self.zones.iter().find(|z| z.id == id) {
+                            None => Error(NotFound).into(),
+                            Some(zone) => SendZones(vec![zone.contents.to_sendable(zone.id)]).into(),
+                        },
+                        GetZoneByNumber(number) => {
+                            match self.zones.iter().find(|z| z.contents.zone == number) {
+                                None => Error(NotFound).into(),
+                                Some(zone) => SendZones(vec![zone.contents.to_sendable(zone.id)]).into(),
+                            }
+                        }
+                        SetZoneDistances { from_zone, distances } => {
+                            if let Some(missing) = distances.keys().find(|&&to| !self.zones.iter().any(|z| z.id == to)) {
+                                Error(BadData(format!("there is no zone with id {}", missing))).into()
+                            } else if distances.contains_key(&from_zone) {
+                                Error(BadData("a zone has no distance to itself".into())).into()
+                            } else {
+                                match self.zones.iter_mut().find(|z| z.id == from_zone) {
+                                    None => Error(NotFound).into(),
+                                    Some(zone) => {
+                                        zone.contents.minutes_to = distances;
+                                        Success.into()
+                                    }
+                                }
+                            }
+                        }
+                        ComputeZoneDistances => FilledZoneDistances(fill_zone_distances(&mut self.zones)).into(),
+                        SetZoneBoundary { zone, boundary } => {
+                            if !boundary.is_empty() && boundary.len() < 3 {
+                                Error(BadData("a boundary needs at least three points".into())).into()
+                            } else {
+                                match self.zones.iter_mut().find(|z| z.id == zone) {
+                                    None => Error(NotFound).into(),
+                                    Some(entry) => {
+                                        entry.contents.boundary = boundary;
+                                        Success.into()
+                                    }
+                                }
+                            }
+                        }
+                        GetZoneAt { location } => SendZones(
+                            nearest_zone(&self.zones, location)
+                                .map(|z| z.contents.to_sendable(z.id))
+                                .into_iter()
+                                .collect(),
+                        )
+                        .into(),
+                        SetZoneCentre { zone, centre } => match self.zones.iter_mut().find(|z| z.id == zone) {
+                            None => Error(NotFound).into(),
+                            Some(entry) => {
+                                entry.contents.centre = centre;
+                                Success.into()
+                            }
+                        },
+                        DeleteZone { zone: id, cascade } => {
+                            // Centre zones and zones that teams are currently in or are sent to
+                            // by their open challenges cannot be deleted. Distances and
+                            // challenges referring to the zone are cleaned up with `cascade`,
+                            // and keep the zone from being deleted without.
+                            let is_centre = |config: &Config| {
+                                config.centre_zone == Some(id) || config.centre_zones.contains(&id)
+                            };
+                            if is_centre(&self.config)
+                                || self
+                                    .sessions
+                                    .iter()
+                                    .any(|s| is_centre(&s.contents.config(&self.config)))
+                            {
+                                Error(BadData(format!("zone {} is a centre zone", id))).into()
+                            } else if !cascade
+                                && (self.zones.iter().any(|z| {
+                                    z.id != id && z.contents.minutes_to.contains_key(&id)
+                                }) || self.challenges.iter().any(|c| c.contents.zone.contains(&id)))
+                            {
+                                Error(BadData(format!(
+                                    "zone {} is still referred to by other zones or challenges",
+                                    id
+                                )))
+                                .into()
+                            } else if let Some(session) = self.sessions.iter().find(|s| {
+                                s.contents.teams.iter().any(|t| {
+                                    t.current_zone_id == Some(id)
+                                        || t.challenges.iter().any(|c| c.zone == Some(id))
+                                })
+                            }) {
+                                Error(BadData(format!(
+                                    "zone {} is still in use by a team in session {}",
+                                    id, session.contents.name
+                                )))
+                                .into()
+                            } else {
+                                match self.zones.iter().position(|z| z.id == id) {
+                                    None => Error(NotFound).into(),
+                                    Some(index) => {
+                                        // autosave only overwrites, so the document has to be
+                                        // removed from the db right away
+                                        match ZoneEntry::get(&id, &self.db) {
+                                            Ok(Some(doc)) => {
+                                                if let Err(err) = doc.delete(&self.db) {
+                                                    eprintln!("Engine: Couldn't delete zone {} from db: {}", id, err);
+                                                    return Error(InternalError).into();
+                                                }
+                                            }
+                                            Ok(None) => (), // zone was never saved
+                                            Err(err) => {
+                                                eprintln!("Engine: Couldn't get zone {} from db: {}", id, err);
+                                                return Error(InternalError).into();
+                                            }
+                                        }
+                                        self.zones.remove(index);
+                                        for zone in &mut self.zones {
+                                            zone.contents.minutes_to.remove(&id);
+                                        }
+                                        for challenge in &mut self.challenges {
+                                            challenge.contents.zone.retain(|z| z != &id);
+                                        }
+                                        Success.into()
+                                    }
+                                }
+                            }
+                        }
                         GetPlayerByPassphrase(passphrase) => {
                             //println!("Engine: getting player by passphrase {}", passphrase);
                             let doc = self
@@ -1574,6 +5168,8 @@ impl Engine {
                                         discord_id,
                                         passphrase,
                                         session,
+                                        language: None,
+                                        ready: false,
                                     }
                                 );
                                 Success.into()
@@ -1640,6 +5236,25 @@ impl Engine {
                                 }
                             }
                         }
+                        SetPlayerLanguage { player, language } => {
+                            match self.players.iter_mut().find(|p| p.id == player) {
+                                None => Error(NotFound).into(),
+                                Some(player) => {
+                                    player.contents.language = language;
+                                    Success.into()
+                                }
+                            }
+                        }
+                        MovePlayer { player, to_session, to_team } => self.move_player(player, to_session, to_team),
+                        SetPlayerReady { player, ready } => {
+                            match self.players.iter_mut().find(|p| p.id == player) {
+                                None => Error(NotFound).into(),
+                                Some(player) => {
+                                    player.contents.ready = ready;
+                                    Success.into()
+                                }
+                            }
+                        }
                         SetPlayerPassphrase { player, passphrase } => {
                             match self.players.iter_mut().find(|p| p.id == player) {
                                 None => Error(NotFound).into(),
@@ -1662,15 +5277,29 @@ impl Engine {
                                 }
                             }
                         }
+                        SetConnectionRole(_) => Error(InternalError).into(),
+                        Authenticate { secret: _ } => Error(InternalError).into(),
                         Ping(payload) => EngineResponse {
                             response_action: Success,
                             broadcast_action: Some(BroadcastAction::Pinged(payload)),
                         }.into(),
+                        Announce { message } => announcement(None, message).into(),
                         GetState => {
                             let sessions = self.sessions.iter().map(|s| s.contents.to_sendable(s.id)).collect();
                             let players = self.players.iter().map(|p| p.contents.to_sendable(p.id)).collect();
                             SendGlobalState { sessions, players }.into()
                         }
+                        GetStatePaged { include_completed: _, include_locations: _, teams_offset: _, teams_limit: _ } => Error(NoSessionSupplied).into(),
+                        GetStateDiff { since_version: _ } => Error(NoSessionSupplied).into(),
+                        GetEvents => Error(NoSessionSupplied).into(),
+                        GetReadiness => Error(NoSessionSupplied).into(),
+                        GetAvailableColours => Error(NoSessionSupplied).into(),
+                        GetTeamCatchStats { team: _ } => Error(NoSessionSupplied).into(),
+                        GetPlayerContributions { team: _ } => Error(NoSessionSupplied).into(),
+                        UploadPeriodPictures { team: _, period: _, pictures: _, token: _ } => Error(NoSessionSupplied).into(),
+                        GetSessionsDetailed => SendSessionsDetailed(
+                            self.sessions.iter().map(|s| s.contents.to_details(s.id, &self.players)).collect()
+                        ).into(),
                         Start => Error(NoSessionSupplied).into(),
                         Stop => Error(NoSessionSupplied).into(),
                         Catch {
@@ -1680,6 +5309,8 @@ impl Engine {
                         Complete {
                             completer: _,
                             completed: _,
+                            pictures: _,
+                            player: _,
                         } => Error(NoSessionSupplied).into(),
                         SendLocation {
                             player: _,
@@ -1695,42 +5326,134 @@ impl Engine {
                         MakeTeamCatcher(_) => Error(NoSessionSupplied).into(),
                         AddChallengeToTeam { team: _, challenge: _ } => Error(NoSessionSupplied).into(),
                         RenameTeam { team: _, new_name: _ } => Error(NoSessionSupplied).into(),
+                        ReloadConfig => match load_config(&self.config_path) {
+                            Ok(config) => {
+                                self.config = config;
+                                Success.into()
+                            }
+                            Err(err) => Error(BadData(err)).into(),
+                        },
+                        ExportSnapshot => self.export_snapshot().into(),
+                        SuggestZoneDistances { min_samples } => self.suggest_zone_distances(min_samples).into(),
+                        ImportSnapshot(snapshot) => self.import_snapshot(snapshot).into(),
+                        CheckIntegrity => SendIntegrityReport(self.integrity_problems()).into(),
+                        RepairIntegrity => SendIntegrityReport(self.repair_integrity()).into(),
+                        GetPictureMetadata(id) => self.get_from_db::<PictureEntry, _, _>(id, |doc| match doc.contents {
+                            PictureEntry::ChallengePicture { metadata, .. } => SendPictureMetadata(metadata).into(),
+                            PictureEntry::Profile { .. } => Error(NotFound).into(),
+                        }).into(),
+                        GetMetrics => SendMetrics(self.metrics.iter().map(|(name, m)| m.to_sendable(name)).collect()).into(),
+                        #[cfg(feature = "debug-time")]
+                        AdvanceTime { minutes } => self.advance_time(minutes),
+                        #[cfg(feature = "debug-locations")]
+                        IngestLocations { team: _, locations: _ } => Error(NoSessionSupplied).into(),
+                        GetRecentBroadcasts { since, limit } => {
+                            let mut broadcasts: Vec<SequencedBroadcast> = self
+                                .recent_broadcasts
+                                .iter()
+                                .filter(|b| since.is_none_or(|since| b.sequence > since))
+                                .cloned()
+                                .collect();
+                            if let Some(limit) = limit {
+                                broadcasts.drain(..broadcasts.len().saturating_sub(limit));
+                            }
+                            SendBroadcasts(broadcasts).into()
+                        }
+                        GetActionLog { session, since } => SendActionLog(
+                            self.action_log
+                                .iter()
+                                .filter(|e| session.is_none() || e.contents.session == session)
+                                .filter(|e| since.is_none_or(|since| e.contents.time >= since))
+                                .map(|e| e.contents.to_sendable(e.id))
+                                .collect(),
+                        ).into(),
+                        IsGameRunning => Error(NoSessionSupplied).into(),
+                        SnapshotScores => Error(NoSessionSupplied).into(),
+                        GetScoreSnapshots => Error(NoSessionSupplied).into(),
+                        SetGameEndTime { new_time: _ } => Error(NoSessionSupplied).into(),
+                        SetSessionMode { mode: _ } => Error(NoSessionSupplied).into(),
+                        GetPeriodPath { team: _, period: _ } => Error(NoSessionSupplied).into(),
+                        GetActiveTimers => SendTimers(self.sessions.iter().flat_map(|s| s.contents.timers().into_iter().map(|t| t.to_sendable(s.id))).collect()).into(),
+                        CancelTimer { id } => match self.sessions.iter_mut().find_map(|s| s.contents.remove_timer(id)) {
+                            None => Error(NotFound).into(),
+                            Some(timer) => timer.cancel_request().into(),
+                        },
+                        SimulateGame { games: _, challenge_set: _ } => Error(NoSessionSupplied).into(),
+                        AnalyzeChallengePoints { challenge_id: _, from_zone: _, samples: _ } => Error(NoSessionSupplied).into(),
+                        GetCandidateChallenges { team: _, period: _ } => Error(NoSessionSupplied).into(),
+                        RefreshChallenges { team: _ } => Error(NoSessionSupplied).into(),
+                        RegenerateChallengesExcept { team: _, keep_indices: _ } => Error(NoSessionSupplied).into(),
                     },
+                };
+                let elapsed = started.elapsed();
+                if elapsed > SLOW_COMMAND {
+                    println!("Engine: {} took {} ms", action_name, elapsed.as_millis());
                 }
-            }
-            InternEngineCommand::AutoSave => {
-                fn vec_overwrite_in_transaction<T>(
-                    entries: Vec<DBEntry<T>>,
-                    transaction: &mut Transaction,
-                ) -> Result<(), bonsaidb::core::Error>
-                where
-                    T: SerializedCollection<Contents = T, PrimaryKey = u64> + 'static,
+                self.metrics.entry(action_name).or_default().record(elapsed);
+                if let (Some(command), InternEngineResponse::DirectResponse(response)) =
+                    (to_log, &package.response)
                 {
-                    let mut ret = Ok(());
-                    for entry in entries {
-                        match entry
-                            .contents
-                            .overwrite_in_transaction(&entry.id, transaction)
-                        {
-                            Ok(()) => (),
-                            Err(err) => {
-                                println!(
-                                    "Engine: something went wrong during overwrite_in_transaction: {}",
-                                    err
-                                );
-                                ret = Err(err);
+                    if !matches!(response.response_action, Error(_)) {
+                        match command.session {
+                            Some(id) => {
+                                if let Some(session) = self.sessions.iter_mut().find(|s| s.id == id)
+                                {
+                                    session.contents.mark_changed(touched);
+                                }
                             }
+                            // global commands like renaming a player can show up in any
+                            // session, so they count as changing everything
+                            None => self
+                                .sessions
+                                .iter_mut()
+                                .for_each(|s| s.contents.mark_changed(None)),
+                        }
+                        self.log_action(command, connection);
+                    }
+                }
+                package
+            }
+            InternEngineCommand::CheckStationary => {
+                let mut requests = vec![self.timer_tracker.timer(
+                    STATIONARY_CHECK_INTERVAL,
+                    InternEngineCommand::CheckStationary,
+                )];
+                // only when a team stops moving, not again on every check while it stays put
+                for session in self
+                    .sessions
+                    .iter_mut()
+                    .filter(|s| s.contents.game.is_some())
+                {
+                    let config = session.contents.config(&self.config);
+                    for (index, team) in session.contents.teams.iter_mut().enumerate() {
+                        let minutes = team.stationary_minutes(&config);
+                        let stationary = minutes >= config.stationary_minutes;
+                        if stationary && !team.stationary {
+                            requests.push(RuntimeRequest::Broadcast(TeamStationary {
+                                session: session.id,
+                                team: index,
+                                minutes,
+                            }));
                         }
+                        team.stationary = stationary;
                     }
-                    ret
                 }
+                InternEngineResponsePackage {
+                    response: Success.into(),
+                    runtime_requests: Some(requests),
+                }
+            }
+            InternEngineCommand::AutoSave => {
                 if self.changes_since_save {
+                    self.offload_locations();
                     let players = self.players.clone();
                     let db = self.db.clone();
                     let sessions = self.sessions.clone();
                     let challenges = self.challenges.clone();
                     let challenge_sets = self.challenge_sets.clone();
                     let zones = self.zones.clone();
+                    let timer_tracker = self.timer_tracker.clone();
+                    let action_log = self.action_log.clone();
                     self.changes_since_save = false;
 
                     InternEngineResponsePackage {
@@ -1746,6 +5469,14 @@ impl Engine {
                                 let _ =
                                     vec_overwrite_in_transaction(challenge_sets, &mut transaction);
                                 let _ = vec_overwrite_in_transaction(zones, &mut transaction);
+                                let _ = vec_overwrite_in_transaction(action_log, &mut transaction);
+                                let _ = vec_overwrite_in_transaction(
+                                    vec![DBEntry {
+                                        id: TimerTracker::DB_ID,
+                                        contents: timer_tracker,
+                                    }],
+                                    &mut transaction,
+                                );
 
                                 match transaction.apply(&db) {
                                     Ok(yay) => println!(
@@ -1766,13 +5497,872 @@ impl Engine {
                     }
                 } else {
                     println!("Engine: Autosave requested, but no changes since last save");
-                    RuntimeRequest::CreateTimer {
-                        duration: Duration::from_secs(10),
-                        payload: InternEngineCommand::AutoSave,
-                    }
-                    .into()
+                    self.timer_tracker
+                        .timer(Duration::from_secs(10), InternEngineCommand::AutoSave)
+                        .into()
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge_entry(kind: ChallengeType) -> ChallengeEntry {
+        ChallengeEntry {
+            kind,
+            sets: Default::default(),
+            status: ChallengeStatus::Approved,
+            title: Some("Test".into()),
+            description: Some("Test".into()),
+            random_place: None,
+            place: None,
+            comment: String::new(),
+            kaffskala: None,
+            grade: None,
+            zone: Vec::new(),
+            bias_sat: 1.0,
+            bias_sun: 1.0,
+            walking_time: 0,
+            stationary_time: 0,
+            additional_points: 100,
+            repetitions: 0..1,
+            points_per_rep: 0,
+            station_distance: 0,
+            time_to_hb: 0,
+            departures: 0,
+            dead_end: false,
+            no_disembark: false,
+            requires_photo: false,
+            requires_completed: Vec::new(),
+            exclusive_group: None,
+            fixed: true,
+            in_perimeter_override: None,
+            translated_titles: HashMap::new(),
+            translated_descriptions: HashMap::new(),
+            action: None,
+            last_edit: chrono::Local::now(),
+        }
+    }
+
+    fn context<'a>(
+        config: &'a Config,
+        zones: &'a [DBEntry<ZoneEntry>],
+        challenges: &'a [DBEntry<ChallengeEntry>],
+        clock: &'a FixedClock,
+    ) -> EngineContext<'a> {
+        EngineContext {
+            config,
+            player_entries: &[],
+            zone_entries: zones,
+            challenge_entries: challenges,
+            scoring: &DefaultScoring,
+            clock,
+        }
+    }
+
+    fn score(challenge: &ChallengeEntry, config: &Config) -> i64 {
+        DefaultScoring
+            .score(challenge, 0, None, None, config, &[])
+            .total()
+    }
+
+    #[test]
+    fn dead_end_points() {
+        let plain = challenge_entry(ChallengeType::Ortsspezifisch);
+        let dead_end = ChallengeEntry {
+            dead_end: true,
+            ..plain.clone()
+        };
+        let mut config = Config::default();
+        assert_eq!(score(&dead_end, &config), score(&plain, &config));
+        config.score_dead_ends = true;
+        assert_eq!(
+            score(&dead_end, &config),
+            score(&plain, &config) + config.points_for_dead_end as i64
+        );
+    }
+
+    struct FlatScoring(i64);
+
+    impl ScoringStrategy for FlatScoring {
+        fn score(
+            &self,
+            _challenge: &ChallengeEntry,
+            _reps: u16,
+            _zone: Option<&DBEntry<ZoneEntry>>,
+            _from_zone: Option<u64>,
+            _config: &Config,
+            _zones: &[DBEntry<ZoneEntry>],
+        ) -> PointBreakdown {
+            PointBreakdown {
+                additional: self.0,
+                ..Default::default()
+            }
+        }
+    }
+
+    #[test]
+    fn custom_scoring_strategy() {
+        let config = Config::default();
+        let clock = FixedClock(chrono::Local::now());
+        let context = EngineContext {
+            scoring: &FlatScoring(1234),
+            ..context(&config, &[], &[], &clock)
+        };
+        let challenge = challenge_entry(ChallengeType::Unspezifisch)
+            .challenge(1, &config, false, None, &context, None);
+        assert_eq!(challenge.points, 1234);
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn generation_period_transitions() {
+        // 09:00 to 17:00, specific for 15 minutes, perimeter, zkaff and end game from 13:30
+        let config = Config::default();
+        assert_eq!(
+            generation_period(&config, time(9, 10)),
+            GenerationPeriod::Specific
+        );
+        assert_eq!(
+            generation_period(&config, time(9, 15)),
+            GenerationPeriod::Normal
+        );
+        assert_eq!(
+            generation_period(&config, time(13, 29)),
+            GenerationPeriod::Normal
+        );
+        assert_eq!(
+            generation_period(&config, time(13, 30)),
+            GenerationPeriod::Perimeter
+        );
+        assert_eq!(
+            generation_period(&config, time(15, 0)),
+            GenerationPeriod::ZKaff
+        );
+        assert_eq!(
+            generation_period(&config, time(16, 30)),
+            GenerationPeriod::EndGame
+        );
+    }
+
+    fn locked_challenge(action: ChallengeActionEntry, clock: &FixedClock) -> InOpenChallenge {
+        let config = Config::default();
+        let entry = ChallengeEntry {
+            action: Some(action),
+            ..challenge_entry(ChallengeType::Unspezifisch)
+        };
+        entry.challenge(
+            1,
+            &config,
+            false,
+            None,
+            &context(&config, &[], &[], clock),
+            None,
+        )
+    }
+
+    #[test]
+    fn completable_by_clock() {
+        let now = chrono::Local::now();
+        let challenge = locked_challenge(
+            ChallengeActionEntry::UncompletableMinutes(Some(30)),
+            &FixedClock(now),
+        );
+        assert!(!challenge.completable_by(now));
+        assert!(!challenge.completable_by(now + chrono::TimeDelta::minutes(30)));
+        assert!(challenge.completable_by(now + chrono::TimeDelta::minutes(31)));
+        let later = FixedClock(now + chrono::TimeDelta::minutes(31));
+        assert!(!challenge.to_sendable(&FixedClock(now)).completable);
+        assert!(challenge.to_sendable(&later).completable);
+    }
+
+    #[test]
+    fn too_rapid_completion_is_rejected() {
+        let now = chrono::Local::now();
+        let config = Config::default();
+        let mut team = test_team();
+        team.challenges.push(locked_challenge(
+            ChallengeActionEntry::UncompletableMinutes(Some(30)),
+            &FixedClock(now),
+        ));
+
+        let early = FixedClock(now + chrono::TimeDelta::minutes(10));
+        assert_eq!(
+            team.complete_challenge(0, &config, &context(&config, &[], &[], &early))
+                .map(|_| ()),
+            Err(commands::Error::ChallengeNotYetCompletable {
+                completable_at: now + chrono::TimeDelta::minutes(30)
+            })
+        );
+        assert_eq!(team.points, 0);
+
+        let late = FixedClock(now + chrono::TimeDelta::minutes(31));
+        assert!(team
+            .complete_challenge(0, &config, &context(&config, &[], &[], &late))
+            .is_ok());
+        assert_eq!(team.points, 100);
+    }
+
+    fn local(hour: u32, minute: u32) -> chrono::DateTime<chrono::Local> {
+        chrono::NaiveDate::from_ymd_opt(2026, 5, 16)
+            .unwrap()
+            .and_time(time(hour, minute))
+            .and_local_timezone(chrono::Local)
+            .unwrap()
+    }
+
+    // 20:00 to 01:00 the next morning
+    fn evening_config() -> Config {
+        Config {
+            start_time: time(20, 0),
+            end_time: time(1, 0),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn generation_period_midnight_wrap() {
+        let config = evening_config();
+        assert_eq!(
+            generation_period(&config, time(20, 5)),
+            GenerationPeriod::Specific
+        );
+        assert_eq!(
+            generation_period(&config, time(21, 0)),
+            GenerationPeriod::Normal
+        );
+        assert_eq!(
+            generation_period(&config, time(22, 0)),
+            GenerationPeriod::Perimeter
+        );
+        assert_eq!(
+            generation_period(&config, time(23, 30)),
+            GenerationPeriod::ZKaff
+        );
+        assert_eq!(
+            generation_period(&config, time(0, 45)),
+            GenerationPeriod::EndGame
+        );
+    }
+
+    fn end_time(session: &Session) -> chrono::DateTime<chrono::Local> {
+        session
+            .game
+            .as_ref()
+            .and_then(|g| g.timer.as_ref())
+            .map(|t| t.end_time)
+            .expect("no end timer")
+    }
+
+    fn started_game(
+        config: &Config,
+        clock: &FixedClock,
+        timer_tracker: &mut TimerTracker,
+    ) -> Session {
+        let mut session = Session::new("Test".into(), Mode::Traditional);
+        session.vroom(
+            EngineAction::Start,
+            1,
+            &context(config, &[], &[], clock),
+            timer_tracker,
+        );
+        session
+    }
+
+    #[test]
+    fn evening_game_ends_next_morning() {
+        let config = evening_config();
+        let clock = FixedClock(local(20, 30));
+        let session = started_game(&config, &clock, &mut TimerTracker::default());
+        assert_eq!(
+            end_time(&session) - clock.0,
+            chrono::TimeDelta::minutes(4 * 60 + 30)
+        );
+    }
+
+    #[test]
+    fn evening_game_started_after_midnight_ends_same_night() {
+        let config = evening_config();
+        let clock = FixedClock(local(0, 30));
+        let session = started_game(&config, &clock, &mut TimerTracker::default());
+        assert_eq!(end_time(&session), local(1, 0));
+    }
+
+    #[test]
+    fn day_game_is_never_rolled_over() {
+        // 09:00 to 17:00 doesn't cross midnight, so a late start doesn't move the end to tomorrow
+        let config = Config::default();
+        let clock = FixedClock(local(17, 30));
+        let session = started_game(&config, &clock, &mut TimerTracker::default());
+        assert_eq!(end_time(&session), local(17, 0));
+    }
+
+    fn set_game_end_time(
+        session: &mut Session,
+        new_time: NaiveTime,
+        config: &Config,
+        clock: &FixedClock,
+        timer_tracker: &mut TimerTracker,
+    ) -> ResponseAction {
+        let package = session.vroom(
+            EngineAction::SetGameEndTime { new_time },
+            1,
+            &context(config, &[], &[], clock),
+            timer_tracker,
+        );
+        match package.response {
+            InternEngineResponse::DirectResponse(response) => response.response_action,
+            InternEngineResponse::DelayedLoopback(_) => panic!("no direct response"),
+        }
+    }
+
+    #[test]
+    fn passed_end_time_is_rejected() {
+        let config = Config::default();
+        let mut timer_tracker = TimerTracker::default();
+        let mut session = started_game(&config, &FixedClock(local(9, 5)), &mut timer_tracker);
+        // shortening the game to a time that's already over doesn't make it run until tomorrow
+        let later = FixedClock(local(16, 30));
+        let response = set_game_end_time(
+            &mut session,
+            time(16, 0),
+            &config,
+            &later,
+            &mut timer_tracker,
+        );
+        assert!(matches!(
+            response,
+            ResponseAction::Error(commands::Error::BadData(_))
+        ));
+        assert_eq!(end_time(&session), local(17, 0));
+        let response = set_game_end_time(
+            &mut session,
+            time(16, 45),
+            &config,
+            &later,
+            &mut timer_tracker,
+        );
+        assert!(matches!(response, ResponseAction::Success));
+        assert_eq!(end_time(&session), local(16, 45));
+    }
+
+    #[test]
+    fn end_time_after_midnight_in_evening_game() {
+        let config = evening_config();
+        let mut timer_tracker = TimerTracker::default();
+        let mut session = started_game(&config, &FixedClock(local(20, 30)), &mut timer_tracker);
+        let later = FixedClock(local(23, 0));
+        let response = set_game_end_time(
+            &mut session,
+            time(0, 30),
+            &config,
+            &later,
+            &mut timer_tracker,
+        );
+        assert!(matches!(response, ResponseAction::Success));
+        assert_eq!(
+            end_time(&session),
+            local(0, 30) + chrono::TimeDelta::days(1)
+        );
+        // still the evening the game started, which is over
+        let response = set_game_end_time(
+            &mut session,
+            time(22, 0),
+            &config,
+            &later,
+            &mut timer_tracker,
+        );
+        assert!(matches!(
+            response,
+            ResponseAction::Error(commands::Error::BadData(_))
+        ));
+    }
+
+    fn test_team() -> TeamEntry {
+        TeamEntry::new("Test".into(), Vec::new(), None, Colour { r: 0, g: 0, b: 0 })
+    }
+
+    #[test]
+    fn exclusive_group_offered_once() {
+        let config = Config::default();
+        let clock = FixedClock(chrono::Local::now());
+        let challenges: Vec<DBEntry<ChallengeEntry>> = (1..=5)
+            .map(|id| DBEntry {
+                id,
+                contents: ChallengeEntry {
+                    exclusive_group: (id <= 2).then_some(7),
+                    ..challenge_entry(ChallengeType::Unspezifisch)
+                },
+            })
+            .collect();
+        let context = context(&config, &[], &challenges, &clock);
+        for _ in 0..50 {
+            let mut team = test_team();
+            team.generate_challenges(&config, GenerationPeriod::Normal, &context);
+            assert_eq!(team.challenges.len(), 3);
+            let grouped = team
+                .challenges
+                .iter()
+                .filter(|c| matches!(c.id, Some(1) | Some(2)))
+                .count();
+            assert!(grouped <= 1);
+        }
+    }
+
+    #[test]
+    fn thousands_separators() {
+        assert_eq!(group_thousands(0, None), "0");
+        assert_eq!(group_thousands(999, None), "999");
+        assert_eq!(group_thousands(1000, None), "1'000");
+        assert_eq!(group_thousands(1234567, None), "1'234'567");
+        assert_eq!(group_thousands(1234567, Some("de")), "1'234'567");
+        assert_eq!(group_thousands(1234567, Some("en")), "1,234,567");
+    }
+
+    #[test]
+    fn description_placeholders() {
+        let config = Config::default();
+        let clock = FixedClock(local(14, 10));
+        let entry = ChallengeEntry {
+            description: Some("%r, %n, %t".into()),
+            repetitions: 1500..1501,
+            ..challenge_entry(ChallengeType::Unspezifisch)
+        };
+        let locked = ChallengeEntry {
+            description: Some("bis %t".into()),
+            action: Some(ChallengeActionEntry::UncompletableMinutes(Some(30))),
+            ..challenge_entry(ChallengeType::Unspezifisch)
+        };
+        let context = context(&config, &[], &[], &clock);
+        // without a lock, %t is %r minutes from now
+        let challenge = entry.challenge(1, &config, false, None, &context, None);
+        assert_eq!(challenge.description, "1500, 1'500, 15:10");
+        let challenge = entry.challenge(1, &config, false, None, &context, Some("en"));
+        assert_eq!(challenge.description, "1500, 1,500, 15:10");
+        let challenge = locked.challenge(2, &config, false, None, &context, None);
+        assert_eq!(challenge.description, "bis 14:40");
+    }
+
+    #[test]
+    fn kept_challenge_survives_regeneration() {
+        let config = Config::default();
+        let clock = FixedClock(chrono::Local::now());
+        let challenges: Vec<DBEntry<ChallengeEntry>> = (1..=6)
+            .map(|id| DBEntry {
+                id,
+                contents: challenge_entry(ChallengeType::Unspezifisch),
+            })
+            .collect();
+        let mut team = test_team();
+        team.generate_challenges(
+            &config,
+            GenerationPeriod::Normal,
+            &context(&config, &[], &challenges[..3], &clock),
+        );
+        let kept = team.challenges[1].clone();
+        team.generate_challenges_keeping(
+            &config,
+            GenerationPeriod::Normal,
+            &context(&config, &[], &challenges, &clock),
+            &[],
+            vec![kept.clone()],
+        );
+        assert_eq!(team.challenges.len(), 3);
+        assert_eq!(team.challenges[0].id, kept.id);
+        // the open challenges count as used, so the other slots get new ones
+        assert!(team.challenges[1..]
+            .iter()
+            .all(|c| c.id.is_some_and(|id| id > 3)));
+    }
+
+    // How long the recreated end of game timer waits after a restart at `now`
+    fn recreated_end_timer(session: &Session, now: chrono::DateTime<chrono::Local>) -> Duration {
+        let id = session
+            .game
+            .as_ref()
+            .and_then(|g| g.timer.as_ref())
+            .unwrap()
+            .id;
+        session
+            .setup(now)
+            .into_iter()
+            .find_map(|request| match request {
+                RuntimeRequest::CreateTimer {
+                    id: timer,
+                    duration,
+                    payload:
+                        InternEngineCommand::Command(EngineCommand {
+                            session: Some(1),
+                            action: EngineAction::Stop,
+                        }),
+                } if timer == id => Some(duration),
+                _ => None,
+            })
+            .expect("end timer not recreated")
+    }
+
+    #[test]
+    fn restart_before_end_waits_past_midnight() {
+        let config = evening_config();
+        let clock = FixedClock(local(20, 30));
+        let session = started_game(&config, &clock, &mut TimerTracker::default());
+        let restart = local(23, 0);
+        assert_eq!(
+            recreated_end_timer(&session, restart),
+            Duration::from_secs(2 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn restart_after_end_stops_immediately() {
+        let config = evening_config();
+        let clock = FixedClock(local(20, 30));
+        let session = started_game(&config, &clock, &mut TimerTracker::default());
+        // the game ended at 01:00, the engine only came back at 02:00
+        let restart = local(2, 0) + chrono::TimeDelta::days(1);
+        assert_eq!(recreated_end_timer(&session, restart), Duration::ZERO);
+    }
+
+    #[test]
+    fn restart_after_end_of_day_game() {
+        let config = Config::default();
+        let clock = FixedClock(local(9, 5));
+        let session = started_game(&config, &clock, &mut TimerTracker::default());
+        assert_eq!(
+            recreated_end_timer(&session, local(16, 0)),
+            Duration::from_secs(60 * 60)
+        );
+        assert_eq!(recreated_end_timer(&session, local(17, 30)), Duration::ZERO);
+    }
+
+    fn zone_entry(id: u64, zone: u64, minutes_to: &[(u64, u64)]) -> DBEntry<ZoneEntry> {
+        DBEntry {
+            id,
+            contents: ZoneEntry {
+                zone,
+                num_conn_zones: 0,
+                num_connections: 0,
+                train_through: false,
+                mongus: false,
+                s_bahn_zone: false,
+                minutes_to: minutes_to.iter().copied().collect(),
+                centre: None,
+                boundary: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn perimeter_override_includes_far_challenge() {
+        let config = Config {
+            centre_zone: Some(1),
+            num_challenges: 1,
+            challenge_fallbacks: Vec::new(),
+            ..Config::default()
+        };
+        let clock = FixedClock(chrono::Local::now());
+        // zone 2 is five hours from the centre, far outside the perimeter
+        let zones = [
+            zone_entry(1, 110, &[(2, 300)]),
+            zone_entry(2, 170, &[(1, 300)]),
+        ];
+        let far = ChallengeEntry {
+            zone: vec![2],
+            ..challenge_entry(ChallengeType::Ortsspezifisch)
+        };
+        let challenges = [
+            DBEntry {
+                id: 10,
+                contents: ChallengeEntry {
+                    in_perimeter_override: Some(true),
+                    ..far.clone()
+                },
+            },
+            DBEntry {
+                id: 11,
+                contents: far,
+            },
+        ];
+        let context = context(&config, &zones, &challenges, &clock);
+        for _ in 0..10 {
+            let mut team = test_team();
+            team.generate_challenges(&config, GenerationPeriod::Perimeter, &context);
+            assert_eq!(team.generation_failed, None);
+            assert_eq!(team.challenges.len(), 1);
+            assert_eq!(team.challenges[0].id, Some(10));
+        }
+    }
+
+    #[test]
+    fn negative_points_clamped_to_zero() {
+        let config = Config::default();
+        let clock = FixedClock(chrono::Local::now());
+        let context = context(&config, &[], &[], &clock);
+        let entry = ChallengeEntry {
+            additional_points: -30000,
+            fixed: false,
+            ..challenge_entry(ChallengeType::Unspezifisch)
+        };
+        assert!(score(&entry, &config) < 0);
+        for _ in 0..10 {
+            let challenge = entry.challenge(1, &config, false, None, &context, None);
+            assert_eq!(challenge.points, 0);
+        }
+    }
+
+    #[test]
+    fn trap_completable_after_unlock() {
+        let now = chrono::Local::now();
+        let config = Config::default();
+        let mut team = test_team();
+        let trap = ChallengeActionEntry::Trap {
+            stuck_minutes: Some(20),
+            catcher_message: Some("bait".into()),
+        };
+        team.challenges
+            .push(locked_challenge(trap, &FixedClock(now)));
+
+        let early = FixedClock(now + chrono::TimeDelta::minutes(19));
+        assert_eq!(
+            team.complete_challenge(0, &config, &context(&config, &[], &[], &early))
+                .map(|_| ()),
+            Err(commands::Error::ChallengeNotYetCompletable {
+                completable_at: now + chrono::TimeDelta::minutes(20)
+            })
+        );
+        assert_eq!(team.challenges.len(), 1);
+        assert!(team.completed_challenges.is_empty());
+
+        let late = FixedClock(now + chrono::TimeDelta::minutes(21));
+        let completed = team
+            .complete_challenge(0, &config, &context(&config, &[], &[], &late))
+            .unwrap();
+        assert_eq!(completed.id, Some(1));
+        assert_eq!(team.completed_challenges.len(), 1);
+        assert_eq!(team.points, 100);
+    }
+
+    fn timer_ids(package: InternEngineResponsePackage) -> Vec<u64> {
+        package
+            .runtime_requests
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|request| match request {
+                RuntimeRequest::CreateTimer { id, .. } => Some(id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn command(session: Option<u64>, action: EngineAction) -> InternEngineCommand {
+        InternEngineCommand::Command(EngineCommand { session, action })
+    }
+
+    #[tokio::test]
+    async fn timer_ids_not_reused_after_restart() {
+        let dir = test_dir("timer");
+        let storage_path = dir.join("db");
+        // doesn't exist, so the default config is used
+        let config_path = dir.join("config.ron");
+
+        let mut engine = Engine::init(&storage_path, &config_path);
+        engine.setup();
+        engine.vroom(
+            command(
+                None,
+                EngineAction::AddSession {
+                    name: "Test".into(),
+                    mode: Mode::Traditional,
+                },
+            ),
+            None,
+        );
+        let session = engine.sessions[0].id;
+        engine.vroom(command(Some(session), EngineAction::Start), None);
+        let active = engine.sessions[0]
+            .contents
+            .game
+            .as_ref()
+            .and_then(|g| g.timer.as_ref())
+            .map(|t| t.id)
+            .expect("no end timer");
+        let issued = engine.timer_tracker.current_id;
+
+        let Some(RuntimeRequest::RawLoopback(save)) = engine
+            .vroom(InternEngineCommand::AutoSave, None)
+            .runtime_requests
+            .and_then(|mut r| r.pop())
+        else {
+            panic!("nothing to save");
+        };
+        // the save sleeps a while after writing, there's no need to wait for that
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while TimerTracker::load(&engine.db).current_id != issued {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the autosave never wrote the timer tracker");
+        save.abort();
+        let _ = save.await;
+        drop(engine);
+
+        let mut engine = Engine::init(&storage_path, &config_path);
+        let recreated = timer_ids(engine.setup());
+        assert!(recreated.contains(&active));
+        let new = timer_ids(engine.vroom(
+            command(
+                Some(session),
+                EngineAction::SetGameEndTime {
+                    new_time: NaiveTime::from_hms_opt(23, 59, 0).unwrap(),
+                },
+            ),
+            None,
+        ));
+        assert!(!new.is_empty());
+        assert!(new.iter().all(|id| *id > issued));
+        drop(engine);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    // A fresh directory for an engine's db and config
+    fn test_dir(name: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        std::env::temp_dir().join(format!(
+            "truinlag-{}-test-{}-{}",
+            name,
+            std::process::id(),
+            nanos
+        ))
+    }
+
+    fn direct_response(package: InternEngineResponsePackage) -> ResponseAction {
+        match package.response {
+            InternEngineResponse::DirectResponse(response) => response.response_action,
+            InternEngineResponse::DelayedLoopback(_) => panic!("no direct response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn travel_times_from_ended_game() {
+        let dir = test_dir("travel");
+        let mut engine = Engine::init(&dir.join("db"), &dir.join("config.ron"));
+        // configured as 30 minutes apart
+        engine.zones = vec![
+            zone_entry(1, 110, &[(2, 30)]),
+            zone_entry(2, 170, &[(1, 30)]),
+        ];
+        engine.clock = Box::new(FixedClock(local(9, 30)));
+        engine.vroom(
+            command(
+                None,
+                EngineAction::AddSession {
+                    name: "Test".into(),
+                    mode: Mode::Traditional,
+                },
+            ),
+            None,
+        );
+        let session = engine.sessions[0].id;
+        engine.vroom(
+            command(
+                Some(session),
+                EngineAction::AddTeam {
+                    name: "Test".into(),
+                    discord_channel: None,
+                    colour: None,
+                },
+            ),
+            None,
+        );
+        engine.vroom(command(Some(session), EngineAction::Start), None);
+
+        // a challenge in zone 1 at 10:00, then one in zone 2 at 10:40
+        for (zone, minute) in [(1, 0), (2, 40)] {
+            let clock = FixedClock(local(10, minute));
+            let config = engine.config.clone();
+            let challenge = ChallengeEntry {
+                zone: vec![zone],
+                ..challenge_entry(ChallengeType::Ortsspezifisch)
+            }
+            .challenge(
+                zone,
+                &config,
+                false,
+                None,
+                &context(&config, &engine.zones, &[], &clock),
+                None,
+            );
+            engine.sessions[0].contents.teams[0].challenges = vec![challenge];
+            engine.clock = Box::new(clock);
+            let completed = engine.vroom(
+                command(
+                    Some(session),
+                    EngineAction::Complete {
+                        completer: 0,
+                        completed: 0,
+                        pictures: Vec::new(),
+                        player: None,
+                    },
+                ),
+                None,
+            );
+            assert!(matches!(
+                direct_response(completed),
+                ResponseAction::SendPeriodSummary(_)
+            ));
+        }
+
+        let suggest = |engine: &mut Engine| match direct_response(engine.vroom(
+            command(None, EngineAction::SuggestZoneDistances { min_samples: 1 }),
+            None,
+        )) {
+            ResponseAction::SendTravelTimeSuggestions(suggestions) => suggestions,
+            other => panic!("unexpected response {:?}", other),
+        };
+        // the game is still running
+        assert!(suggest(&mut engine).is_empty());
+        engine.vroom(command(Some(session), EngineAction::Stop), None);
+        let suggestions = suggest(&mut engine);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!((suggestions[0].from_zone, suggestions[0].to_zone), (1, 2));
+        assert_eq!(suggestions[0].configured_minutes, Some(30));
+        assert_eq!(suggestions[0].observed_minutes, 40);
+        assert_eq!(suggestions[0].samples, 1);
+        drop(engine);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn pings_left_out_of_replay() {
+        let dir = test_dir("ping");
+        let mut engine = Engine::init(&dir.join("db"), &dir.join("config.ron"));
+        let pinged = engine.vroom(command(None, EngineAction::Ping(None)), None);
+        assert!(matches!(
+            pinged.runtime_requests.as_deref(),
+            Some([RuntimeRequest::Broadcast(BroadcastAction::Pinged(None))])
+        ));
+        assert!(engine.recent_broadcasts.is_empty());
+        engine.vroom(
+            command(
+                None,
+                EngineAction::Announce {
+                    message: "Test".into(),
+                },
+            ),
+            None,
+        );
+        assert_eq!(engine.recent_broadcasts.len(), 1);
+        drop(engine);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,174 @@
+// Just enough of a TIFF reader to get the capture time and place out of the EXIF chunk of a
+// JPEG. Anything unexpected makes the affected field `None` instead of failing the upload.
+use crate::PictureMetadata;
+
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const DATE_TIME: u16 = 0x0132;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const GPS_LATITUDE_REF: u16 = 1;
+const GPS_LATITUDE: u16 = 2;
+const GPS_LONGITUDE_REF: u16 = 3;
+const GPS_LONGITUDE: u16 = 4;
+
+struct Entry {
+    tag: u16,
+    format: u16,
+    count: u32,
+    value: usize, // position of the value, which is stored inline if it fits in 4 bytes
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(0..4)? {
+            [0x49, 0x49, 42, 0] => true,
+            [0x4d, 0x4d, 0, 42] => false,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            little_endian,
+        })
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn first_ifd(&self) -> Option<usize> {
+        Some(self.u32(4)? as usize)
+    }
+
+    fn entries(&self, ifd: usize) -> Option<Vec<Entry>> {
+        let count = self.u16(ifd)? as usize;
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let at = ifd + 2 + i * 12;
+            let format = self.u16(at + 2)?;
+            let count = self.u32(at + 4)?;
+            let size = match format {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                _ => continue,
+            } * count as usize;
+            let value = if size <= 4 {
+                at + 8
+            } else {
+                self.u32(at + 8)? as usize
+            };
+            entries.push(Entry {
+                tag: self.u16(at)?,
+                format,
+                count,
+                value,
+            });
+        }
+        Some(entries)
+    }
+
+    fn ascii(&self, entry: &Entry) -> Option<&'a str> {
+        if entry.format != 2 {
+            return None;
+        }
+        let bytes = self
+            .data
+            .get(entry.value..entry.value + entry.count as usize)?;
+        std::str::from_utf8(bytes)
+            .ok()
+            .map(|s| s.trim_end_matches('\0'))
+    }
+
+    fn rational(&self, at: usize) -> Option<f64> {
+        let denominator = self.u32(at + 4)?;
+        if denominator == 0 {
+            return None;
+        }
+        Some(self.u32(at)? as f64 / denominator as f64)
+    }
+
+    // degrees, minutes and seconds as three rationals
+    fn degrees(&self, entry: &Entry) -> Option<f64> {
+        if entry.format != 5 || entry.count != 3 {
+            return None;
+        }
+        Some(
+            self.rational(entry.value)?
+                + self.rational(entry.value + 8)? / 60.0
+                + self.rational(entry.value + 16)? / 3600.0,
+        )
+    }
+
+    fn offset(&self, entry: &Entry) -> Option<usize> {
+        match entry.format {
+            4 => Some(self.u32(entry.value)? as usize),
+            _ => None,
+        }
+    }
+}
+
+fn find(entries: &[Entry], tag: u16) -> Option<&Entry> {
+    entries.iter().find(|e| e.tag == tag)
+}
+
+fn parse_time(text: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::parse_from_str(text, "%Y:%m:%d %H:%M:%S").ok()
+}
+
+// `data` is the EXIF chunk starting at the TIFF header
+pub(crate) fn parse(data: &[u8]) -> PictureMetadata {
+    let Some(tiff) = Tiff::new(data) else {
+        return PictureMetadata::default();
+    };
+    let Some(ifd0) = tiff.first_ifd().and_then(|ifd| tiff.entries(ifd)) else {
+        return PictureMetadata::default();
+    };
+
+    let taken_at = find(&ifd0, EXIF_IFD)
+        .and_then(|e| tiff.offset(e))
+        .and_then(|ifd| tiff.entries(ifd))
+        .and_then(|exif| find(&exif, DATE_TIME_ORIGINAL).and_then(|e| tiff.ascii(e)))
+        .and_then(parse_time)
+        .or_else(|| {
+            find(&ifd0, DATE_TIME)
+                .and_then(|e| tiff.ascii(e))
+                .and_then(parse_time)
+        });
+
+    let location = find(&ifd0, GPS_IFD)
+        .and_then(|e| tiff.offset(e))
+        .and_then(|ifd| tiff.entries(ifd))
+        .and_then(|gps| {
+            let mut latitude = tiff.degrees(find(&gps, GPS_LATITUDE)?)?;
+            let mut longitude = tiff.degrees(find(&gps, GPS_LONGITUDE)?)?;
+            if find(&gps, GPS_LATITUDE_REF).and_then(|e| tiff.ascii(e)) == Some("S") {
+                latitude = -latitude;
+            }
+            if find(&gps, GPS_LONGITUDE_REF).and_then(|e| tiff.ascii(e)) == Some("W") {
+                longitude = -longitude;
+            }
+            Some((latitude, longitude))
+        });
+
+    PictureMetadata { taken_at, location }
+}
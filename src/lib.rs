@@ -1,8 +1,9 @@
-use image::{DynamicImage, ImageFormat};
+use image::{DynamicImage, ImageDecoder, ImageFormat};
 use serde::{Deserialize, Serialize};
 
 pub mod api;
 pub mod commands;
+mod exif;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct Colour {
@@ -11,6 +12,62 @@ pub struct Colour {
     pub b: u8,
 }
 
+impl Colour {
+    // `#RRGGBB`, the `#` may be left out
+    pub fn from_hex(text: &str) -> Result<Colour, TextError> {
+        let hex = text.trim().strip_prefix('#').unwrap_or(text.trim());
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(TextError(format!(
+                "{:?} is not a colour, expected #RRGGBB",
+                text
+            )));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Colour {
+            r: channel(0),
+            g: channel(2),
+            b: channel(4),
+        })
+    }
+
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl std::str::FromStr for Colour {
+    type Err = TextError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Colour::from_hex(s)
+    }
+}
+
+impl std::fmt::Display for Colour {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+// The configured team colours of a session, split by whether a team already has them.
+// `add_team` without a colour takes the first available one.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ColourPalette {
+    pub used: Vec<Colour>,
+    pub available: Vec<Colour>,
+}
+
+// Text that couldn't be parsed into one of the types here
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextError(pub String);
+
+impl std::fmt::Display for TextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TextError {}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Picture {
     data: Vec<u8>,
@@ -38,11 +95,32 @@ impl Picture {
             data: buff.into_inner(),
         })
     }
+    // Keeps the bytes as they are, unlike `from_img`, which drops the EXIF data when re-encoding
+    pub fn from_jpeg(data: Vec<u8>) -> Result<Self, image::ImageError> {
+        image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(&data))?;
+        Ok(Self { data })
+    }
     pub fn try_into_img(self) -> Result<DynamicImage, image::ImageError> {
-        image::load_from_memory_with_format(&self.data, ImageFormat::Avif)
+        image::load_from_memory_with_format(&self.data, ImageFormat::Jpeg)
+    }
+    pub fn metadata(&self) -> PictureMetadata {
+        image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(&self.data))
+            .and_then(|mut decoder| decoder.exif_metadata())
+            .ok()
+            .flatten()
+            .map(|chunk| exif::parse(&chunk))
+            .unwrap_or_default()
     }
 }
 
+// Where and when a picture was taken according to its EXIF data, so referees can check it
+// against the zone and time of the completion. The time is the camera's local time.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PictureMetadata {
+    pub taken_at: Option<chrono::NaiveDateTime>,
+    pub location: Option<(f64, f64)>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct GameSession {
     pub name: String,
@@ -50,7 +128,34 @@ pub struct GameSession {
     pub id: u64,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionDetails {
+    pub id: u64,
+    pub name: String,
+    pub mode: Mode,
+    pub game_running: bool,
+    pub start_time: Option<chrono::DateTime<chrono::Local>>,
+    pub team_count: usize,
+    pub player_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Timer {
+    pub id: u64,
+    pub session: u64,
+    pub end_time: chrono::DateTime<chrono::Local>,
+    pub description: String,
+}
+
+// A portable dump of everything the engine stores, except pictures. `data` is ron encoded and
+// its layout is only known to the engine, which refuses snapshots of any other `version`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub version: u32,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
 pub enum ChallengeType {
     Kaff,
     Ortsspezifisch,
@@ -59,12 +164,30 @@ pub enum ChallengeType {
     Zoneable,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+// What a connection may do. Every connection starts out as a referee, spectators only get
+// broadcasts and read-only queries. A spectator connection can't become a referee again.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ConnectionRole {
+    #[default]
+    Referee,
+    Spectator,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TeamRole {
     Runner,
     Catcher,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GenerationPeriod {
+    Specific,
+    Normal,
+    Perimeter,
+    ZKaff,
+    EndGame,
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 pub enum RandomPlaceType {
     Zone,
@@ -85,6 +208,8 @@ pub enum ChallengeActionEntry {
     UncompletableMinutes(Option<u64>), // None -> uses repetitions (%r)
 
     //das mit de trap isch mal es konzept, ich säg mal bitte nonig :)
+    // The catcher message is broadcast as soon as a team is dealt the trap, not when they
+    // complete it, so the catchers know the bait is out.
     Trap {
         stuck_minutes: Option<u64>, // None -> uses repetitions (%r)
         catcher_message: Option<String>,
@@ -123,6 +248,9 @@ pub struct RawChallenge {
     pub departures: u8,
     pub dead_end: bool,
     pub no_disembark: bool,
+    pub requires_photo: bool,
+    pub requires_completed: Vec<u64>, // ids of challenges a team has to complete first
+    pub exclusive_group: Option<u64>, // a team is never offered two challenges of the same group
     pub fixed: bool,
     pub in_perimeter_override: Option<bool>,
     pub translated_titles: std::collections::HashMap<String, String>,
@@ -130,6 +258,10 @@ pub struct RawChallenge {
     pub action: Option<ChallengeActionEntry>,
     pub last_edit: chrono::DateTime<chrono::Local>,
     pub id: Option<u64>,
+    // references to deleted sets and zones, only filled in by the engine so editors can show
+    // them. They are dropped when the challenge is sent back.
+    pub dangling_sets: Vec<u64>,
+    pub dangling_zones: Vec<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -142,6 +274,8 @@ pub struct Zone {
     pub s_bahn_zone: bool,
     pub minutes_to: std::collections::HashMap<u64, u64>,
     pub id: u64,
+    pub centre: Option<(f64, f64)>, // latitude, longitude
+    pub boundary: Vec<(f64, f64)>,  // latitude, longitude, empty if unknown
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -157,6 +291,7 @@ pub struct Team {
     pub completed_challenges: Vec<CompletedChallenge>,
     // pub thumb_name: String,
     pub location: Option<(f64, f64)>,
+    pub current_zone: Option<u64>, // zone number, not the id
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -164,6 +299,8 @@ pub struct Player {
     pub name: String,
     pub id: u64,
     pub session: Option<u64>,
+    #[serde(default)]
+    pub ready: bool,
     // pub thumb_name: String,
 }
 
@@ -172,9 +309,325 @@ pub struct Challenge {
     pub title: String,
     pub description: String,
     pub points: u64,
+    pub completable: bool,
+    pub completable_at: Option<chrono::DateTime<chrono::Local>>, // only for locked challenges
+    #[serde(default)]
+    pub breakdown: Option<PointBreakdown>,
     // pub attached_images: Vec<String>,
 }
 
+// How the points of a generated challenge came together. The parts add up to the points of the
+// challenge, `clamped` being whatever the configured minimum and maximum added or took away.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PointBreakdown {
+    pub additional: i64,
+    pub kaffness: i64,
+    pub grade: i64,
+    pub walking: i64,
+    pub stationary: i64,
+    pub repetitions: i64,
+    pub dead_end: i64,
+    #[serde(default)]
+    pub no_disembark: i64,
+    pub zone_kaffness: i64,
+    pub travel: i64,
+    pub noise: i64,
+    pub clamped: i64,
+}
+
+impl PointBreakdown {
+    pub fn total(&self) -> i64 {
+        self.additional
+            + self.kaffness
+            + self.grade
+            + self.walking
+            + self.stationary
+            + self.repetitions
+            + self.dead_end
+            + self.no_disembark
+            + self.zone_kaffness
+            + self.travel
+            + self.noise
+            + self.clamped
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TeamScore {
+    pub team: usize,
+    pub name: String,
+    pub points: u64,
+    pub bounty: u64,
+    pub role: TeamRole,
+}
+
+// The scores of all teams at a moment during a game, e.g. for a halftime leaderboard
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScoreSnapshot {
+    pub time: chrono::DateTime<chrono::Local>,
+    pub teams: Vec<TeamScore>,
+}
+
+// A mutating command that was applied successfully, as recorded in the action log
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoggedAction {
+    pub id: u64,
+    pub session: Option<u64>,
+    pub connection: Option<u64>, // counted up per client connection since the engine started
+    pub time: chrono::DateTime<chrono::Local>,
+    pub action: commands::EngineAction,
+}
+
+// A broadcast as kept by the engine for clients that connect late. The sequence number counts
+// up from 1 every time the engine starts.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SequencedBroadcast {
+    pub sequence: u64,
+    pub action: commands::BroadcastAction,
+}
+
+// How long the engine took to process one kind of command since it started
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommandMetrics {
+    pub action: String,
+    pub count: u64,
+    pub total_micros: u64,
+    pub max_micros: u64,
+    pub histogram: Vec<(u64, u64)>, // (upper bound in µs, count), the last bound is u64::MAX
+}
+
+// A reference in the db that doesn't resolve, found by `CheckIntegrity`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum IntegrityProblem {
+    PlayerInMissingSession {
+        player: u64,
+        session: u64,
+    },
+    MissingTeamPlayer {
+        session: u64,
+        team: usize,
+        player: u64,
+    },
+    TeamPlayerInOtherSession {
+        session: u64,
+        team: usize,
+        player: u64,
+    },
+    MissingTeamZone {
+        session: u64,
+        team: usize,
+        zone: u64,
+    },
+    MissingPhoto {
+        session: u64,
+        team: usize,
+        period: usize,
+        picture: u64,
+    },
+    MissingChallengeSet {
+        challenge: u64,
+        set: u64,
+    },
+    MissingChallengeZone {
+        challenge: u64,
+        zone: u64,
+    },
+}
+
+// The results of a game as it ended, the standings are sorted by points, best first
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GameSummary {
+    pub session: u64,
+    pub winner: Option<usize>,
+    pub standings: Vec<TeamScore>,
+    pub catches: usize,
+    pub completed_challenges: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct MinimalLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub time: chrono::NaiveTime,
+}
+
+// Which players of a team have checked in with `SetPlayerReady` before the game
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TeamReadiness {
+    pub team: usize,
+    pub ready: Vec<u64>,
+    pub unready: Vec<u64>,
+}
+
+// A team's catches over all its periods, for stats screens
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TeamCatchStats {
+    pub team: usize,
+    pub catches: u64,
+    pub times_caught: u64,
+    pub bounty_collected: u64,
+    pub bounty_lost: u64,
+    pub catch_bonuses: u64, // flat and streak bonuses, on top of the bounty collected
+}
+
+// How long teams took between two zones in past games, next to the configured travel time. Zone
+// ids are the ones `minutes_to` uses.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TravelTimeSuggestion {
+    pub from_zone: u64,
+    pub to_zone: u64,
+    pub configured_minutes: Option<u64>,
+    pub observed_minutes: u64, // median
+    pub samples: u64,
+}
+
+// The challenges one player of a team was credited with, `player` is `None` for completions
+// nobody was credited with
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerContribution {
+    pub player: Option<u64>,
+    pub challenges: u64,
+    pub points: u64,
+}
+
+// Something that happened in a game, for tickers and recaps. The location is where the acting
+// team was last seen before the event.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Event {
+    pub time: chrono::NaiveTime,
+    pub location: Option<(f64, f64)>,
+    pub kind: EventKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum EventKind {
+    Catch {
+        catcher: usize,
+        caught: usize,
+        bounty: u64,
+    },
+    Complete {
+        team: usize,
+        title: String,
+        points: u64,
+    },
+}
+
+// One row of `Event::to_csv`, the fields that don't apply to the kind of event are left empty
+#[derive(Serialize)]
+struct EventRecord<'a> {
+    time: chrono::NaiveTime,
+    kind: &'static str,
+    team: usize,
+    other_team: Option<usize>, // the caught team for catches
+    title: Option<&'a str>,
+    points: Option<u64>,
+    bounty: Option<u64>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+impl Event {
+    // The columns of `Event::to_csv`, in the order of the fields of `EventRecord`
+    const CSV_HEADER: [&'static str; 9] = [
+        "time",
+        "kind",
+        "team",
+        "other_team",
+        "title",
+        "points",
+        "bounty",
+        "latitude",
+        "longitude",
+    ];
+
+    // A csv table of the events with a header row, e.g. for spreadsheets. The header is written
+    // even without events, serializing only writes it along with the first row.
+    pub fn to_csv(events: &[Event]) -> String {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        writer
+            .write_record(Self::CSV_HEADER)
+            .expect("writing to memory doesn't fail");
+        for event in events {
+            let (kind, team, other_team, title, points, bounty) = match &event.kind {
+                EventKind::Catch {
+                    catcher,
+                    caught,
+                    bounty,
+                } => ("catch", *catcher, Some(*caught), None, None, Some(*bounty)),
+                EventKind::Complete {
+                    team,
+                    title,
+                    points,
+                } => (
+                    "complete",
+                    *team,
+                    None,
+                    Some(title.as_str()),
+                    Some(*points),
+                    None,
+                ),
+            };
+            writer
+                .serialize(EventRecord {
+                    time: event.time,
+                    kind,
+                    team,
+                    other_team,
+                    title,
+                    points,
+                    bounty,
+                    latitude: event.location.map(|l| l.0),
+                    longitude: event.location.map(|l| l.1),
+                })
+                .expect("events only contain types csv can write");
+        }
+        let bytes = writer.into_inner().expect("writing to memory doesn't fail");
+        String::from_utf8(bytes).expect("csv of strings and numbers is valid utf8")
+    }
+}
+
+// What a catch or completion did to the acting team
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PeriodSummary {
+    pub team: usize,
+    pub points_gained: u64,
+    pub points: u64,
+    pub bounty: u64,
+    pub challenges: Vec<Challenge>,
+    #[serde(default)]
+    pub catch: Option<CatchBreakdown>, // only for catches
+    #[serde(default)]
+    pub milestones: Vec<(Milestone, u64)>, // reached with this period, with their bonus points
+}
+
+// One-time bonuses for the first team to get somewhere in a game. `Completions(n)` is the n-th
+// completion of any team.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Milestone {
+    FirstCatch,
+    FirstCompletion,
+    Completions(u64),
+}
+
+// Where the points of a catch came from. `streak` counts the catches since the catcher last
+// completed a challenge, this one included.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CatchBreakdown {
+    pub bounty: u64,
+    pub flat_bonus: u64,
+    pub streak: u64,
+    pub streak_bonus: u64,
+}
+
+impl CatchBreakdown {
+    pub fn total(&self) -> u64 {
+        self.bounty + self.flat_bonus + self.streak_bonus
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CompletedChallenge {
     pub title: String,
@@ -184,6 +637,66 @@ pub struct CompletedChallenge {
     // pub attached_images: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SimulatedPeriod {
+    pub period: GenerationPeriod,
+    pub points: Vec<u64>, // points of every challenge generated during the period
+}
+
+// The challenges generation would pick from for a team's next challenge. `tier` is 0 if they
+// passed all filters, otherwise the position of the fallback they needed in the config plus 1.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CandidateChallenges {
+    pub period: GenerationPeriod,
+    pub tier: usize,
+    pub challenges: Vec<u64>, // ids of the raw challenges, empty if generation would fail
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PointDistribution {
+    pub samples: u64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: u64,
+    pub max: u64,
+    pub percentiles: Vec<(u8, u64)>, // (percentile, points)
+}
+
+impl PointDistribution {
+    const PERCENTILES: [u8; 7] = [5, 10, 25, 50, 75, 90, 95];
+
+    // Returns `None` if there are no samples.
+    pub fn from_samples(samples: &[u64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let n = sorted.len() as f64;
+        let mean = sorted.iter().map(|&p| p as f64).sum::<f64>() / n;
+        let variance = sorted
+            .iter()
+            .map(|&p| (p as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        Some(Self {
+            samples: sorted.len() as u64,
+            mean,
+            std_dev: variance.sqrt(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            percentiles: Self::PERCENTILES
+                .iter()
+                .map(|&p| {
+                    // nearest-rank method
+                    let rank = ((p as f64 / 100.0) * n).ceil().max(1.0) as usize;
+                    (p, sorted[rank - 1])
+                })
+                .collect(),
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 pub enum Mode {
     Traditional,
@@ -652,3 +1165,120 @@ mod tests {
     }
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colour_from_hex() {
+        let colour = Colour {
+            r: 0x12,
+            g: 0xab,
+            b: 0xff,
+        };
+        assert_eq!(Colour::from_hex("#12abff").unwrap(), colour);
+        assert_eq!(Colour::from_hex("#12ABFF").unwrap(), colour);
+        assert_eq!(Colour::from_hex(" #12abff ").unwrap(), colour);
+        assert_eq!(colour.to_hex().parse::<Colour>().unwrap(), colour);
+    }
+
+    #[test]
+    fn colour_from_hex_without_hash() {
+        let colour = Colour { r: 1, g: 2, b: 3 };
+        assert_eq!(Colour::from_hex("010203").unwrap(), colour);
+    }
+
+    #[test]
+    fn colour_from_hex_wrong_length() {
+        assert!(Colour::from_hex("").is_err());
+        assert!(Colour::from_hex("#").is_err());
+        assert!(Colour::from_hex("#fff").is_err());
+        assert!(Colour::from_hex("#12abff0").is_err());
+        assert!(Colour::from_hex("#12abff00").is_err());
+    }
+
+    #[test]
+    fn colour_from_hex_non_hex_digits() {
+        assert!(Colour::from_hex("#12abfg").is_err());
+        assert!(Colour::from_hex("#+1abff").is_err());
+        assert!(Colour::from_hex("##12abf").is_err());
+        // six bytes, but not six characters
+        assert!(Colour::from_hex("#12äbf").is_err());
+    }
+
+    #[test]
+    fn empty_events_csv_has_header() {
+        assert_eq!(
+            Event::to_csv(&[]),
+            "time,kind,team,other_team,title,points,bounty,latitude,longitude\n"
+        );
+    }
+
+    #[test]
+    fn events_csv_round_trip() {
+        let time = chrono::NaiveTime::from_hms_opt(14, 5, 0).unwrap();
+        let events = [
+            Event {
+                time,
+                location: Some((47.37, 8.54)),
+                kind: EventKind::Catch {
+                    catcher: 1,
+                    caught: 2,
+                    bounty: 300,
+                },
+            },
+            Event {
+                time,
+                location: None,
+                kind: EventKind::Complete {
+                    team: 0,
+                    title: "Usflug, \"Uf\" Wernetshausen".into(),
+                    points: 450,
+                },
+            },
+        ];
+        type Row = (
+            chrono::NaiveTime,
+            String,
+            usize,
+            Option<usize>,
+            Option<String>,
+            Option<u64>,
+            Option<u64>,
+            Option<f64>,
+            Option<f64>,
+        );
+        let csv = Event::to_csv(&events);
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(reader.headers().unwrap(), Event::CSV_HEADER.as_slice());
+        let rows: Vec<Row> = reader.deserialize().map(|r| r.unwrap()).collect();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    time,
+                    "catch".into(),
+                    1,
+                    Some(2),
+                    None,
+                    None,
+                    Some(300),
+                    Some(47.37),
+                    Some(8.54)
+                ),
+                (
+                    time,
+                    "complete".into(),
+                    0,
+                    None,
+                    Some("Usflug, \"Uf\" Wernetshausen".into()),
+                    Some(450),
+                    None,
+                    None,
+                    None
+                ),
+            ]
+        );
+    }
+}
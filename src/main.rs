@@ -1,11 +1,9 @@
 mod engine;
 mod error;
 pub mod runtime;
-use error::Result;
 use runtime::manager;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     manager().await.unwrap();
-    Ok(())
 }
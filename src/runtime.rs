@@ -4,19 +4,28 @@ use crate::{
 };
 use async_broadcast as broadcast;
 use chrono;
-use std::{future::Future, marker::Unpin, path::Path};
+use std::{
+    collections::HashMap,
+    future::Future,
+    marker::Unpin,
+    path::{Path, PathBuf},
+};
 use tokio::{
     net, select,
     sync::{mpsc, oneshot, Mutex},
-    task::{JoinError, JoinHandle},
+    task::{AbortHandle, JoinError, JoinHandle},
     time::Duration,
 };
-use truinlag::commands::{self, *};
+use truinlag::{
+    commands::{self, *},
+    ConnectionRole, SequencedBroadcast,
+};
 
 #[derive(Debug)]
 pub enum EngineSignal {
     Command {
         command: commands::EngineCommandPackage,
+        connection: u64,
         channel: oneshot::Sender<IOSignal>,
     },
     BroadcastRequest(oneshot::Sender<broadcast::Receiver<IOSignal>>),
@@ -29,17 +38,25 @@ pub enum EngineSignal {
     RawLoopbackCommand(InternEngineCommand),
 }
 
+// Timers and alarms carry an id issued by the engine's `TimerTracker` so they can be cancelled.
 pub enum RuntimeRequest {
     CreateTimer {
+        id: u64,
         duration: Duration,
         payload: InternEngineCommand,
     },
     CreateAlarm {
+        id: u64,
         time: chrono::NaiveTime,
         payload: InternEngineCommand,
     },
+    CancelTimer(u64),
     // Similar to DelayedLoopback but not associated with a client.
     RawLoopback(JoinHandle<InternEngineCommand>),
+    // For broadcasts that don't stem from a client command, e.g. from timers.
+    Broadcast(BroadcastAction),
+    // Broadcasts the engine has numbered for `GetRecentBroadcasts`
+    SequencedBroadcast(SequencedBroadcast),
 }
 
 pub struct InternEngineResponsePackage {
@@ -55,9 +72,11 @@ pub enum InternEngineResponse {
 }
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum InternEngineCommand {
     Command(EngineCommand),
     AutoSave,
+    CheckStationary,
 }
 
 #[derive(Clone, Debug)]
@@ -115,6 +134,47 @@ impl From<RuntimeRequest> for InternEngineResponsePackage {
     }
 }
 
+// What every connection is held to, shared by all io tasks
+#[derive(Clone)]
+struct ConnectionPolicy {
+    secret: Option<String>,
+    commands_per_second: f64,
+    command_burst: u32,
+}
+
+// Refills continuously at `rate` tokens per second up to `capacity`. Every command takes a
+// token, so a single connection can't flood the engine, which handles commands one at a time.
+struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    rate: f64,
+    last_refill: tokio::time::Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            tokens: burst as f64,
+            capacity: burst as f64,
+            rate,
+            last_refill: tokio::time::Instant::now(),
+        }
+    }
+
+    fn take(&mut self) -> bool {
+        let now = tokio::time::Instant::now();
+        let elapsed = (now - self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
 pub async fn manager() -> Result<()> {
     type TaskList =
         std::rc::Rc<Mutex<Vec<Box<dyn Future<Output = Result<(), JoinError>> + Unpin>>>>;
@@ -129,12 +189,38 @@ pub async fn manager() -> Result<()> {
 
     let (oneshot_tx, oneshot_rx) = oneshot::channel::<()>();
 
-    println!("Manager: starting engine");
-    let engine_handle =
-        tokio::spawn(
-            async move { engine(mpsc_rx, broadcast_tx, oneshot_tx, mpsc_tx.clone()).await },
+    // separate storage, config and socket (see `truinlag::api::default_socket_path`) let several
+    // instances run on one host, e.g. staging next to production
+    let storage_path =
+        PathBuf::from(std::env::var("TRUINLAG_STORAGE").unwrap_or_else(|_| "truintabase".into()));
+    let config_path =
+        PathBuf::from(std::env::var("TRUINLAG_CONFIG").unwrap_or_else(|_| "config.ron".into()));
+
+    // the lock is created next to the storage, so it can be taken before the storage exists
+    let lock_path = storage_path.with_extension("lock");
+    println!("Manager: locking storage {}", storage_path.display());
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)
+        .expect(
+            "Manager: cannot lock storage (maybe another instance uses it, session improperly terminated, etc.)",
         );
 
+    println!("Manager: starting engine");
+    let engine_config_path = config_path.clone();
+    let engine_handle = tokio::spawn(async move {
+        engine(
+            mpsc_rx,
+            broadcast_tx,
+            oneshot_tx,
+            mpsc_tx.clone(),
+            &storage_path,
+            &engine_config_path,
+        )
+        .await
+    });
+
     println!("Manager: starting ctrlc");
     let ctrlc_tx = mpsc_tx_staller.clone();
     let ctrlc_handle = tokio::spawn(async move { ctrlc(ctrlc_tx).await });
@@ -149,10 +235,22 @@ pub async fn manager() -> Result<()> {
 
     let io_tasks_2 = io_tasks.clone();
 
-    let socket = "/tmp/truinsocket";
+    let socket = truinlag::api::default_socket_path();
+
+    // without a secret, every connection may use privileged actions
+    let secret = std::env::var("TRUINLAG_SECRET").ok();
+    if secret.is_none() {
+        println!("Manager: TRUINLAG_SECRET is not set, privileged actions are not protected");
+    }
+    let (commands_per_second, command_burst) = engine::command_rate_limit(&config_path);
+    let policy = ConnectionPolicy {
+        secret,
+        commands_per_second,
+        command_burst,
+    };
 
     println!("Manager: binding to socket {}", socket);
-    let listener = net::UnixListener::bind(socket).expect(
+    let listener = net::UnixListener::bind(&socket).expect(
         "Manager: cannot bind to socket (maybe other session running, session improperly terminated, etc.)",
     );
 
@@ -162,6 +260,8 @@ pub async fn manager() -> Result<()> {
             sender: mpsc::Sender<EngineSignal>,
             tasks: TaskList,
             addr: tokio::net::unix::SocketAddr,
+            connection: u64,
+            policy: ConnectionPolicy,
         ) -> Result<()> {
             let (broadcast_rx_tx, broadcast_rx_rx) = oneshot::channel();
             sender
@@ -170,7 +270,7 @@ pub async fn manager() -> Result<()> {
             let broadcast_rx = broadcast_rx_rx.await?;
 
             let io_handle = tokio::spawn(async move {
-                io(sender, broadcast_rx, stream, addr).await;
+                io(sender, broadcast_rx, stream, addr, connection, policy).await;
             });
 
             let mut tasks = tasks.lock().await;
@@ -182,20 +282,32 @@ pub async fn manager() -> Result<()> {
 
         println!("Manager: starting to accept new connections");
 
+        let mut connections: u64 = 0;
         loop {
             let stream = listener.accept().await;
 
             match stream {
                 Ok((stream, addr)) => {
-                    println!("Manager: accepted new connection: {:?}", addr);
-                    make_io_task(stream, mpsc_tx_staller.clone(), io_tasks_2.clone(), addr)
-                        .await
-                        .unwrap_or_else(|err| {
-                            eprintln!(
-                                "Manager: Encountered an error creating new i/o task, continuing: {}",
-                                err
-                            )
-                        });
+                    connections += 1;
+                    println!(
+                        "Manager: accepted new connection {}: {:?}",
+                        connections, addr
+                    );
+                    make_io_task(
+                        stream,
+                        mpsc_tx_staller.clone(),
+                        io_tasks_2.clone(),
+                        addr,
+                        connections,
+                        policy.clone(),
+                    )
+                    .await
+                    .unwrap_or_else(|err| {
+                        eprintln!(
+                            "Manager: Encountered an error creating new i/o task, continuing: {}",
+                            err
+                        )
+                    });
                 }
                 Err(err) => eprintln!(
                     "Manager: Error accepting new connection, continuing: {}",
@@ -268,6 +380,12 @@ pub async fn manager() -> Result<()> {
         .await
         .expect("couldn't remove socket file while shutting down");
 
+    println!("Manager: unlocking storage");
+
+    tokio::fs::remove_file(lock_path)
+        .await
+        .expect("couldn't remove storage lock while shutting down");
+
     println!("cya");
 
     Ok(())
@@ -278,30 +396,40 @@ async fn engine(
     broadcast_handle: broadcast::Sender<IOSignal>,
     oneshot_handle: oneshot::Sender<()>,
     mpsc_sender: mpsc::Sender<EngineSignal>,
+    storage_path: &Path,
+    config_path: &Path,
 ) -> Result<()> {
     const SEND_ERROR: &str =
         "Engine: The broadcast channel should never be closed because of `_broadcast_rx_staller`";
     async fn handle_runtime_requests(
         requests: Option<Vec<RuntimeRequest>>,
         mpsc_sender: &mpsc::Sender<EngineSignal>,
+        broadcast_handle: &broadcast::Sender<IOSignal>,
+        timers: &mut HashMap<u64, AbortHandle>,
     ) -> Vec<JoinHandle<()>> {
         let mut handles = Vec::new();
         if let Some(requests) = requests {
             for request in requests {
                 match request {
-                    RuntimeRequest::CreateTimer { duration, payload } => {
+                    RuntimeRequest::CreateTimer {
+                        id,
+                        duration,
+                        payload,
+                    } => {
                         let sender = mpsc_sender.clone();
-                        handles.push(tokio::spawn(async move {
+                        let handle = tokio::spawn(async move {
                             tokio::time::sleep(duration).await;
                             sender
                                 .send(EngineSignal::RawLoopbackCommand(payload))
                                 .await
                                 .unwrap()
-                        }));
+                        });
+                        timers.insert(id, handle.abort_handle());
+                        handles.push(handle);
                     }
-                    RuntimeRequest::CreateAlarm { time, payload } => {
+                    RuntimeRequest::CreateAlarm { id, time, payload } => {
                         let sender = mpsc_sender.clone();
-                        handles.push(tokio::spawn(async move {
+                        let handle = tokio::spawn(async move {
                             tokio::time::sleep(
                                 (time - chrono::offset::Local::now().time())
                                     .abs()
@@ -313,8 +441,14 @@ async fn engine(
                                 .send(EngineSignal::RawLoopbackCommand(payload))
                                 .await
                                 .unwrap()
-                        }));
+                        });
+                        timers.insert(id, handle.abort_handle());
+                        handles.push(handle);
                     }
+                    RuntimeRequest::CancelTimer(id) => match timers.remove(&id) {
+                        Some(timer) => timer.abort(),
+                        None => println!("Engine: couldn't cancel timer {}, not found", id),
+                    },
                     RuntimeRequest::RawLoopback(handle) => {
                         let sender = mpsc_sender.clone();
                         handles.push(tokio::spawn(async move {
@@ -324,6 +458,19 @@ async fn engine(
                                 .unwrap();
                         }));
                     }
+                    RuntimeRequest::Broadcast(action) => {
+                        let message = IOSignal::Command(ClientCommand::Broadcast(action));
+                        if let Err(err) = broadcast_handle.broadcast_direct(message).await {
+                            println!("{}: {}", SEND_ERROR, err);
+                        }
+                    }
+                    RuntimeRequest::SequencedBroadcast(broadcast) => {
+                        let message =
+                            IOSignal::Command(ClientCommand::SequencedBroadcast(broadcast));
+                        if let Err(err) = broadcast_handle.broadcast_direct(message).await {
+                            println!("{}: {}", SEND_ERROR, err);
+                        }
+                    }
                 }
             }
         }
@@ -335,8 +482,15 @@ async fn engine(
         channel: oneshot::Sender<IOSignal>,
         mpsc_sender: mpsc::Sender<EngineSignal>,
         id: u64,
+        timers: &mut HashMap<u64, AbortHandle>,
     ) -> Vec<JoinHandle<()>> {
-        let mut handles = handle_runtime_requests(response.runtime_requests, &mpsc_sender).await;
+        let mut handles = handle_runtime_requests(
+            response.runtime_requests,
+            &mpsc_sender,
+            broadcast_handle,
+            timers,
+        )
+        .await;
         match response.response {
             InternEngineResponse::DirectResponse(response) => {
                 if let Some(action) = response.broadcast_action {
@@ -371,10 +525,18 @@ async fn engine(
         }
         handles
     }
-    let mut engine = engine::Engine::init(Path::new("truintabase"));
-    let mut handles = handle_runtime_requests(engine.setup().runtime_requests, &mpsc_sender).await;
+    let mut engine = engine::Engine::init(storage_path, config_path);
+    let mut timers = HashMap::new();
+    let mut handles = handle_runtime_requests(
+        engine.setup().runtime_requests,
+        &mpsc_sender,
+        &broadcast_handle,
+        &mut timers,
+    )
+    .await;
     loop {
         handles.retain(|h| !h.is_finished());
+        timers.retain(|_, t| !t.is_finished());
         match mpsc_handle
             .recv()
             .await
@@ -382,15 +544,20 @@ async fn engine(
         {
             EngineSignal::Command {
                 command: package,
+                connection,
                 channel,
             } => {
                 handles.append(
                     &mut handle_intern_response(
-                        engine.vroom(InternEngineCommand::Command(package.command)),
+                        engine.vroom(
+                            InternEngineCommand::Command(package.command),
+                            Some(connection),
+                        ),
                         &broadcast_handle,
                         channel,
                         mpsc_sender.clone(),
                         package.id,
+                        &mut timers,
                     )
                     .await,
                 );
@@ -402,11 +569,12 @@ async fn engine(
             } => {
                 handles.append(
                     &mut handle_intern_response(
-                        engine.vroom(command),
+                        engine.vroom(command, None),
                         &broadcast_handle,
                         channel,
                         mpsc_sender.clone(),
                         id,
+                        &mut timers,
                     )
                     .await,
                 );
@@ -421,16 +589,29 @@ async fn engine(
             EngineSignal::Shutdown => {
                 println!("Engine: shutdown signal received, awaiting tasks");
                 for handle in handles {
-                    handle.await;
+                    let _ = handle.await;
                 }
                 println!("Engine: tasks awaited, breaking loop");
                 break;
             }
             EngineSignal::RawLoopbackCommand(command) => {
+                // there is no client to respond to, but broadcasts still have to go out, e.g.
+                // when a timer ends a game
+                let package = engine.vroom(command, None);
+                let mut requests = package.runtime_requests.unwrap_or_default();
+                if let InternEngineResponse::DirectResponse(EngineResponse {
+                    response_action: _,
+                    broadcast_action: Some(action),
+                }) = package.response
+                {
+                    requests.push(RuntimeRequest::Broadcast(action));
+                }
                 handles.append(
                     &mut handle_runtime_requests(
-                        engine.vroom(command).runtime_requests,
+                        Some(requests),
                         &mpsc_sender,
+                        &broadcast_handle,
+                        &mut timers,
                     )
                     .await,
                 );
@@ -464,10 +645,13 @@ async fn io(
     rx: broadcast::Receiver<IOSignal>,
     stream: net::UnixStream,
     addr: tokio::net::unix::SocketAddr,
+    connection: u64,
+    policy: ConnectionPolicy,
 ) {
     use bytes::Bytes;
     use futures::prelude::*;
     use futures::SinkExt;
+    use subtle::ConstantTimeEq;
     use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 
     async fn engine_parser(
@@ -518,14 +702,66 @@ async fn io(
         }
     }
 
+    struct ConnectionState {
+        role: ConnectionRole,
+        authenticated: bool,
+        bucket: TokenBucket,
+    }
+
+    // The state is kept per connection, so commands a connection isn't allowed to send are
+    // answered right here and never reach the engine.
+    fn screen_command(
+        state: &mut ConnectionState,
+        secret: Option<&str>,
+        action: &EngineAction,
+    ) -> Option<ResponseAction> {
+        use commands::Error::{TooRapid, Unauthorized};
+        if !state.bucket.take() {
+            return Some(ResponseAction::Error(TooRapid));
+        }
+        match action {
+            EngineAction::SetConnectionRole(new_role) => {
+                if state.role == ConnectionRole::Spectator && *new_role == ConnectionRole::Referee {
+                    Some(ResponseAction::Error(Unauthorized))
+                } else {
+                    state.role = *new_role;
+                    Some(ResponseAction::Success)
+                }
+            }
+            EngineAction::Authenticate { secret: attempt } => match secret {
+                Some(secret) if !bool::from(secret.as_bytes().ct_eq(attempt.as_bytes())) => {
+                    Some(ResponseAction::Error(Unauthorized))
+                }
+                _ => {
+                    state.authenticated = true;
+                    Some(ResponseAction::Success)
+                }
+            },
+            action if state.role == ConnectionRole::Spectator && !action.spectator_allowed() => {
+                Some(ResponseAction::Error(Unauthorized))
+            }
+            action if secret.is_some() && !state.authenticated && action.is_privileged() => {
+                Some(ResponseAction::Error(Unauthorized))
+            }
+            _ => None,
+        }
+    }
+
     async fn client_parser(
         tx: mpsc::Sender<EngineSignal>,
         recv_tx: mpsc::Sender<oneshot::Receiver<IOSignal>>,
         stream: net::unix::OwnedReadHalf,
         addr: &net::unix::SocketAddr,
+        connection: u64,
+        policy: ConnectionPolicy,
     ) -> Result<()> {
         let mut transport = FramedRead::new(stream, LengthDelimitedCodec::new());
         let mut count: u64 = 0;
+        let mut state = ConnectionState {
+            role: ConnectionRole::default(),
+            authenticated: false,
+            bucket: TokenBucket::new(policy.commands_per_second, policy.command_burst),
+        };
 
         while let Some(message) = transport.next().await {
             //println!("IO {:?}: ({}) received message from client", addr, count);
@@ -533,11 +769,28 @@ async fn io(
                 Ok(val) => {
                     let (oneshot_send, oneshot_recv) = oneshot::channel();
                     let command: commands::EngineCommandPackage = bincode::deserialize(&val)?;
-                    tx.send(EngineSignal::Command {
-                        command,
-                        channel: oneshot_send,
-                    })
-                    .await?;
+                    match screen_command(
+                        &mut state,
+                        policy.secret.as_deref(),
+                        &command.command.action,
+                    ) {
+                        Some(action) => {
+                            let _ = oneshot_send.send(IOSignal::Command(ClientCommand::Response(
+                                ResponsePackage {
+                                    action,
+                                    id: command.id,
+                                },
+                            )));
+                        }
+                        None => {
+                            tx.send(EngineSignal::Command {
+                                command,
+                                connection,
+                                channel: oneshot_send,
+                            })
+                            .await?
+                        }
+                    }
                     //println!("IO {:?}: ({}) forwarded message", addr, count);
                     recv_tx.send(oneshot_recv).await?;
                     //println!("IO {:?}: ({}) sent oneshot_recv", addr, count);
@@ -555,6 +808,8 @@ async fn io(
         engine_rx: broadcast::Receiver<IOSignal>,
         stream: net::UnixStream,
         addr: &net::unix::SocketAddr,
+        connection: u64,
+        policy: ConnectionPolicy,
     ) -> Result<()> {
         let (read_stream, write_stream) = stream.into_split();
 
@@ -563,7 +818,7 @@ async fn io(
         let broadcast_relay_tx = client_tx.clone();
 
         select! {
-            res = client_parser(engine_tx, recv_tx, read_stream, addr) => res?,
+            res = client_parser(engine_tx, recv_tx, read_stream, addr, connection, policy) => res?,
             res = engine_parser(client_rx, write_stream, addr) => res?,
             res = response_fwd(recv_rx, client_tx, addr) => res?,
             res = broadcast_fwd(engine_rx, broadcast_relay_tx) => res?
@@ -572,7 +827,7 @@ async fn io(
         Ok(())
     }
 
-    match wrapper(tx, rx, stream, &addr).await {
+    match wrapper(tx, rx, stream, &addr, connection, policy).await {
         Ok(_) => println!("IO {:?}: terminated without error", addr),
         Err(err) => eprintln!("IO {:?}: {}", addr, err),
     }